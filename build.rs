@@ -1,8 +1,19 @@
 use std::env;
+use std::fs;
 use std::io;
+use std::path::PathBuf;
 use winres::WindowsResource;
 
+#[allow(dead_code)]
+#[path = "src/engine/compile.rs"]
+mod compile;
+
+const SCHEMAS: [&str; 2] = ["sitelen", "emoji"];
+
 fn main() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=res");
+    compile_schemas()?;
     if env::var_os("CARGO_CFG_WINDOWS").is_none() {
         return Ok(());
     }
@@ -10,4 +21,19 @@ fn main() -> io::Result<()> {
         .set_icon_with_id("res/tray_lite.ico", "0")
         .set_icon_with_id("res/tray_dark.ico", "1")
         .compile()
-}
\ No newline at end of file
+}
+
+/// Compile the schemas in res/schema, which are included by global.rs.
+fn compile_schemas() -> io::Result<()> {
+    println!("cargo:rerun-if-changed=src/engine/compile.rs");
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    for name in SCHEMAS {
+        let path = format!("res/schema/{name}.schema");
+        let text = fs::read_to_string(&path)?;
+        match compile::compile(&text) {
+            Ok(bytes) => fs::write(out_dir.join(format!("{name}.bin")), bytes)?,
+            Err(problems) => panic!("{path}:\n{}", problems.join("\n")),
+        }
+    }
+    Ok(())
+}
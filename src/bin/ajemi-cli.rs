@@ -0,0 +1,308 @@
+use std::{env, fs, io::{self, Read}, path::PathBuf, process::ExitCode};
+use ajemi::{compile_schema, conf::{self, Severity}, generate_schemas, record, stats, user_schema_dir, ucsur::{self, Form, Joiners, LongGlyphs, Spaces}};
+
+//----------------------------------------------------------------------------
+//
+//  Command line tools for things that don't need a text service.
+//
+//----------------------------------------------------------------------------
+
+const USAGE: &str = "\
+Usage: ajemi-cli <command>
+
+Commands:
+    validate [path]    Check conf.toml for mistakes. Defaults to the one in %APPDATA%\\Ajemi.
+    replay [path]      Replay recorded compositions and report the ones that output differently.
+                       Defaults to recording.toml in %APPDATA%\\Ajemi.
+    stats [csv|json] [path]
+                       Summarize the typing statistics, or export them as CSV or JSON.
+                       Defaults to stats.toml in %APPDATA%\\Ajemi.
+    compile <schema> [output]
+                       Compile a schema for the IME to load. Defaults to the same name with the
+                       extension .bin in %APPDATA%\\Ajemi\\schemas, where the IME looks for them.
+                       The name goes in [schema] order and [spacing] of conf.toml.
+    generate <words> [dir]
+                       Generate sitelen.schema and emoji.schema from a table of words like
+                       res/schema/words.txt. Defaults to the directory of the table.
+    check [path]       Check sitelen pona text in UCSUR for long glyphs and cartouches that
+                       don't pair up, stray joiners and mixed spaces. Reads the standard input
+                       if no path is given.
+    normalize [options] [path]
+                       Print sitelen pona text in UCSUR in a canonical form. Options:
+                           --long-glyph keep|strip|derive|derive-pi
+                           --joiner keep|stacking|scaling
+                           --space keep|ideographic|ascii";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["validate"] => match conf::path() {
+            Ok(path) => validate(path),
+            Err(err) => {
+                eprintln!("Failed to locate conf.toml. {err}");
+                ExitCode::FAILURE
+            }
+        }
+        ["validate", path] => validate(PathBuf::from(path)),
+        ["replay"] => match conf::dir() {
+            Ok(dir) => replay(dir.join("recording.toml")),
+            Err(err) => {
+                eprintln!("Failed to locate recording.toml. {err}");
+                ExitCode::FAILURE
+            }
+        }
+        ["replay", path] => replay(PathBuf::from(path)),
+        ["stats", ref rest @ ..] if rest.len() <= 2 => {
+            let (format, path) = match rest {
+                [format @ ("csv" | "json"), rest @ ..] => (Some(*format), rest.first()),
+                rest => (None, rest.first()),
+            };
+            match path.map(PathBuf::from).map_or_else(stats::path, Ok) {
+                Ok(path) => report(path, format),
+                Err(err) => {
+                    eprintln!("Failed to locate stats.toml. {err}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        ["compile", schema] => {
+            let schema = PathBuf::from(schema);
+            let name = schema.with_extension("bin");
+            match user_schema_dir() {
+                Ok(dir) => compile(schema, dir.join(name.file_name().unwrap_or_default())),
+                Err(err) => {
+                    eprintln!("Failed to locate the schemas. {err}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        ["compile", schema, output] => compile(PathBuf::from(schema), PathBuf::from(output)),
+        ["generate", words] => {
+            let words = PathBuf::from(words);
+            let dir = words.parent().map(PathBuf::from).unwrap_or_default();
+            generate(words, dir)
+        }
+        ["generate", words, dir] => generate(PathBuf::from(words), PathBuf::from(dir)),
+        ["check"] => check(None),
+        ["check", path] => check(Some(PathBuf::from(path))),
+        ["normalize", ref rest @ ..] => match parse_form(rest) {
+            Some((form, path)) => normalize(path.map(PathBuf::from), &form),
+            None => {
+                eprintln!("{USAGE}");
+                ExitCode::FAILURE
+            }
+        }
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn validate(path: PathBuf) -> ExitCode {
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read {}. {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let problems = match conf::validate(&text) {
+        Ok(problems) => problems,
+        Err(err) => {
+            eprintln!("{}: {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+    if problems.is_empty() {
+        println!("No problems found in {}.", path.display());
+    }
+    if problems.iter().any(|problem| problem.severity == Severity::Error) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn replay(path: PathBuf) -> ExitCode {
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read {}. {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let replays = match record::replay(&text) {
+        Ok(replays) => replays,
+        Err(err) => {
+            eprintln!("{}: {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut mismatches = 0;
+    for (index, replay) in replays.iter().enumerate() {
+        if replay.matches() {
+            continue;
+        }
+        mismatches += 1;
+        let recording = &replay.recording;
+        let events: Vec<String> = recording.events.iter().map(|event| event.to_string()).collect();
+        println!("composition[{index}] at {} in {}", recording.time, recording.schema);
+        println!("    events:   {}", events.join(" "));
+        println!("    expected: {:?}", recording.output);
+        println!("    actual:   {:?}", replay.output);
+    }
+    println!("{} of {} compositions replayed differently.", mismatches, replays.len());
+    if mismatches == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+fn report(path: PathBuf, format: Option<&str>) -> ExitCode {
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read {}. {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let stats = match stats::parse(&text) {
+        Ok(stats) => stats,
+        Err(err) => {
+            eprintln!("{}: {err}", path.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    match format {
+        Some("csv") => print!("{}", stats.to_csv()),
+        Some(_) => println!("{}", stats.to_json()),
+        None => print!("{}", stats.summary(20)),
+    }
+    ExitCode::SUCCESS
+}
+
+fn compile(schema: PathBuf, output: PathBuf) -> ExitCode {
+    let text = match fs::read_to_string(&schema) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read {}. {err}", schema.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let bytes = match compile_schema(&text) {
+        Ok(bytes) => bytes,
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("{}: {problem}", schema.display());
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(err) = output.parent().map_or(Ok(()), fs::create_dir_all).and_then(|_| fs::write(&output, &bytes)) {
+        eprintln!("Failed to write {}. {err}", output.display());
+        return ExitCode::FAILURE;
+    }
+    println!("Compiled {} into {} ({} bytes).", schema.display(), output.display(), bytes.len());
+    ExitCode::SUCCESS
+}
+
+fn generate(words: PathBuf, dir: PathBuf) -> ExitCode {
+    let text = match fs::read_to_string(&words) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Failed to read {}. {err}", words.display());
+            return ExitCode::FAILURE;
+        }
+    };
+    let schemas = match generate_schemas(&text) {
+        Ok(schemas) => schemas,
+        Err(problems) => {
+            for problem in problems {
+                eprintln!("{}: {problem}", words.display());
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    for (name, schema) in schemas {
+        let output = dir.join(format!("{name}.schema"));
+        if let Err(err) = fs::write(&output, schema) {
+            eprintln!("Failed to write {}. {err}", output.display());
+            return ExitCode::FAILURE;
+        }
+        println!("Generated {}.", output.display());
+    }
+    ExitCode::SUCCESS
+}
+
+/// Read the file, or the standard input without a path.
+fn read_text(path: Option<&PathBuf>) -> Option<String> {
+    let mut text = String::new();
+    let result = match path {
+        Some(path) => fs::read_to_string(path).map(|it| text = it),
+        None => io::stdin().read_to_string(&mut text).map(|_| ()),
+    };
+    match result {
+        Ok(()) => Some(text),
+        Err(err) => {
+            let name = path.map_or("the standard input".to_string(), |path| path.display().to_string());
+            eprintln!("Failed to read {name}. {err}");
+            None
+        }
+    }
+}
+
+fn check(path: Option<PathBuf>) -> ExitCode {
+    let Some(text) = read_text(path.as_ref()) else {
+        return ExitCode::FAILURE;
+    };
+    let issues = ucsur::check(&text);
+    let name = path.map_or("<stdin>".to_string(), |path| path.display().to_string());
+    for issue in &issues {
+        println!("{name}: {issue}");
+    }
+    if issues.is_empty() {
+        println!("No problems found in {name}.");
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// The form and the path of `normalize [options] [path]`.
+fn parse_form<'a>(mut args: &[&'a str]) -> Option<(Form, Option<&'a str>)> {
+    let mut form = Form::default();
+    while let [option, value, rest @ ..] = args {
+        match (*option, *value) {
+            ("--long-glyph", "keep") => form.long_glyphs = LongGlyphs::Keep,
+            ("--long-glyph", "strip") => form.long_glyphs = LongGlyphs::Strip,
+            ("--long-glyph", "derive") => form.long_glyphs = LongGlyphs::Derive { long_pi: false },
+            ("--long-glyph", "derive-pi") => form.long_glyphs = LongGlyphs::Derive { long_pi: true },
+            ("--joiner", "keep") => form.joiners = Joiners::Keep,
+            ("--joiner", "stacking") => form.joiners = Joiners::Stacking,
+            ("--joiner", "scaling") => form.joiners = Joiners::Scaling,
+            ("--space", "keep") => form.spaces = Spaces::Keep,
+            ("--space", "ideographic") => form.spaces = Spaces::Ideographic,
+            ("--space", "ascii") => form.spaces = Spaces::Ascii,
+            _ => break,
+        }
+        args = rest;
+    }
+    match args {
+        [] => Some((form, None)),
+        [path] if !path.starts_with("--") => Some((form, Some(path))),
+        _ => None,
+    }
+}
+
+fn normalize(path: Option<PathBuf>, form: &Form) -> ExitCode {
+    let Some(text) = read_text(path.as_ref()) else {
+        return ExitCode::FAILURE;
+    };
+    print!("{}", ucsur::normalize(&text, form));
+    ExitCode::SUCCESS
+}
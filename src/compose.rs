@@ -0,0 +1,691 @@
+use std::{fmt::{self, Display}, str::FromStr};
+use crate::{conf::Config, engine::{Engine, Stroke, SuggestState, Suggestion}, keys::Action, pairs::{Closing, Pairs}, CANDI_NUM, PREEDIT_DELIMITER};
+use Input::*;
+
+//----------------------------------------------------------------------------
+//
+//  Composition without TSF. Events go in and what the text service should
+//  do about them comes out, so that recorded compositions can be replayed
+//  without a text service. See tsf/composition.rs for the TSF side.
+//
+//----------------------------------------------------------------------------
+
+/// Inputs that are easier to understand and handle.
+/// See tsf/key_event_sink.rs for how keys are translated to them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    Letter(char), Number(usize), Punct(char),
+    Space, Backspace, Delete, Enter, Tab, Escape,
+    Left, Up, Right, Down, Home, End,
+    Unknown(usize)
+}
+
+/// Things that happen to a composition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Input(Input),
+    Action(Action),
+    /// A chorded stroke, by its keys.
+    Stroke(String),
+    /// A candidate on the page is clicked.
+    Click(usize),
+    /// Interrupted, like when the window loses the focus.
+    Abort,
+}
+
+/// What the text service should do after an event.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Effect {
+    None,
+    /// Update the preedit and the candidate list.
+    Respelled,
+    /// Update the caret in the preedit.
+    CaretMoved,
+    /// Update the candidate list.
+    Paged,
+    /// End the composition, leaving the text.
+    Finish(String),
+    /// End the composition, leaving the text if possible.
+    Abort(String),
+    /// Toggle long glyphs with `toggle_long_glyph` and then `refresh`. The config is
+    /// left to the caller, so that it's only read while composing.
+    ToggleLongGlyph,
+}
+
+/// A suggestion selected in the middle of a composition, with the spelling it was selected from.
+pub struct Segment {
+    pub output: String,
+    pub spelling: String,
+}
+
+/// What it took to compose, for the typing statistics. See stats.rs.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Tally {
+    /// Keys pressed, the vowels of a chorded stroke each counted.
+    pub keystrokes: usize,
+    pub selections: Vec<Selection>,
+    /// If the raw spelling is left in the output, by releasing or canceling.
+    pub released: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    pub output: String,
+    /// The index of the suggestion, 0 for the 1st one.
+    pub index: usize,
+    /// How many words the suggestion makes, more than 1 for sentences.
+    pub words: usize,
+}
+
+/// The engine and the config a composition is handled with.
+#[derive(Clone, Copy)]
+struct Env<'a> {
+    engine: &'a Engine,
+    conf: &'a Config,
+}
+
+#[derive(Default)]
+pub struct Composer {
+    pub spelling: String,
+    /// In bytes.
+    pub caret: usize,
+    pub selected: Vec<Segment>,
+    pub suggestions: Vec<Suggestion>,
+    pub page: usize,
+    /// The suggestion that is committed, the first one on the page unless cycled to another.
+    pub highlighted: usize,
+    /// If the highlighted suggestion is shown in the preedit in place of the spelling.
+    pub cycled: bool,
+    pub tally: Tally,
+    /// If the composition directly follows a commit, which it's separated from. See [spacing].
+    pub follows_commit: bool,
+    /// The paired punctuators of the document, given back when the composition ends.
+    pub pairs: Pairs,
+    /// What the punctuator that ends the composition does besides being committed.
+    pub closing: Closing,
+    /// Kept so that each keystroke only looks up what it changes.
+    suggest_state: SuggestState,
+}
+
+/// 󱦜 and 󱦝, which are separated like words.
+pub fn is_sentence_punct(ch: char) -> bool {
+    matches!(ch, '\u{F199C}' | '\u{F199D}')
+}
+
+/// If the text ends with a word or a sentence punctuator, which what's committed next is
+/// separated from. Spaces, CJK punctuators and anything ASCII are not separated from.
+pub fn ends_with_word(text: &str) -> bool {
+    text.chars().next_back().is_some_and(|ch| is_sentence_punct(ch)
+        || !(ch.is_ascii() || ch.is_whitespace() || matches!(ch, '\u{200B}' | '\u{3000}'..='\u{303F}' | '\u{FF00}'..='\u{FF65}')))
+}
+
+/// Switch the schema, which takes effect whether composing or not.
+/// The quotes of the document are opened again for a new schema.
+/// Returns false if the action is not one of those.
+pub fn switch(action: Action, pairs: &mut Pairs, engine: &mut Engine) -> bool {
+    use Action::*;
+    match action {
+        NextSchema => engine.next_schema(),
+        PreviousSchema => engine.previous_schema(),
+        SelectSchema(index) => engine.select_schema(index),
+        _ => return false,
+    }
+    pairs.reset_quotes();
+    true
+}
+
+pub fn toggle_long_glyph(conf: &mut Config) {
+    conf.behavior.long_glyph = !conf.behavior.long_glyph;
+}
+
+impl Composer {
+    pub fn handle(&mut self, event: &Event, engine: &mut Engine, conf: &Config) -> Effect {
+        self.tally.keystrokes += match event {
+            Event::Stroke(keys) => keys.len(),
+            Event::Abort => 0,
+            _ => 1,
+        };
+        // a punctuator typed after anything else is no double tap
+        self.pairs.last = None;
+        if let Event::Action(action) = event {
+            if *action == Action::ToggleLongGlyph {
+                return Effect::ToggleLongGlyph;
+            }
+            if switch(*action, &mut self.pairs, engine) {
+                return self.respell(Env { engine, conf });
+            }
+        }
+        let env = Env { engine, conf };
+        match event {
+            Event::Input(input) => self.input(*input, env),
+            Event::Action(action) => self.act(*action, env),
+            Event::Stroke(keys) => self.push_stroke(&Stroke { keys: keys.clone(), time: 0 }, env),
+            Event::Click(index) => self.select_on_page(*index, env),
+            Event::Abort => self.abort(env),
+        }
+    }
+
+    /// Suggest again for the config changed, like after `Effect::ToggleLongGlyph`.
+    pub fn refresh(&mut self, engine: &Engine, conf: &Config) -> Effect {
+        self.respell(Env { engine, conf })
+    }
+
+    fn input(&mut self, input: Input, env: Env) -> Effect {
+        match input {
+            Letter(letter) => self.push(letter, env),
+            Number(0) => Effect::None,
+            Number(number) => self.select_on_page(number - 1, env),
+            Punct(punct) if env.engine.is_joiner(punct) => self.push(punct, env),
+            Punct(punct) => self.force_commit(punct, env),
+            Escape => self.cancel(env),
+            Backspace => self.pop(env),
+            Delete => self.delete(env),
+            Left => self.move_caret_left(),
+            Right => self.move_caret_right(),
+            Home => self.move_caret(0),
+            End => self.move_caret(self.spelling.len()),
+            // they do what they are bound to in conf.toml, or nothing at all.
+            Space | Enter | Tab => Effect::None,
+            // the candidate list is not navigable yet.
+            Up | Down => Effect::None,
+            Unknown(_) => Effect::None,
+        }
+    }
+
+    fn act(&mut self, action: Action, env: Env) -> Effect {
+        use Action::*;
+        match action {
+            Commit => self.commit(env),
+            Release => self.release(env),
+            ReleaseWithSpace => {
+                self.caret = self.spelling.len();
+                self.push(' ', env);
+                self.release(env)
+            }
+            DeleteSegment => self.delete_segment(env),
+            NextPage => self.next_page(),
+            PreviousPage => self.previous_page(),
+            CycleCandidate => self.cycle(),
+            ToggleIme => self.abort(env),
+            NextSchema | PreviousSchema | SelectSchema(_) | ToggleLongGlyph => unreachable!(),
+        }
+    }
+
+    /// The preedit, with the spelling grouped as the 1st suggestion suggests,
+    /// and the caret within it in UTF-16 units. If cycled, the highlighted suggestion
+    /// is shown instead, followed by what's left of the spelling.
+    pub fn preedit(&self) -> (String, usize) {
+        let mut preedit = self.selected_output();
+        // the caret within the preedit, in bytes
+        let mut caret = None;
+        if self.cycled {
+            let sugg = &self.suggestions[self.highlighted];
+            preedit.push_str(&sugg.output);
+            caret = Some(preedit.len());
+            let last = *sugg.groupping.last().unwrap();
+            if last != self.spelling.len() {
+                preedit.push_str(PREEDIT_DELIMITER);
+                preedit.push_str(&self.spelling[last..]);
+            }
+        } else if self.suggestions.is_empty() {
+            caret = Some(preedit.len() + self.caret);
+            preedit.push_str(&self.spelling);
+        } else {
+            let mut from = 0;
+            for to in &self.suggestions[0].groupping {
+                if caret.is_none() && self.caret <= *to {
+                    caret = Some(preedit.len() + self.caret - from);
+                }
+                preedit.push_str(&self.spelling[from..*to]);
+                preedit.push_str(PREEDIT_DELIMITER);
+                from = *to;
+            }
+            if caret.is_none() {
+                caret = Some(preedit.len() + self.caret - from);
+            }
+            if from != self.spelling.len() {
+                preedit.push_str(&self.spelling[from..])
+            } else {
+                preedit.pop();
+            }
+        }
+        let caret = caret.map_or(0, |caret| preedit[..caret].encode_utf16().count());
+        (preedit, caret)
+    }
+
+    /// The suggestions on the current page.
+    pub fn candidates(&self) -> &[Suggestion] {
+        let from = usize::min(self.page * CANDI_NUM, self.suggestions.len());
+        let to = usize::min(from + CANDI_NUM, self.suggestions.len());
+        &self.suggestions[from..to]
+    }
+
+    /// The index of the highlighted suggestion on the current page.
+    pub fn highlighted_on_page(&self) -> usize {
+        self.highlighted - self.page * CANDI_NUM
+    }
+
+    /// Insert a char at the caret.
+    fn push(&mut self, ch: char, env: Env) -> Effect {
+        self.spelling.insert(self.caret, ch);
+        self.caret += ch.len_utf8();
+        self.respell(env)
+    }
+
+    /// Push a chorded stroke. A stroke that resolves to a word is selected right away,
+    /// unless there's a spelling pending, in which case the keys are inserted at the caret.
+    fn push_stroke(&mut self, stroke: &Stroke, env: Env) -> Effect {
+        let resolved = env.engine.resolve_stroke(stroke, &env.conf.chord.overrides);
+        match resolved {
+            Some((spelling, output)) if self.spelling.is_empty() => {
+                self.tally.selections.push(Selection { output: output.clone(), index: 0, words: 1 });
+                self.selected.push(Segment { output, spelling });
+            }
+            _ => {
+                self.spelling.insert_str(self.caret, &stroke.keys);
+                self.caret += stroke.keys.len();
+            }
+        }
+        self.respell(env)
+    }
+
+    /// Remove the char before the caret.
+    /// At the start of the spelling, the last selected segment is reverted instead.
+    fn pop(&mut self, env: Env) -> Effect {
+        let Some(ch) = self.spelling[..self.caret].chars().next_back() else {
+            return self.unselect(env);
+        };
+        self.caret -= ch.len_utf8();
+        self.spelling.remove(self.caret);
+        if self.spelling.is_empty() && self.selected.is_empty() {
+            return self.abort(env);
+        }
+        self.respell(env)
+    }
+
+    /// Remove the char after the caret.
+    fn delete(&mut self, env: Env) -> Effect {
+        if self.caret >= self.spelling.len() {
+            return Effect::None;
+        }
+        self.spelling.remove(self.caret);
+        if self.spelling.is_empty() && self.selected.is_empty() {
+            return self.abort(env);
+        }
+        self.respell(env)
+    }
+
+    /// Remove the chars from the start of the segment before the caret to the caret.
+    /// Segments are defined by the groupping of the 1st suggestion.
+    fn delete_segment(&mut self, env: Env) -> Effect {
+        if self.caret == 0 {
+            return Effect::None;
+        }
+        let from = self.suggestions.first()
+            .and_then(|sugg| sugg.groupping.iter().cloned().take_while(|to| *to < self.caret).last())
+            .unwrap_or(0);
+        self.spelling.replace_range(from..self.caret, "");
+        self.caret = from;
+        if self.spelling.is_empty() && self.selected.is_empty() {
+            return self.abort(env);
+        }
+        self.respell(env)
+    }
+
+    fn move_caret_left(&mut self) -> Effect {
+        let Some(ch) = self.spelling[..self.caret].chars().next_back() else {
+            return Effect::None;
+        };
+        self.move_caret(self.caret - ch.len_utf8())
+    }
+
+    fn move_caret_right(&mut self) -> Effect {
+        let Some(ch) = self.spelling[self.caret..].chars().next() else {
+            return Effect::None;
+        };
+        self.move_caret(self.caret + ch.len_utf8())
+    }
+
+    /// The spelling is shown again if cycled.
+    fn move_caret(&mut self, caret: usize) -> Effect {
+        self.caret = caret;
+        if self.cycled {
+            self.cycled = false;
+            return Effect::Respelled;
+        }
+        Effect::CaretMoved
+    }
+
+    fn next_page(&mut self) -> Effect {
+        if (self.page + 1) * CANDI_NUM >= self.suggestions.len() {
+            return Effect::None;
+        }
+        self.page += 1;
+        self.highlight_page()
+    }
+
+    fn previous_page(&mut self) -> Effect {
+        if self.page == 0 {
+            return Effect::None;
+        }
+        self.page -= 1;
+        self.highlight_page()
+    }
+
+    /// Highlight the first suggestion on the page.
+    fn highlight_page(&mut self) -> Effect {
+        self.highlighted = self.page * CANDI_NUM;
+        match self.cycled {
+            true => Effect::Respelled,
+            false => Effect::Paged,
+        }
+    }
+
+    /// Highlight the next suggestion, turning the page if it's on the next one,
+    /// and show it in the preedit. The last one goes back to the first.
+    fn cycle(&mut self) -> Effect {
+        if self.suggestions.is_empty() {
+            return Effect::None;
+        }
+        self.highlighted = (self.highlighted + 1) % self.suggestions.len();
+        self.page = self.highlighted / CANDI_NUM;
+        self.cycled = true;
+        Effect::Respelled
+    }
+
+    /// Re-suggest after the spelling is edited.
+    fn respell(&mut self, env: Env) -> Effect {
+        self.suggestions = env.engine.suggest_with(&mut self.suggest_state, &self.spelling, env.conf);
+        self.page = 0;
+        self.highlighted = 0;
+        self.cycled = false;
+        Effect::Respelled
+    }
+
+    /// Commit the highlighted suggestion, keeping the unrecognizable trailing characters
+    fn commit(&mut self, env: Env) -> Effect {
+        if self.spelling.is_empty() {
+            Effect::Finish(self.lead(self.selected_output(), env))
+        } else if self.suggestions.is_empty() {
+            self.force_release(' ', env)
+        } else {
+            self.select(self.highlighted, env)
+        }
+    }
+
+    /// Commit the highlighted suggestion and release the unrecognizable trailing characters,
+    /// followed by the punctuator.
+    fn force_commit(&mut self, ch: char, env: Env) -> Effect {
+        if self.suggestions.is_empty() {
+            return self.force_release(ch, env);
+        }
+        let index = self.highlighted;
+        let sugg = &self.suggestions[index];
+        self.tally.selections.push(Selection { output: sugg.output.clone(), index, words: sugg.groupping.len() });
+        let mut text = self.selected_output();
+        text.push_str(&sugg.output);
+        let last = *sugg.groupping.last().unwrap();
+        if last != self.spelling.len() {
+            self.tally.released = true;
+            text.push_str(raw_separator(env));
+            text.push_str(&self.spelling[last..])
+        }
+        self.push_punct(&mut text, ch, env);
+        Effect::Finish(self.lead(text, env))
+    }
+
+    /// Select the desired suggestion on the current page by pressing numbers.
+    fn select_on_page(&mut self, index: usize, env: Env) -> Effect {
+        if index >= CANDI_NUM {
+            return Effect::None;
+        }
+        self.select(self.page * CANDI_NUM + index, env)
+    }
+
+    fn select(&mut self, index: usize, env: Env) -> Effect {
+        let Some(sugg) = self.suggestions.get(index) else {
+            return Effect::None;
+        };
+        self.tally.selections.push(Selection { output: sugg.output.clone(), index, words: sugg.groupping.len() });
+        let last = *sugg.groupping.last().unwrap();
+        if last == self.spelling.len() {
+            let mut text = self.selected_output();
+            text.push_str(&sugg.output);
+            Effect::Finish(self.lead(text, env))
+        } else {
+            self.selected.push(Segment {
+                output: sugg.output.clone(),
+                spelling: self.spelling[..last].to_string()
+            });
+            // TODO strip off the begining instead of re allocate
+            self.spelling = self.spelling[last..].to_string();
+            self.caret = self.caret.saturating_sub(last);
+            self.respell(env)
+        }
+    }
+
+    /// Put the last selected segment back to the spelling.
+    fn unselect(&mut self, env: Env) -> Effect {
+        let Some(segment) = self.selected.pop() else {
+            return Effect::None;
+        };
+        self.tally.selections.pop();
+        self.spelling.insert_str(0, &segment.spelling);
+        self.caret += segment.spelling.len();
+        self.respell(env)
+    }
+
+    // Release the raw ascii chars
+    fn release(&mut self, env: Env) -> Effect {
+        self.tally.released = true;
+        Effect::Finish(self.released(None, env))
+    }
+
+    // Cancel the selections and suggestions, leaving nothing but the raw spelling.
+    fn cancel(&mut self, env: Env) -> Effect {
+        self.tally.released = true;
+        let mut text = String::new();
+        for segment in &self.selected {
+            text.push_str(&segment.spelling);
+        }
+        text.push_str(&self.spelling);
+        Effect::Finish(self.lead(text, env))
+    }
+
+    fn force_release(&mut self, ch: char, env: Env) -> Effect {
+        self.tally.released = true;
+        Effect::Finish(self.released(Some(ch), env))
+    }
+
+    // Interupted. Abort everything.
+    fn abort(&mut self, env: Env) -> Effect {
+        Effect::Abort(self.released(None, env))
+    }
+
+    /// The selected output and the raw spelling, followed by the char.
+    fn released(&mut self, ch: Option<char>, env: Env) -> String {
+        let mut text = self.selected_output();
+        if !text.is_empty() && !self.spelling.is_empty() {
+            text.push_str(raw_separator(env));
+        }
+        text.push_str(&self.spelling);
+        if let Some(ch) = ch {
+            self.push_punct(&mut text, ch, env);
+        }
+        self.lead(text, env)
+    }
+
+    /// Push the punctuator as it's remapped, separated from the text if it's 󱦜 or 󱦝.
+    /// A closer that is ahead of the caret already is stepped over instead.
+    fn push_punct(&mut self, text: &mut String, ch: char, env: Env) {
+        let spacing = env.engine.spacing(env.conf);
+        let output = env.engine.remap_punct(ch, &mut self.pairs, true, text.chars().next_back(), spacing).output;
+        self.closing = self.pairs.close(&output, env.conf.behavior.auto_close);
+        match self.closing {
+            Closing::Over(_) => return,
+            _ if output.starts_with(is_sentence_punct) && !text.is_empty() => text.push_str(spacing.separator()),
+            _ => (),
+        }
+        text.push_str(&output);
+        if let Closing::Open(closer) = self.closing {
+            text.push(closer);
+        }
+    }
+
+    /// Separate the text from the commit before, if it follows one.
+    fn lead(&self, text: String, env: Env) -> String {
+        if !self.follows_commit || text.is_empty() {
+            return text;
+        }
+        env.engine.spacing(env.conf).separator().to_string() + &text
+    }
+
+    fn selected_output(&self) -> String {
+        let mut output = String::new();
+        for segment in &self.selected {
+            output.push_str(&segment.output);
+        }
+        output
+    }
+}
+
+/// Separates words from the raw spelling, which is never run into them.
+fn raw_separator(env: Env) -> &'static str {
+    match env.engine.spacing(env.conf).separator() {
+        "" => " ",
+        separator => separator,
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//  Events as text, like "letter:a", "action:commit" or "backspace",
+//  for recordings. See record.rs.
+//
+//----------------------------------------------------------------------------
+
+const KEY_NAMES: [(&str, Input); 13] = [
+    ("space", Space), ("backspace", Backspace), ("delete", Delete), ("enter", Enter), ("tab", Tab),
+    ("escape", Escape), ("left", Left), ("up", Up), ("right", Right), ("down", Down),
+    ("home", Home), ("end", End), ("unknown", Unknown(0)),
+];
+
+impl Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Input(Letter(letter)) => write!(f, "letter:{letter}"),
+            Event::Input(Number(number)) => write!(f, "number:{number}"),
+            Event::Input(Punct(punct)) => write!(f, "punct:{punct}"),
+            Event::Input(Unknown(key_code)) => write!(f, "unknown:{key_code:#04X}"),
+            Event::Input(input) => {
+                let name = KEY_NAMES.iter().find(|(_, it)| it == input).map(|(name, _)| *name).unwrap();
+                f.write_str(name)
+            }
+            Event::Action(action) => write!(f, "action:{action}"),
+            Event::Stroke(keys) => write!(f, "stroke:{keys}"),
+            // numbered from 1 like the candidates
+            Event::Click(index) => write!(f, "click:{}", index + 1),
+            Event::Abort => f.write_str("abort"),
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = ();
+    fn from_str(text: &str) -> Result<Event, ()> {
+        let single = |arg: &str| {
+            let mut chars = arg.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Ok(ch),
+                _ => Err(()),
+            }
+        };
+        let event = match text.split_once(':') {
+            Some(("letter", arg)) => Event::Input(Letter(single(arg)?)),
+            Some(("number", arg)) => Event::Input(Number(arg.parse().map_err(|_| ())?)),
+            Some(("punct", arg)) => Event::Input(Punct(single(arg)?)),
+            Some(("unknown", arg)) => {
+                let key_code = usize::from_str_radix(arg.trim_start_matches("0x"), 16).map_err(|_| ())?;
+                Event::Input(Unknown(key_code))
+            }
+            Some(("action", arg)) => Event::Action(arg.parse()?),
+            Some(("stroke", arg)) => Event::Stroke(arg.to_string()),
+            Some(("click", arg)) => match arg.parse::<usize>() {
+                Ok(number) if number >= 1 => Event::Click(number - 1),
+                _ => return Err(()),
+            }
+            _ if text == "abort" => Event::Abort,
+            _ => KEY_NAMES.iter()
+                .find(|(name, _)| *name == text)
+                .map(|(_, input)| Event::Input(*input))
+                .ok_or(())?,
+        };
+        Ok(event)
+    }
+}
+
+#[test]
+fn test() {
+    let events = [
+        Event::Input(Letter('a')), Event::Input(Punct(':')), Event::Input(Number(2)), Event::Input(Backspace),
+        Event::Input(Unknown(0x7B)), Event::Action(Action::SelectSchema(1)), Event::Stroke("aei".to_string()),
+        Event::Click(0), Event::Abort,
+    ];
+    for event in events {
+        assert_eq!(event.to_string().parse::<Event>(), Ok(event));
+    }
+    assert_eq!(Event::Click(0).to_string(), "click:1");
+    assert!("letter:ab".parse::<Event>().is_err());
+    assert!("click:0".parse::<Event>().is_err());
+
+    let mut engine = Engine::new(&["sitelen".to_string()]);
+    let conf = Config::default();
+    let mut composer = Composer::default();
+    let mut handle = |event: &str| composer.handle(&event.parse().unwrap(), &mut engine, &conf);
+    assert_eq!(handle("letter:a"), Effect::Respelled);
+    assert_eq!(handle("letter:o"), Effect::Respelled);
+    assert_eq!(handle("left"), Effect::CaretMoved);
+    assert_eq!(handle("end"), Effect::CaretMoved);
+    assert_eq!(handle("backspace"), Effect::Respelled);
+    assert_eq!(handle("backspace"), Effect::Abort(String::new()));
+
+    // e and n are spelled alike
+    let mut composer = Composer::default();
+    let mut handle = |event: &str| composer.handle(&event.parse().unwrap(), &mut engine, &conf);
+    handle("letter:e");
+    assert_eq!(handle("action:cycle_candidate"), Effect::Respelled);
+    assert_eq!(composer.preedit(), ("\u{F1986}".to_string(), 2));
+    assert_eq!(composer.handle(&"action:commit".parse().unwrap(), &mut engine, &conf), Effect::Finish("\u{F1986}".to_string()));
+    assert_eq!(composer.tally.selections[0].index, 1);
+
+    // long glyphs are toggled by the caller, which then suggests again
+    let mut long = Config::default();
+    let mut composer = Composer::default();
+    composer.handle(&"letter:a".parse().unwrap(), &mut engine, &long);
+    assert_eq!(composer.handle(&"action:toggle_long_glyph".parse().unwrap(), &mut engine, &long), Effect::ToggleLongGlyph);
+    assert!(!long.behavior.long_glyph);
+    toggle_long_glyph(&mut long);
+    assert_eq!(composer.refresh(&engine, &long), Effect::Respelled);
+
+    use crate::conf::Spacing;
+    let mut spaced = Config::default();
+    spaced.spacing.set("sitelen", Spacing::Ascii);
+    let mut composer = Composer::default();
+    composer.follows_commit = true;
+    composer.handle(&"letter:a".parse().unwrap(), &mut engine, &spaced);
+    assert_eq!(composer.handle(&"punct:.".parse().unwrap(), &mut engine, &spaced), Effect::Finish(" \u{F1900} \u{F199C}".to_string()));
+    let mut closing = Config::default();
+    closing.behavior.auto_close = true;
+    let mut composer = Composer::default();
+    let mut handle = |composer: &mut Composer, event: &str| composer.handle(&event.parse().unwrap(), &mut engine, &closing);
+    handle(&mut composer, "letter:a");
+    assert_eq!(handle(&mut composer, "punct:["), Effect::Finish("\u{F1900}\u{F1990}\u{F1991}".to_string()));
+    assert_eq!(composer.closing, Closing::Open('\u{F1991}'));
+    let mut composer = Composer { pairs: composer.pairs, ..Composer::default() };
+    handle(&mut composer, "letter:a");
+    assert_eq!(handle(&mut composer, "punct:]"), Effect::Finish("\u{F1900}".to_string()));
+    assert_eq!(composer.closing, Closing::Over('\u{F1991}'));
+    assert!(ends_with_word("\u{F1900}\u{F199C}"));
+    assert!(!ends_with_word("\u{F1900}「"));
+    assert!(!ends_with_word("\u{F1900}a"));
+}
@@ -1,75 +1,580 @@
-use std::{env, fs, os::windows::fs::MetadataExt, path::PathBuf};
+use std::{collections::HashMap, env, fmt::{self, Display}, fs, io, path::PathBuf, sync::OnceLock, time::SystemTime};
 use anyhow::Result;
+use log::{error, warn};
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use toml::{Table, Value};
-use crate::{extend::TableExt, ui::Color, DEFAULT_CONF, IME_NAME};
-// font
-pub static mut FONT: String = String::new(); 
-pub static mut FONT_SIZE: i32 = 0;
-// layout
-pub static mut VERTICAL: bool = false;
-// color scheme
-pub static mut CANDI_COLOR: Color = Color::white();
-pub static mut CANDI_HIGHLIGHTED_COLOR: Color = Color::white();
-pub static mut INDEX_COLOR: Color = Color::white();
-pub static mut CLIP_COLOR: Color = Color::white();
-pub static mut BKG_COLOR: Color = Color::white();
-pub static mut HIGHTLIGHT_COLOR: Color = Color::white();
-// behavior
-pub static mut LONG_PI: bool = false;
-pub static mut LONG_GLYPH: bool = false;
-pub static mut CJK_SPACE: bool = false;
-
-static mut LAST_MODIFIED: u64 = 0;
+use crate::{engine::{self, ChordOverrides, SCHEMA_NAMES, TIER_NAMES}, extend::{LoadValue, TableLoader}, keymap::Keymap, keys::KeyBindings, ui::{layout::Layout, theme::ColorScheme}, DEFAULT_CONF, IME_NAME};
+use crate::profile::{self, App, Profile};
 
-pub fn setup() {
-    unsafe { let _ = use_default(); }
+//----------------------------------------------------------------------------
+//
+//  Configuration. Defaults are in res/conf.toml, on top of which the user's
+//  conf.toml in %APPDATA%\Ajemi is loaded. Values that are invalid are
+//  reported and left as the defaults. See profile.rs for [[profile]].
+//
+//----------------------------------------------------------------------------
+
+#[derive(Clone)]
+pub struct Config {
+    pub font: Font,
+    pub layout: Layout,
+    pub color: ColorScheme,
+    pub behavior: Behavior,
+    pub schema: SchemaOrder,
+    pub spacing: SchemaSpacing,
+    pub keys: KeyBindings,
+    pub keymap: Keymap,
+    pub chord: ChordInput,
+    pub record: Record,
+    pub stats: TypingStats,
+    pub profiles: Vec<Profile>,
+}
+
+#[derive(Clone)]
+pub struct Font {
+    pub name: String,
+    pub size: i32,
+}
+
+#[derive(Clone)]
+pub struct Behavior {
+    /// If the IME is on in a window that is newly focused.
+    pub enabled: bool,
+    pub long_pi: bool,
+    pub long_glyph: bool,
+    pub caps_lock_ascii: bool,
+    /// If cartouches and long glyphs are closed as they're opened, with the caret inside.
+    pub auto_close: bool,
+    /// If words can be typed by their Latin names, whose letters are folded into the spellings.
+    pub latin_spelling: bool,
+}
+
+/// The schemas in the order they're switched through. The first one is used by default.
+/// Also the tiers of words suggested from them.
+#[derive(Clone)]
+pub struct SchemaOrder {
+    pub order: Vec<String>,
+    /// If each tier is suggested, in the order of `TIER_NAMES`.
+    pub tiers: [bool; TIER_NAMES.len()],
+    pub penalty: TierPenalty,
+}
+
+/// How many letters shorter than it's typed a word of each tier ranks as among the candidates,
+/// in the order of `TIER_NAMES`.
+#[derive(Default, Clone)]
+pub struct TierPenalty {
+    pub penalties: [i32; TIER_NAMES.len()],
+}
+
+/// What separates words, see [spacing].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    #[default]
+    None,
+    Ideographic,
+    Ascii,
+    ZeroWidth,
+    Thin,
+}
+
+const SPACINGS: [(&str, Spacing); 5] = [
+    ("none", Spacing::None), ("ideographic", Spacing::Ideographic), ("ascii", Spacing::Ascii),
+    ("zero_width", Spacing::ZeroWidth), ("thin", Spacing::Thin),
+];
+
+impl Spacing {
+    pub fn separator(self) -> &'static str {
+        match self {
+            Spacing::None => "",
+            Spacing::Ideographic => "\u{3000}",
+            Spacing::Ascii => " ",
+            Spacing::ZeroWidth => "\u{200B}",
+            Spacing::Thin => "\u{2009}",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        SPACINGS.iter().find(|(_, it)| *it == self).map(|(name, _)| *name).unwrap()
+    }
+}
+
+/// The spacing of each schema by name. Schemas not given any are spaced by nothing.
+#[derive(Clone)]
+pub struct SchemaSpacing {
+    pub spacings: HashMap<String, Spacing>,
+}
+
+impl SchemaSpacing {
+    pub fn of(&self, schema: &str) -> Spacing {
+        self.spacings.get(schema).copied().unwrap_or_default()
+    }
+
+    pub fn set(&mut self, schema: &str, spacing: Spacing) {
+        self.spacings.insert(schema.to_string(), spacing);
+    }
+}
+
+#[derive(Clone)]
+pub struct ChordInput {
+    pub enabled: bool,
+    pub overrides: ChordOverrides,
+}
+
+/// Compositions written to recording.toml for bug reports, see record.rs.
+#[derive(Clone)]
+pub struct Record {
+    pub enabled: bool,
+}
+
+/// Counters of compositions written to stats.toml, see stats.rs.
+#[derive(Clone)]
+pub struct TypingStats {
+    pub enabled: bool,
+    /// If the words selected are counted as well.
+    pub words: bool,
+}
+
+impl Config {
+    fn new() -> Config {
+        Config {
+            font: Font { name: String::new(), size: 0 },
+            layout: Layout::new(),
+            color: ColorScheme::new(),
+            behavior: Behavior { enabled: true, long_pi: false, long_glyph: false, caps_lock_ascii: true, auto_close: false, latin_spelling: false },
+            schema: SchemaOrder { order: Vec::new(), tiers: [true; TIER_NAMES.len()], penalty: TierPenalty { penalties: [0; TIER_NAMES.len()] } },
+            spacing: SchemaSpacing { spacings: HashMap::new() },
+            keys: KeyBindings::new(),
+            keymap: Keymap::new(),
+            chord: ChordInput { enabled: false, overrides: ChordOverrides::new() },
+            record: Record { enabled: false },
+            stats: TypingStats { enabled: true, words: false },
+            profiles: Vec::new(),
+        }
+    }
+
+    fn apply(&mut self, profile: &Profile) {
+        // problems are reported when the profile is loaded
+        self.load(Value::Table(profile.overrides.clone()), "", &mut Vec::new());
+    }
+
+    /// Parse the text of conf.toml on top of the defaults.
+    pub fn parse(text: &str) -> Result<(Config, Vec<Problem>)> {
+        let table = text.parse::<Table>()?;
+        let mut config = Config::default();
+        let mut problems = Vec::new();
+        config.load(Value::Table(table), "", &mut problems);
+        Ok((config, problems))
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let mut config = Config::new();
+        let table = DEFAULT_CONF.parse::<Table>().unwrap();
+        config.load(Value::Table(table), "", &mut Vec::new());
+        config
+    }
+}
+
+impl LoadValue for Config {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("font", &mut self.font);
+        table.give("layout", &mut self.layout);
+        table.give("color", &mut self.color);
+        table.give("behavior", &mut self.behavior);
+        table.give("schema", &mut self.schema);
+        table.give("spacing", &mut self.spacing);
+        table.give("keys", &mut self.keys);
+        table.give("keymap", &mut self.keymap);
+        table.give("chord", &mut self.chord);
+        table.give("record", &mut self.record);
+        table.give("stats", &mut self.stats);
+        table.give("profile", &mut self.profiles);
+        table.finish();
+        for (chord, action, other) in self.keys.conflicts() {
+            problems.push(Problem::warning(&Problem::join(path, &format!("keys.{other}")),
+                format!("{chord} is also bound to {action}, which is overridden")));
+        }
+        // check the overrides by loading them on their own
+        for (index, profile) in self.profiles.iter().enumerate() {
+            let path = Problem::join(path, &format!("profile[{index}]"));
+            Config::new().load(Value::Table(profile.overrides.clone()), &path, problems);
+        }
+    }
+}
+
+impl LoadValue for Font {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("name", &mut self.name);
+        table.give("size", &mut self.size);
+        table.finish();
+    }
+}
+
+impl LoadValue for Behavior {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("enabled", &mut self.enabled);
+        table.give("long_pi", &mut self.long_pi);
+        table.give("long_glyph", &mut self.long_glyph);
+        table.give("caps_lock_ascii", &mut self.caps_lock_ascii);
+        table.give("auto_close", &mut self.auto_close);
+        table.give("latin_spelling", &mut self.latin_spelling);
+        table.finish();
+    }
+}
+
+impl LoadValue for SchemaOrder {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        let mut order: Vec<String> = Vec::new();
+        let mut tiers: Vec<String> = TIER_NAMES.iter().zip(self.tiers)
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| name.to_string())
+            .collect();
+        table.give("order", &mut order);
+        table.give("tiers", &mut tiers);
+        table.give("penalty", &mut self.penalty);
+        table.finish();
+        let order_path = Problem::join(path, "order");
+        for name in &order {
+            if !engine::schema_exists(name) {
+                problems.push(Problem::error(&order_path, format!("unknown schema {name:?}")));
+            }
+        }
+        order.retain(|name| engine::schema_exists(name));
+        if !order.is_empty() {
+            self.order = order;
+        }
+        self.tiers = tiers_named(&tiers, &Problem::join(path, "tiers"), problems);
+    }
+}
+
+/// If each tier is in `names`, reporting the unknown names at `path`.
+pub(crate) fn tiers_named(names: &[String], path: &str, problems: &mut Vec<Problem>) -> [bool; TIER_NAMES.len()] {
+    for name in names {
+        if !TIER_NAMES.contains(&name.as_str()) {
+            problems.push(Problem::error(path, format!("unknown tier {name:?}")));
+        }
+    }
+    TIER_NAMES.map(|tier| names.iter().any(|name| name == tier))
 }
 
-pub fn reload() {
-    unsafe { let _ = use_customized(); }
+impl LoadValue for TierPenalty {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        let previous = self.penalties;
+        for (name, penalty) in TIER_NAMES.iter().zip(&mut self.penalties) {
+            table.give(name, penalty);
+        }
+        table.finish();
+        // negative penalties are left as they were
+        for (index, name) in TIER_NAMES.iter().enumerate() {
+            if self.penalties[index] < 0 {
+                problems.push(Problem::error(&Problem::join(path, name), format!("{} is negative", self.penalties[index])));
+                self.penalties[index] = previous[index];
+            }
+        }
+    }
+}
+
+impl LoadValue for Spacing {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::String(name) = value else {
+            problems.push(Problem::mistyped(path, "a string", &value));
+            return;
+        };
+        match SPACINGS.iter().find(|(it, _)| *it == name) {
+            Some((_, spacing)) => *self = *spacing,
+            None => problems.push(Problem::error(path, format!("unknown spacing {name:?}"))),
+        }
+    }
+}
+
+impl LoadValue for SchemaSpacing {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        let names = SCHEMA_NAMES.iter().map(|name| name.to_string()).chain(engine::user_schema_names());
+        for name in names {
+            let mut spacing = self.of(&name);
+            table.give(&name, &mut spacing);
+            self.set(&name, spacing);
+        }
+        table.finish();
+    }
+}
+
+impl LoadValue for ChordInput {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("enabled", &mut self.enabled);
+        table.give("overrides", &mut self.overrides);
+        table.finish();
+    }
+}
+
+impl LoadValue for Record {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("enabled", &mut self.enabled);
+        table.finish();
+    }
+}
+
+impl LoadValue for TypingStats {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("enabled", &mut self.enabled);
+        table.give("words", &mut self.words);
+        table.finish();
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//  Problems found in conf.toml.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The value is ignored.
+    Error,
+    /// The value is used, but probably not as intended.
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub severity: Severity,
+    /// The path of the key like `behavior.long_glyph`.
+    pub path: String,
+    pub message: String,
 }
 
-unsafe fn use_default() -> Result<()>{
-    use_conf(DEFAULT_CONF)
+impl Problem {
+    pub fn error(path: &str, message: impl Into<String>) -> Problem {
+        Problem { severity: Severity::Error, path: path.to_string(), message: message.into() }
+    }
+
+    pub fn warning(path: &str, message: impl Into<String>) -> Problem {
+        Problem { severity: Severity::Warning, path: path.to_string(), message: message.into() }
+    }
+
+    pub fn mistyped(path: &str, expected: &str, found: &Value) -> Problem {
+        Problem::error(path, format!("expected {expected}, found {}", found.type_str()))
+    }
+
+    pub fn join(path: &str, key: &str) -> String {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{path}.{key}")
+        }
+    }
 }
 
-unsafe fn use_customized() -> Result<()> {
-    let path = PathBuf::from(env::var("APPDATA")?).join(IME_NAME).join("conf.toml");
-    let last_modified = fs::metadata(&path)?.last_write_time();
-    if last_modified == LAST_MODIFIED {
-        return Ok(());
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}: {}", self.path, self.message)
     }
-    let customized = fs::read_to_string(path)?;
-    use_conf(DEFAULT_CONF)?;
-    use_conf(&customized)?;
-    LAST_MODIFIED = last_modified;
-    Ok(())
 }
 
-unsafe fn use_conf(text: &str) -> Result<()>{
-    let mut table = text.parse::<Table>()?;
-    if let Some(Value::Table(color)) = table.get_mut("color") {
-        color.give("candidate", &mut CANDI_COLOR);
-        color.give("highlighted", &mut CANDI_HIGHLIGHTED_COLOR);
-        color.give("index", &mut INDEX_COLOR);
-        color.give("clip", &mut CLIP_COLOR);
-        color.give("background", &mut BKG_COLOR);
-        color.give("highlight", &mut HIGHTLIGHT_COLOR);
+//----------------------------------------------------------------------------
+//
+//  The global config.
+//
+//----------------------------------------------------------------------------
+
+/// The config loaded from the files, and the profile applied to it.
+struct Base {
+    config: Config,
+    app: Option<App>,
+    profile: Option<usize>,
+    last_modified: Option<SystemTime>,
+}
+
+static BASE: OnceLock<Mutex<Base>> = OnceLock::new();
+/// The config in use, with the profile of the app applied.
+static CONF: OnceLock<RwLock<Config>> = OnceLock::new();
+
+fn lock() -> &'static RwLock<Config> {
+    CONF.get_or_init(|| RwLock::new(Config::default()))
+}
+
+/// The config in use. Don't keep it while calling `conf_mut`.
+pub fn conf() -> RwLockReadGuard<'static, Config> {
+    lock().read_recursive()
+}
+
+pub fn conf_mut() -> RwLockWriteGuard<'static, Config> {
+    lock().write()
+}
+
+/// Taken before `conf_mut` where both are, never after.
+fn base() -> MutexGuard<'static, Base> {
+    BASE.get_or_init(|| Mutex::new(Base { config: Config::default(), app: None, profile: None, last_modified: None })).lock()
+}
+
+pub fn setup() {
+    lock();
+}
+
+/// Switch to the profile of the app. Returns true if the config in use is replaced.
+pub fn use_profile(app: App) -> bool {
+    let mut base = base();
+    let profile = profile::find(&base.config.profiles, &app);
+    base.app = Some(app);
+    if profile == base.profile {
+        return false;
     }
+    base.profile = profile;
+    activate(&base);
+    true
+}
 
-    if let Some(Value::Table(layout)) = table.get_mut("layout") {
-        layout.give("vertical", &mut VERTICAL);
+/// Replace the config in use as a whole so that nothing sees a half-applied one.
+fn activate(base: &Base) {
+    let mut config = base.config.clone();
+    if let Some(profile) = base.profile.and_then(|index| base.config.profiles.get(index)) {
+        config.apply(profile);
     }
+    *conf_mut() = config;
+}
 
-    if let Some(Value::Table(font)) = table.get_mut("font") {
-        font.give("name", &mut FONT);
-        font.give("size", &mut FONT_SIZE);
+/// Load the user's conf.toml if it's modified, created or deleted since last time.
+/// Returns true if the config is replaced. It's cheap enough to be called frequently.
+pub fn reload() -> bool {
+    match use_customized() {
+        Ok(replaced) => replaced,
+        Err(err) => {
+            error!("Failed to load conf.toml. {err}");
+            false
+        }
     }
+}
+
+/// %APPDATA%\Ajemi, where conf.toml and themes are.
+pub fn dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(env::var("APPDATA")?).join(IME_NAME))
+}
+
+pub fn path() -> Result<PathBuf> {
+    Ok(dir()?.join("conf.toml"))
+}
 
-    if let Some(Value::Table(behavior)) = table.get_mut("behavior") {
-        behavior.give("long_pi", &mut LONG_PI);
-        behavior.give("long_glyph", &mut LONG_GLYPH);
-        behavior.give("cjk_space", &mut CJK_SPACE);
+fn use_customized() -> Result<bool> {
+    let path = path()?;
+    let last_modified = match fs::metadata(&path) {
+        Ok(metadata) => Some(metadata.modified()?),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    let mut base = base();
+    if last_modified == base.last_modified {
+        return Ok(false);
+    }
+    base.last_modified = last_modified;
+    if last_modified.is_none() {
+        base.config = Config::default();
+    } else {
+        let (config, problems) = Config::parse(&fs::read_to_string(path)?)?;
+        for problem in problems {
+            match problem.severity {
+                Severity::Error => error!("{problem}"),
+                Severity::Warning => warn!("{problem}"),
+            }
+        }
+        base.config = config;
     }
-    Ok(())
-}
\ No newline at end of file
+    base.profile = base.app.as_ref().and_then(|app| profile::find(&base.config.profiles, app));
+    activate(&base);
+    Ok(true)
+}
+
+/// Check the text of conf.toml. Syntax errors are returned as `Err`.
+pub fn validate(text: &str) -> Result<Vec<Problem>> {
+    Config::parse(text).map(|(_, problems)| problems)
+}
+
+#[test]
+fn test() {
+    assert_eq!(validate(DEFAULT_CONF).unwrap(), Vec::new());
+    let problems = validate(r##"
+        [behavior]
+        long_glyph = "yes"
+        long_pi = true
+        long_ai = true
+        [color]
+        candidate = "#0078D7"
+        index = "0078D7"
+        [keys]
+        commit = "Ctrl+Hyper"
+        [typo]
+        [[profile]]
+        exe = "Discord.exe"
+        schema.order = ["emoji", "toki"]
+        behavior.long_gliph = true
+    "##).unwrap();
+    assert_eq!(problems, vec![
+        Problem::error("color.index", "expected a color like \"#RRGGBB\", found \"0078D7\""),
+        Problem::error("behavior.long_glyph", "expected a boolean, found string"),
+        Problem::warning("behavior.long_ai", "unknown key"),
+        Problem::error("keys.commit", "unrecognizable chord \"Ctrl+Hyper\""),
+        Problem::warning("typo", "unknown key"),
+        Problem::warning("profile[0].behavior.long_gliph", "unknown key"),
+        Problem::error("profile[0].schema.order", "unknown schema \"toki\""),
+    ]);
+    let (mut config, _) = Config::parse(r#"
+        [[profile]]
+        class = "OpusApp"
+        behavior.long_glyph = true
+        schema.order = ["emoji"]
+    "#).unwrap();
+    let profile = config.profiles[0].clone();
+    config.apply(&profile);
+    assert!(config.behavior.long_glyph);
+    assert_eq!(config.schema.order, ["emoji"]);
+    let (config, _) = Config::parse("behavior.long_glyph = \"yes\"\nbehavior.long_pi = true").unwrap();
+    assert!(!config.behavior.long_glyph);
+    assert!(config.behavior.long_pi);
+    let (config, problems) = Config::parse("spacing.emoji = \"thin\"\nspacing.sitelen = \"wide\"\nspacing.toki = \"none\"").unwrap();
+    assert_eq!(config.spacing.of("emoji").separator(), "\u{2009}");
+    assert_eq!(config.spacing.of("sitelen"), Spacing::None);
+    assert_eq!(problems, vec![
+        Problem::error("spacing.sitelen", "unknown spacing \"wide\""),
+        Problem::warning("spacing.toki", "unknown key"),
+    ]);
+    let (config, problems) = Config::parse("schema.tiers = [\"pu\", \"ku pi ale\"]\nschema.penalty.\"ku lili\" = -1").unwrap();
+    assert_eq!(config.schema.tiers, [true, false, false, false]);
+    assert_eq!(config.schema.penalty.penalties, [0; 4]);
+    assert_eq!(problems, vec![
+        Problem::error("schema.penalty.ku lili", "-1 is negative"),
+        Problem::error("schema.tiers", "unknown tier \"ku pi ale\""),
+    ]);
+    assert!(validate("[behavior").is_err());
+}
@@ -0,0 +1,188 @@
+use toml::Value;
+use crate::{conf::Problem, extend::LoadValue};
+use super::{compile::key_set, schema::Schema, Engine};
+
+//----------------------------------------------------------------------------
+//
+//  Chorded input. The keys held together until all of them are released
+//  form a stroke, which is resolved to a word spelled with these keys.
+//  A key released and pressed again while others are held is repeated
+//  in the stroke, thus "aueei" can be typed within a single stroke.
+//
+//----------------------------------------------------------------------------
+
+/// Keys pressed within a stroke, in the order they are pressed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stroke {
+    pub keys: String,
+    /// When the last key is released.
+    pub time: u64,
+}
+
+impl Stroke {
+    /// The distinct keys in alphabetical order, which is how strokes are indexed.
+    pub fn key_set(&self) -> String {
+        key_set(&self.keys)
+    }
+}
+
+/// Turns timestamped key-downs and key-ups into strokes. Timestamps are in milliseconds.
+#[derive(Default)]
+pub struct ChordResolver {
+    held: Vec<char>,
+    pressed: Vec<(u64, char)>,
+}
+
+impl ChordResolver {
+    pub fn key_down(&mut self, key: char, time: u64) {
+        // repeated key-downs from holding the key
+        if self.held.contains(&key) {
+            return;
+        }
+        self.held.push(key);
+        self.pressed.push((time, key));
+    }
+
+    /// Returns the stroke when every key is released.
+    pub fn key_up(&mut self, key: char, time: u64) -> Option<Stroke> {
+        // the key might be pressed before chorded input is turned on
+        let index = self.held.iter().position(|held| *held == key)?;
+        self.held.remove(index);
+        if !self.held.is_empty() {
+            return None;
+        }
+        self.pressed.sort_by_key(|(time, _)| *time);
+        let keys = self.pressed.drain(..).map(|(_, key)| key).collect();
+        Some(Stroke { keys, time })
+    }
+
+    pub fn is_holding(&self, key: char) -> bool {
+        self.held.contains(&key)
+    }
+
+    pub fn reset(&mut self) {
+        self.held.clear();
+        self.pressed.clear();
+    }
+}
+
+/// Strokes resolved to words other than the ones derived from the schema.
+/// They are given as key sets and spellings, for example `aei = "aeia"`.
+#[derive(Default, Clone)]
+pub struct ChordOverrides {
+    overrides: Vec<(String, String)>,
+}
+
+impl ChordOverrides {
+    pub const fn new() -> ChordOverrides {
+        ChordOverrides { overrides: Vec::new() }
+    }
+
+    /// The key sets and the spellings they resolve to.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.overrides.iter().map(|(keys, spelling)| (keys.as_str(), spelling.as_str()))
+    }
+
+    fn get(&self, key_set: &str) -> Option<&str> {
+        self.overrides.iter()
+            .find(|(keys, _)| keys == key_set)
+            .map(|(_, spelling)| spelling.as_str())
+    }
+}
+
+impl LoadValue for ChordOverrides {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        for (keys, spelling) in table {
+            let Value::String(spelling) = spelling else {
+                problems.push(Problem::mistyped(&Problem::join(path, &keys), "a string", &spelling));
+                continue;
+            };
+            let keys = key_set(&keys);
+            self.overrides.retain(|(overridden, _)| *overridden != keys);
+            self.overrides.push((keys, spelling));
+        }
+    }
+}
+
+impl Schema {
+    /// Find the word for the stroke, preferring the one spelled exactly as the keys
+    /// are pressed, then the one using the keys in the same order, then the shorter one.
+    fn resolve_stroke(&self, stroke: &Stroke) -> Option<(&str, &str)> {
+        let order = |spelling: &str| {
+            let mut order: Vec<char> = Vec::new();
+            for ch in spelling.chars() {
+                if !order.contains(&ch) {
+                    order.push(ch);
+                }
+            }
+            order
+        };
+        let stroke_order = order(&stroke.keys);
+        self.chords(&stroke.key_set())
+            .min_by_key(|(spelling, _)| (
+                *spelling != stroke.keys,
+                order(spelling) != stroke_order,
+                spelling.len()))
+    }
+
+    fn word_of(&self, spelling: &str) -> Option<&str> {
+        self.chords(&key_set(spelling))
+            .find(|(candidate, _)| *candidate == spelling)
+            .map(|(_, word)| word)
+    }
+}
+
+impl Engine {
+    /// Resolve the stroke into the spelling and the word.
+    pub fn resolve_stroke(&self, stroke: &Stroke, overrides: &ChordOverrides) -> Option<(String, String)> {
+        let schema = self.schema();
+        if let Some(spelling) = overrides.get(&stroke.key_set()) {
+            if let Some(word) = schema.word_of(spelling) {
+                return Some((spelling.to_string(), word.to_string()));
+            }
+        }
+        schema.resolve_stroke(stroke)
+            .map(|(spelling, word)| (spelling.to_string(), word.to_string()))
+    }
+}
+
+#[test]
+fn test() {
+    let mut resolver = ChordResolver::default();
+    // a, u and e pressed together, e pressed again, then i
+    resolver.key_down('a', 0);
+    resolver.key_down('u', 5);
+    resolver.key_down('e', 12);
+    resolver.key_down('e', 40);
+    assert_eq!(resolver.key_up('e', 60), None);
+    resolver.key_down('e', 70);
+    resolver.key_down('i', 80);
+    assert_eq!(resolver.key_up('u', 90), None);
+    assert_eq!(resolver.key_up('e', 91), None);
+    assert_eq!(resolver.key_up('i', 92), None);
+    assert_eq!(resolver.key_up('a', 100), Some(Stroke { keys: "aueei".to_string(), time: 100 }));
+    assert_eq!(resolver.key_up('o', 110), None);
+    // rolled over
+    resolver.key_down('o', 200);
+    resolver.key_down('a', 210);
+    assert_eq!(resolver.key_up('o', 220), None);
+    let stroke = resolver.key_up('a', 230).unwrap();
+    assert_eq!(stroke.keys, "oa");
+    assert_eq!(stroke.key_set(), "ao");
+
+    let schema = Schema::new(crate::SITELEN_SCHEMA).unwrap();
+    let stroke = |keys: &str| Stroke { keys: keys.to_string(), time: 0 };
+    assert_eq!(schema.resolve_stroke(&stroke("aueei")).map(|it| it.0), Some("aueei"));
+    assert_eq!(schema.resolve_stroke(&stroke("oa")).map(|it| it.0), Some("oa"));
+    assert_eq!(schema.resolve_stroke(&stroke("ao")).map(|it| it.0), Some("ao"));
+    assert_eq!(schema.resolve_stroke(&stroke("aoa")).map(|it| it.0), Some("aoa"));
+
+    let mut overrides = ChordOverrides::new();
+    overrides.load(r#"oa = "aoa""#.parse::<toml::Table>().map(Value::Table).unwrap(), "chord.overrides", &mut Vec::new());
+    assert_eq!(overrides.get("ao"), Some("aoa"));
+    assert_eq!(schema.word_of("aoa"), Some("󱤂"));
+}
@@ -0,0 +1,555 @@
+use std::{collections::HashMap, fmt};
+
+//----------------------------------------------------------------------------
+//
+//  Compile schemas from text into the binary format that schema.rs reads in
+//  place, so that loading a schema takes no parsing. The text stays the
+//  source of truth. The built-in schemas are compiled by build.rs, which
+//  includes this file on its own, hence nothing else from the crate is used
+//  here. The schemas of users are compiled by `ajemi-cli compile`.
+//
+//----------------------------------------------------------------------------
+
+pub const MAGIC: &[u8; 4] = b"AJMS";
+pub const VERSION: u32 = 4;
+
+// The binary starts with the magic, the version, the open and close quotes
+// (single then double) and the offset and count of each section, followed by
+// the sections. Integers are u32 in little endian. Strings are referenced by
+// their offset in STRINGS and length in bytes, ranges of word ids by their
+// index in IDS and length. Words are sorted, so are the keys of PREFIXES,
+// CHORDS, PUNCTS and LETTERS, all of which are looked up by binary search. The rules
+// of a punctuator go from the most specific one, see `Source::parse`.
+
+/// The bytes of the strings, which a prefix shares with the spelling it's from.
+pub const STRINGS: usize = 0;
+/// Word, alternatives, note spelling, note Latin name, note gloss, note tier.
+/// The tier is its index in `TIER_NAMES` plus 1, or 0 if the word has none.
+pub const WORDS: usize = 1;
+/// Prefix of a spelling, kind, the exact word, other words.
+pub const PREFIXES: usize = 2;
+/// Key set, entries.
+pub const CHORDS: usize = 3;
+/// Spelling, word.
+pub const CHORD_ENTRIES: usize = 4;
+/// Punctuator, if it's typed twice, condition, output.
+pub const PUNCTS: usize = 5;
+pub const IDS: usize = 6;
+/// Latin letter, the char it's spelled with.
+pub const LETTERS: usize = 7;
+pub const SECTIONS: usize = 8;
+
+/// The numbers of u32 in a record of each section.
+pub const RECORD_LENS: [usize; SECTIONS] = [0, 11, 6, 4, 3, 6, 1, 2];
+pub const HEADER_LEN: usize = 4 + 4 + 4 * 4 + SECTIONS * 2 * 4;
+
+/// The tiers words are tagged with by a line like `[ku suli]`, which are turned on and
+/// off by `[schema] tiers` in conf.toml. Words before any tier are always suggested.
+pub const TIER_NAMES: [&str; 4] = ["pu", "ku suli", "ku lili", "nimi sin"];
+
+/// The kinds of prefixes, see `Candidate`.
+pub const EXACT: u32 = 0;
+pub const UNIQUE: u32 = 1;
+pub const DUPLICATES: u32 = 2;
+
+/// When a punctuation rule applies, besides the punctuator being typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Always,
+    /// The punctuator ends a composition.
+    Composing,
+    /// The punctuator is typed while not composing.
+    Idle,
+    /// The punctuator is typed right after the char.
+    After(char),
+}
+
+impl Condition {
+    pub fn encode(self) -> [u32; 2] {
+        use Condition::*;
+        match self {
+            Always => [0, 0],
+            Composing => [1, 0],
+            Idle => [2, 0],
+            After(ch) => [3, ch as u32],
+        }
+    }
+
+    pub fn decode([kind, ch]: [u32; 2]) -> Condition {
+        use Condition::*;
+        match kind {
+            1 => Composing,
+            2 => Idle,
+            3 => After(char::from_u32(ch).unwrap_or_default()),
+            _ => Always,
+        }
+    }
+}
+
+/// The distinct keys in alphabetical order, which is how chorded strokes are indexed.
+pub fn key_set(keys: &str) -> String {
+    let mut chars: Vec<char> = keys.chars().collect();
+    chars.sort_unstable();
+    chars.dedup();
+    chars.into_iter().collect()
+}
+
+/// Compile the text of a schema. Lines that can't be understood are returned as `Err`.
+pub fn compile(text: &str) -> Result<Vec<u8>, Vec<String>> {
+    let source = Source::parse(text)?;
+    Ok(Writer::default().write(&source))
+}
+
+//----------------------------------------------------------------------------
+//
+//  The text.
+//
+//----------------------------------------------------------------------------
+
+/// To expain why a certain spelling is mapped to certain word(s)
+enum Candidate {
+    /// The spelling is an exact spelling of a certain word.
+    /// Meanwhile it can also be a prefix of other words.
+    /// For example, `"li"` is `Exact("li", ["lili", "linja", "lipu"])`.
+    Exact(String, Vec<String>),
+    /// The spelling is unique prefix for a certain word. No other words starts with it.
+    /// For example, `"kije"` is `Unique("kijetesantakalu")`.
+    Unique(String),
+    /// The spelling is not an exact spelling or a unique prefix.
+    /// For example, `"an"` is `Duplicates(["anpa", "ante", "anu"])`.
+    Duplicates(Vec<String>)
+}
+
+/// The code, the Latin name and the meaning of a word, from `spelling word | latin | gloss`.
+#[derive(Clone)]
+struct Note {
+    spelling: String,
+    latin: String,
+    gloss: String,
+    tier: Option<usize>,
+}
+
+/// `key output [condition]`, where the key is typed twice for a double tap like `..`.
+struct PunctRule {
+    key: char,
+    twice: bool,
+    condition: Condition,
+    output: String,
+}
+
+/// A Latin letter and the char it's spelled with, like `t=u`.
+fn letter(token: &str) -> Option<(char, char)> {
+    let mut chars = token.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some('='), Some(spelled), None) if letter.is_ascii_alphanumeric() && spelled.is_ascii_alphanumeric() =>
+            Some((letter, spelled)),
+        _ => None,
+    }
+}
+
+/// The schema as it's written, indexed the way it's looked up.
+struct Source {
+    candis: HashMap<String, Candidate>,
+    alters: HashMap<String, Vec<String>>,
+    /// Key sets of chorded strokes -> spellings and words, in the order they're defined.
+    chords: HashMap<String, Vec<(String, String)>>,
+    /// Words and their alternatives -> notes, the first one if a word is spelled in many ways.
+    notes: HashMap<String, Note>,
+    /// In the order they're written.
+    puncts: Vec<PunctRule>,
+    squote: (char, char),
+    dquote: (char, char),
+    /// Latin letters -> the chars they're spelled with.
+    letters: HashMap<char, char>,
+}
+
+#[derive(Clone, Copy)]
+enum Atom<'a> {
+    Text(&'a str),
+    Punct(char)
+}
+
+impl<'a> From<&'a str> for Atom<'a> {
+    fn from(str: &'a str) -> Atom<'a> {
+        use Atom::*;
+        let mut chars = str.chars();
+        let first_ch = chars.nth(0).unwrap();
+        if !first_ch.is_alphanumeric() && chars.nth(1).is_none() {
+            Punct(first_ch)
+        } else if first_ch == '#' {
+            match u32::from_str_radix(&str[1..], 16).ok().and_then(char::from_u32) {
+                Some(punct) => Punct(punct),
+                None => Text(str)
+            }
+        } else if str == "space" {
+            Punct(' ')
+        } else {
+            Text(str)
+        }
+    }
+}
+
+impl fmt::Display for Atom<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Atom::*;
+        match self {
+            Text(text) => f.write_str(text),
+            Punct(punct) => write!(f, "{punct}"),
+        }
+    }
+}
+
+/// The key of a punctuation rule, like `.`, `#3000` or `space`, or a punctuator written twice
+/// like `..` for a double tap, whose output replaces that of the first tap.
+fn punct_key(token: &str) -> Option<(char, bool)> {
+    let mut chars = token.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(ch), None, _) if !ch.is_alphanumeric() => Some((ch, false)),
+        (Some(ch), Some(again), None) if !ch.is_alphanumeric() && again == ch => Some((ch, true)),
+        _ => match Atom::from(token) {
+            Atom::Punct(ch) => Some((ch, false)),
+            Atom::Text(_) => None,
+        }
+    }
+}
+
+/// The output of a punctuation rule, `space` or chars each written as itself or as a codepoint,
+/// like `󱦜#3000`. A codepoint ends at 6 hex digits or at anything else.
+fn punct_output(token: &str) -> Option<String> {
+    if token == "space" {
+        return Some(" ".to_string());
+    }
+    let mut output = String::new();
+    let mut rest = token;
+    while let Some(ch) = rest.chars().next() {
+        let digits = match ch {
+            '#' => rest[1..].bytes().take(6).take_while(u8::is_ascii_hexdigit).count(),
+            _ => 0,
+        };
+        if digits == 0 {
+            output.push(ch);
+            rest = &rest[ch.len_utf8()..];
+        } else {
+            output.push(char::from_u32(u32::from_str_radix(&rest[1..=digits], 16).ok()?)?);
+            rest = &rest[1 + digits..];
+        }
+    }
+    Some(output)
+}
+
+/// `composing`, `idle` or `after:X`, where X is a char or a codepoint.
+fn punct_condition(token: &str) -> Option<Condition> {
+    match token {
+        "composing" => Some(Condition::Composing),
+        "idle" => Some(Condition::Idle),
+        _ => {
+            let after = punct_output(token.strip_prefix("after:")?)?;
+            let mut chars = after.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(Condition::After(ch)),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl PunctRule {
+    fn parse(tokens: &[&str]) -> Option<PunctRule> {
+        let (key, twice) = punct_key(tokens.first()?)?;
+        let (output, condition) = match tokens[1..] {
+            [output] => (output, Condition::Always),
+            [output, condition] => (output, punct_condition(condition)?),
+            _ => return None,
+        };
+        Some(PunctRule { key, twice, condition, output: punct_output(output)? })
+    }
+}
+
+impl Source {
+    /// Parse the text, which is a word or a rule per line.
+    ///
+    /// Words go `spelling word alternatives... | Latin name | gloss`.
+    ///
+    /// Words go into the tier of the line like `[pu]` before them, see `TIER_NAMES`.
+    ///
+    /// Letters go `t=u k=u ...`, a Latin letter and the char it's spelled with each, which
+    /// spell the Latin names typed in place of the spellings, see `[behavior] latin_spelling`.
+    ///
+    /// Punctuators go `key output [condition]`, see `PunctRule`, except for `'` and `"`,
+    /// which go `' open close` and are opened and closed in turn. Of the rules of a
+    /// punctuator, the one for a double tap goes first, then the ones with a condition,
+    /// then the rest, each in the order they're written. The first one that applies is used.
+    fn parse(text: &str) -> Result<Source, Vec<String>> {
+        use Atom::*;
+        use Candidate::*;
+        let mut candis = HashMap::new();
+        let mut alters = HashMap::new();
+        let mut chords: HashMap<String, Vec<(String, String)>> = HashMap::new();
+        let mut notes = HashMap::new();
+        let mut puncts = Vec::new();
+        let mut squote = ('\'', '\'');
+        let mut dquote = ('"', '"');
+        let mut letters = HashMap::new();
+        let mut tier = None;
+        let mut problems = Vec::new();
+
+        let mut atoms = Vec::new();
+        for (index, list) in text.lines().enumerate() {
+            if list.is_empty() || list.starts_with("//") {
+                continue;
+            }
+            // not a punctuation rule like `[ #F1990`
+            if let Some(name) = list.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).filter(|name| name.starts_with(char::is_alphabetic)) {
+                match TIER_NAMES.iter().position(|it| *it == name) {
+                    Some(index) => tier = Some(index),
+                    None => problems.push(format!("line {}: unknown tier {name}", index + 1)),
+                }
+                continue;
+            }
+            // the Latin name and the gloss come after a bar
+            let (list, latin, gloss) = match list.split_once('|') {
+                Some((list, rest)) => {
+                    let (latin, gloss) = rest.split_once('|').unwrap_or((rest, ""));
+                    (list, latin.trim(), gloss.trim())
+                }
+                None => (list, "", ""),
+            };
+            if let Some(pairs) = list.split_whitespace().map(letter).collect::<Option<Vec<_>>>() {
+                letters.extend(pairs);
+                continue;
+            }
+            atoms.clear();
+            atoms.extend(list.split(char::is_whitespace).filter(|str|!str.is_empty()).map(Atom::from));
+            match atoms[..] {
+                [Punct('\''), Punct(open), Punct(close)] => {
+                    squote = (open, close);
+                }
+                [Punct('"'), Punct(open), Punct(close)] => {
+                    dquote = (open, close);
+                }
+                [Punct('\''), Punct(dumb)] => {
+                    squote = (dumb, dumb);
+                }
+                [Punct('"'), Punct(dumb)] => {
+                    dquote = (dumb, dumb);
+                }
+                [Punct(_), ..] => {
+                    let tokens: Vec<&str> = list.split_whitespace().collect();
+                    match PunctRule::parse(&tokens) {
+                        Some(rule) => puncts.push(rule),
+                        None => problems.push(format!("line {}: unrecogniable punctuation rule: {list}", index + 1)),
+                    }
+                }
+                [Text(spelling), word, .. ] => {
+                    // store exact spelling -> word, keeping the words before it. one spelled
+                    // alike stays the exact one, with this one the first of the others
+                    let candi = match candis.remove(spelling) {
+                        None => Exact(word.to_string(), Vec::new()),
+                        Some(Unique(unique)) => Exact(word.to_string(), vec![unique]),
+                        Some(Duplicates(others)) => Exact(word.to_string(), others),
+                        Some(Exact(exact, mut others)) => {
+                            others.insert(0, word.to_string());
+                            Exact(exact, others)
+                        }
+                    };
+                    candis.insert(spelling.to_string(), candi);
+                    // store key set -> spelling and word
+                    chords.entry(key_set(spelling))
+                        .or_default()
+                        .push((spelling.to_string(), word.to_string()));
+                    // store prefixes -> word
+                    for len in 1..spelling.len() {
+                        let prefix = &spelling[0..len];
+                        match candis.get_mut(prefix) {
+                            None => {
+                                candis.insert(prefix.to_string(), Unique(word.to_string()));
+                            },
+                            Some(Unique(unique)) => {
+                                let duplicates = vec![unique.clone(), word.to_string()];
+                                candis.insert(prefix.to_string(), Duplicates(duplicates));
+                            },
+                            Some(Duplicates(duplicates)) | Some(Exact(_, duplicates)) => {
+                                duplicates.push(word.to_string());
+                            }
+                        }
+                    }
+                    // store word and alternatives -> note
+                    let note = Note { spelling: spelling.to_string(), latin: latin.to_string(), gloss: gloss.to_string(), tier };
+                    for word in atoms.iter().skip(1) {
+                        notes.entry(word.to_string()).or_insert_with(|| note.clone());
+                    }
+                    // store word -> alternatives
+                    let word = word.to_string();
+                    for alter in atoms.iter().skip(2) {
+                        match alters.get_mut(&word) {
+                            None => {
+                                alters.insert(word.clone(), vec![alter.to_string()]);
+                            }
+                            Some(alters) => {
+                                alters.push(alter.to_string());
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    problems.push(format!("line {}: unrecogniable pattern: {list}", index + 1));
+                }
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        Ok(Source {candis, alters, chords, notes, puncts, squote, dquote, letters})
+    }
+
+    /// Every word and alternative, sorted, the index of which is the id of the word.
+    fn words(&self) -> Vec<&str> {
+        use Candidate::*;
+        let mut words: Vec<&str> = Vec::new();
+        for candi in self.candis.values() {
+            match candi {
+                Exact(word, others) => words.extend(Some(word).into_iter().chain(others).map(String::as_str)),
+                Unique(word) => words.push(word),
+                Duplicates(others) => words.extend(others.iter().map(String::as_str)),
+            }
+        }
+        for (word, alters) in &self.alters {
+            words.extend(Some(word).into_iter().chain(alters).map(String::as_str));
+        }
+        words.extend(self.notes.keys().map(String::as_str));
+        words.extend(self.chords.values().flatten().map(|(_, word)| word.as_str()));
+        words.sort_unstable();
+        words.dedup();
+        words
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//  The binary.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Default)]
+struct Writer {
+    sections: [Vec<u32>; SECTIONS],
+    strings: Vec<u8>,
+    string_refs: HashMap<String, [u32; 2]>,
+    word_ids: HashMap<String, u32>,
+}
+
+impl Writer {
+    fn write(mut self, source: &Source) -> Vec<u8> {
+        use Candidate::*;
+        // spellings first, so that their prefixes can share their bytes
+        let mut spellings: Vec<&str> = source.chords.values().flatten().map(|(spelling, _)| spelling.as_str()).collect();
+        spellings.sort_unstable();
+        for spelling in spellings {
+            self.string(spelling);
+        }
+        for (id, word) in source.words().into_iter().enumerate() {
+            self.word_ids.insert(word.to_string(), id as u32);
+        }
+
+        let mut words: Vec<(&String, &u32)> = Vec::new();
+        let word_ids = self.word_ids.clone();
+        words.extend(word_ids.iter());
+        words.sort_by_key(|(_, id)| **id);
+        for (word, _) in words {
+            let word_ref = self.string(word);
+            let alters = source.alters.get(word).map(Vec::as_slice).unwrap_or_default();
+            let alters = self.ids(alters);
+            let (note, tier) = match source.notes.get(word) {
+                Some(note) => ([self.string(&note.spelling), self.string(&note.latin), self.string(&note.gloss)], note.tier.map_or(0, |tier| tier as u32 + 1)),
+                None => ([[0, 0]; 3], 0),
+            };
+            self.sections[WORDS].extend(word_ref.into_iter().chain(alters).chain(note.into_iter().flatten()).chain([tier]));
+        }
+
+        let mut prefixes: Vec<(&String, &Candidate)> = source.candis.iter().collect();
+        prefixes.sort_unstable_by_key(|(prefix, _)| *prefix);
+        for (prefix, candi) in prefixes {
+            let prefix = self.string(prefix);
+            let (kind, exact, others) = match candi {
+                Exact(word, others) => (EXACT, self.word_ids[word], others.as_slice()),
+                Unique(word) => (UNIQUE, self.word_ids[word], [].as_slice()),
+                Duplicates(others) => (DUPLICATES, 0, others.as_slice()),
+            };
+            let others = self.ids(others);
+            self.sections[PREFIXES].extend(prefix.into_iter().chain([kind, exact]).chain(others));
+        }
+
+        let mut chords: Vec<(&String, &Vec<(String, String)>)> = source.chords.iter().collect();
+        chords.sort_unstable_by_key(|(key_set, _)| *key_set);
+        for (key_set, entries) in chords {
+            let key_set = self.string(key_set);
+            let start = (self.sections[CHORD_ENTRIES].len() / RECORD_LENS[CHORD_ENTRIES]) as u32;
+            for (spelling, word) in entries {
+                let spelling = self.string(spelling);
+                let word = self.word_ids[word];
+                self.sections[CHORD_ENTRIES].extend(spelling.into_iter().chain([word]));
+            }
+            self.sections[CHORDS].extend(key_set.into_iter().chain([start, entries.len() as u32]));
+        }
+
+        let mut puncts: Vec<&PunctRule> = source.puncts.iter().collect();
+        // stable, so that the rules as specific as each other stay in the order they're written
+        puncts.sort_by_key(|rule| (rule.key, !rule.twice, rule.condition == Condition::Always));
+        for rule in puncts {
+            let output = self.string(&rule.output);
+            self.sections[PUNCTS].extend([rule.key as u32, rule.twice as u32].into_iter().chain(rule.condition.encode()).chain(output));
+        }
+
+        let mut letters: Vec<(&char, &char)> = source.letters.iter().collect();
+        letters.sort_unstable();
+        for (letter, spelled) in letters {
+            self.sections[LETTERS].extend([*letter as u32, *spelled as u32]);
+        }
+
+        self.finish(source)
+    }
+
+    /// Intern the string, along with its prefixes.
+    fn string(&mut self, string: &str) -> [u32; 2] {
+        if let Some(string_ref) = self.string_refs.get(string) {
+            return *string_ref;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(string.as_bytes());
+        for len in (1..=string.len()).filter(|len| string.is_char_boundary(*len)) {
+            self.string_refs.entry(string[..len].to_string()).or_insert([offset, len as u32]);
+        }
+        [offset, string.len() as u32]
+    }
+
+    fn ids(&mut self, words: &[String]) -> [u32; 2] {
+        let start = self.sections[IDS].len() as u32;
+        for word in words {
+            self.sections[IDS].push(self.word_ids[word]);
+        }
+        [start, words.len() as u32]
+    }
+
+    fn finish(self, source: &Source) -> Vec<u8> {
+        let mut header: Vec<u32> = vec![VERSION, source.squote.0 as u32, source.squote.1 as u32, source.dquote.0 as u32, source.dquote.1 as u32];
+        let mut offset = HEADER_LEN;
+        for (section, records) in self.sections.iter().enumerate() {
+            if section == STRINGS {
+                header.extend([0, self.strings.len() as u32]);
+            } else {
+                header.extend([offset as u32, (records.len() / RECORD_LENS[section]) as u32]);
+                offset += records.len() * 4;
+            }
+        }
+        // the strings come last
+        header[5 + STRINGS * 2] = offset as u32;
+
+        let mut bytes = Vec::with_capacity(offset + self.strings.len());
+        bytes.extend_from_slice(MAGIC);
+        for int in header.into_iter().chain(self.sections.into_iter().flatten()) {
+            bytes.extend_from_slice(&int.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.strings);
+        bytes
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use super::compile::TIER_NAMES;
+
+//----------------------------------------------------------------------------
+//
+//  Generate the schemas from words.txt, a table of the words by their Latin
+//  names, with their glyphs in UCSUR and their emoji. A word is spelled by
+//  typing each Latin letter as a vowel, so that adding a word takes a line,
+//  and the schemas never spell a word differently. See `ajemi-cli generate`.
+//
+//----------------------------------------------------------------------------
+
+const HEADER: &str = "\
+// spelling word alternatives... | Latin name | gloss
+// Generated from words.txt by `ajemi-cli generate`. Edit that instead.
+";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Letters,
+    Words,
+    /// Goes at the end of the schema as it is.
+    Tail(usize),
+}
+
+/// `latin[=spelling] codepoint emoji... | gloss`
+struct Word<'a> {
+    latin: &'a str,
+    spelling: String,
+    /// If the spelling is written rather than mapped from the Latin name.
+    written: bool,
+    glyph: Option<char>,
+    emoji: Vec<&'a str>,
+    gloss: &'a str,
+}
+
+impl Word<'_> {
+    fn parse<'a>(line: &'a str, letters: &HashMap<char, char>) -> Result<Word<'a>, String> {
+        let (list, gloss) = line.split_once('|').map_or((line, ""), |(list, gloss)| (list, gloss.trim()));
+        let mut tokens = list.split_whitespace();
+        let (Some(name), Some(codepoint)) = (tokens.next(), tokens.next()) else {
+            return Err(format!("expected a Latin name and a codepoint: {line}"));
+        };
+        let (latin, spelling, written) = match name.split_once('=') {
+            Some((latin, spelling)) => {
+                if spelling.is_empty() || !spelling.chars().all(|ch| letters.values().any(|vowel| *vowel == ch)) {
+                    return Err(format!("{spelling:?} is not spelled with the vowels"));
+                }
+                (latin, spelling.to_string(), true)
+            }
+            None => {
+                let spelling = name.chars()
+                    .map(|letter| letters.get(&letter).copied().ok_or_else(|| format!("unknown letter {letter:?} in {name}")))
+                    .collect::<Result<String, String>>()?;
+                (name, spelling, false)
+            }
+        };
+        let glyph = match codepoint {
+            "-" => None,
+            _ => Some(codepoint.strip_prefix('#')
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .and_then(char::from_u32)
+                .ok_or_else(|| format!("unknown codepoint {codepoint}"))?),
+        };
+        Ok(Word { latin, spelling, written, glyph, emoji: tokens.collect(), gloss })
+    }
+
+    fn line(&self, words: &str) -> String {
+        match self.gloss {
+            "" => format!("{} {words} | {}\n", self.spelling, self.latin),
+            gloss => format!("{} {words} | {} | {gloss}\n", self.spelling, self.latin),
+        }
+    }
+}
+
+/// A line like `[ku suli]`, which the words after it are in.
+fn is_tier(line: &str) -> bool {
+    line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')).is_some_and(|name| TIER_NAMES.contains(&name))
+}
+
+/// Generate sitelen.schema and emoji.schema from the text of words.txt, by their names.
+/// Lines that can't be understood and words spelled like another one are returned as `Err`.
+pub fn generate(text: &str) -> Result<Vec<(&'static str, String)>, Vec<String>> {
+    let mut problems = Vec::new();
+    let mut letters = HashMap::new();
+    // spellings -> Latin names
+    let mut spelled: HashMap<String, &str> = HashMap::new();
+    let mut words = [String::new(), String::new()];
+    let mut tails = [String::new(), String::new()];
+    let mut section = None;
+    for (index, line) in text.lines().enumerate() {
+        let number = index + 1;
+        let comment = line.is_empty() || line.starts_with("//");
+        match line {
+            "[letters]" => section = Some(Section::Letters),
+            "[words]" => section = Some(Section::Words),
+            "[sitelen]" => section = Some(Section::Tail(0)),
+            "[emoji]" => section = Some(Section::Tail(1)),
+            _ => match section {
+                None | Some(Section::Letters) if comment => (),
+                None => problems.push(format!("line {number}: outside of any section: {line}")),
+                Some(Section::Letters) => {
+                    let mut chars = line.split_whitespace().map(|token| {
+                        let mut chars = token.chars();
+                        chars.next().filter(|_| chars.next().is_none())
+                    });
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some(Some(letter)), Some(Some(vowel)), None) => {
+                            letters.insert(letter, vowel);
+                        }
+                        _ => problems.push(format!("line {number}: expected a letter and a vowel: {line}")),
+                    }
+                }
+                Some(Section::Words) if line.starts_with('[') && !is_tier(line) =>
+                    problems.push(format!("line {number}: unknown tier {line}")),
+                // comments and tiers go to both schemas
+                Some(Section::Words) if comment || is_tier(line) => {
+                    for words in &mut words {
+                        words.push_str(line);
+                        words.push('\n');
+                    }
+                }
+                Some(Section::Words) => {
+                    let word = match Word::parse(line, &letters) {
+                        Ok(word) => word,
+                        Err(message) => {
+                            problems.push(format!("line {number}: {message}"));
+                            continue;
+                        }
+                    };
+                    match spelled.get(&word.spelling) {
+                        Some(other) if !word.written => problems.push(format!(
+                            "line {number}: {} is spelled {} like {other}, give it a spelling of its own like `{}={}`",
+                            word.latin, word.spelling, word.latin, word.spelling)),
+                        Some(_) => (),
+                        None => {
+                            spelled.insert(word.spelling.clone(), word.latin);
+                        }
+                    }
+                    if let Some(glyph) = word.glyph {
+                        words[0].push_str(&word.line(&glyph.to_string()));
+                    }
+                    if !word.emoji.is_empty() {
+                        words[1].push_str(&word.line(&word.emoji.join(" ")));
+                    }
+                }
+                Some(Section::Tail(index)) => {
+                    tails[index].push_str(line);
+                    tails[index].push('\n');
+                }
+            }
+        }
+    }
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    // the letters go first, sorted, so that Latin names can be typed
+    let mut letters: Vec<String> = letters.into_iter().map(|(letter, vowel)| format!("{letter}={vowel}")).collect();
+    letters.sort_unstable();
+    let head = match letters.is_empty() {
+        true => HEADER.to_string(),
+        false => format!("{HEADER}\n// Latin letter=vowel\n{}\n", letters.join(" ")),
+    };
+    let schema = |index: usize| match tails[index].trim_end() {
+        "" => format!("{head}\n{}\n", words[index].trim_end()),
+        tail => format!("{head}\n{}\n\n{tail}\n", words[index].trim_end()),
+    };
+    Ok(vec![("sitelen", schema(0)), ("emoji", schema(1))])
+}
+
+#[test]
+fn test() {
+    // the built-in schemas are kept generated
+    let schemas = generate(include_str!("../../res/schema/words.txt")).unwrap();
+    assert_eq!(schemas[0], ("sitelen", include_str!("../../res/schema/sitelen.schema").to_string()));
+    assert_eq!(schemas[1], ("emoji", include_str!("../../res/schema/emoji.schema").to_string()));
+
+    let text = "[letters]\na a\nk u\nl o\n[words]\n// pu\n[pu]\nala #F1902 ❌ | no\nkala - 🐟 🐙 | fish\n[sitelen]\n. 󱦜\n";
+    let schemas = generate(text).unwrap();
+    let head = format!("{HEADER}\n// Latin letter=vowel\na=a k=u l=o\n");
+    assert_eq!(schemas[0].1, format!("{head}\n// pu\n[pu]\naoa 󱤂 | ala | no\n\n. 󱦜\n"));
+    assert_eq!(schemas[1].1, format!("{head}\n// pu\n[pu]\naoa ❌ | ala | no\nuaoa 🐟 🐙 | kala | fish\n"));
+    let problems = generate("[letters]\na a\no o\nl o\n[words]\nala #F1902\naoa #F1903\naoa=aoa #F1904\nb -\n[ku]\n").unwrap_err();
+    assert_eq!(problems, vec![
+        "line 7: aoa is spelled aoa like ala, give it a spelling of its own like `aoa=aoa`",
+        "line 9: unknown letter 'b' in b",
+        "line 10: unknown tier [ku]",
+    ]);
+}
@@ -0,0 +1,108 @@
+use super::schema::{Candidate::{self, *}, Cursor, Schema, WordId};
+
+//----------------------------------------------------------------------------
+//
+//  The prefixes found in the spelling, from every byte of it, which both the
+//  single words and the sentences are suggested from. It's kept between
+//  keystrokes by the composition, so that only what's after the edit gets
+//  walked down the trie again, a byte at a time.
+//
+//----------------------------------------------------------------------------
+
+/// The length of a match and its word.
+type Found = Option<(usize, WordId)>;
+
+/// The walk down the trie from a byte of the spelling.
+#[derive(Default, Clone)]
+struct Walk {
+    /// Where the walk is after each byte. It stops where no prefix goes on.
+    steps: Vec<Cursor>,
+}
+
+/// Suggestion state kept between keystrokes. See `Engine::suggest_with`.
+#[derive(Default)]
+pub struct SuggestState {
+    /// The schema walked, by name.
+    schema: String,
+    spelling: String,
+    walks: Vec<Walk>,
+}
+
+impl SuggestState {
+    /// Walk the spelling, reusing the walks over the bytes it starts with in common with the last one.
+    pub(super) fn update(&mut self, name: &str, schema: &Schema, spelling: &str) {
+        if self.schema != name {
+            self.schema = name.to_string();
+            self.spelling.clear();
+            self.walks.clear();
+        }
+        let common = self.spelling.bytes().zip(spelling.bytes()).take_while(|(a, b)| a == b).count();
+        self.spelling.truncate(common);
+        self.walks.truncate(common);
+        for (start, walk) in self.walks.iter_mut().enumerate() {
+            walk.steps.truncate(common - start);
+        }
+        for (end, byte) in spelling.bytes().enumerate().skip(common) {
+            self.spelling.push(byte as char);
+            self.walks.push(Walk::default());
+            for (start, walk) in self.walks.iter_mut().enumerate() {
+                // stopped already
+                if walk.steps.len() != end - start {
+                    continue;
+                }
+                let root = schema.root();
+                if let Some(cursor) = schema.step(walk.steps.last().unwrap_or(&root), byte) {
+                    walk.steps.push(cursor);
+                }
+            }
+        }
+    }
+
+    /// The prefixes from the start and their lengths, the longest first.
+    pub(super) fn prefixes<'a>(&'a self, schema: &'a Schema, start: usize) -> impl Iterator<Item = (usize, Candidate<'a>)> {
+        let steps = self.walks.get(start).map_or(&[][..], |walk| &walk.steps);
+        steps.iter().enumerate().rev()
+            .filter_map(|(index, cursor)| schema.candidate_at(cursor).map(|candi| (index + 1, candi)))
+    }
+
+    /// The longest exact match from the start and the longest unique match,
+    /// which is ignored unless it's longer than the exact one. Only the words
+    /// that are suggested match, see `Engine::suggested`.
+    pub(super) fn matches(&self, schema: &Schema, start: usize, suggested: impl Fn(WordId) -> bool) -> (Found, Found) {
+        let mut unique = None;
+        for (len, candi) in self.prefixes(schema, start) {
+            match candi {
+                Exact(word, _) if suggested(word) => return (Some((len, word)), unique),
+                Unique(word) if unique.is_none() && suggested(word) => unique = Some((len, word)),
+                _ => (),
+            }
+        }
+        (None, unique)
+    }
+
+    pub fn spelling(&self) -> &str {
+        &self.spelling
+    }
+}
+
+#[test]
+fn test() {
+    let schema = Schema::compile("aoa ❌\naoaea 🏹\nea 🔥\n");
+    let mut state = SuggestState::default();
+    let lens = |state: &SuggestState, start| state.prefixes(&schema, start).map(|(len, _)| len).collect::<Vec<_>>();
+    state.update("test", &schema, "aoae");
+    assert_eq!(lens(&state, 0), vec![4, 3, 2, 1]);
+    assert_eq!(lens(&state, 3), vec![1]);
+    state.update("test", &schema, "aoa");
+    assert_eq!(lens(&state, 0), vec![3, 2, 1]);
+    state.update("test", &schema, "aoaea");
+    assert_eq!(lens(&state, 0), vec![5, 4, 3, 2, 1]);
+    assert_eq!(lens(&state, 3), vec![2, 1]);
+    let (exact, unique) = state.matches(&schema, 0, |_| true);
+    assert_eq!((exact.map(|it| it.0), unique.map(|it| it.0)), (Some(5), None));
+    // edited in the middle
+    state.update("test", &schema, "eaoa");
+    assert_eq!(lens(&state, 0), vec![2, 1]);
+    assert_eq!(lens(&state, 1), vec![3, 2, 1]);
+    assert_eq!(state.spelling(), "eaoa");
+}
@@ -1,5 +1,3 @@
-use crate::conf::{LONG_GLYPH, LONG_PI};
-
 const ALA: char = '󱤂';
 const AWEN: char = '󱤈';
 const KEN: char = '󱤘';
@@ -11,12 +9,16 @@ const LA: char = '󱤡';
 const KAMA: char = '󱤖';
 
 
-const START_OF_LONG_GLYGH: char = '󱦗';
-const END_OF_LONG_GLYPH: char = '󱦘';
-const START_OF_REVERSE_LONG_GLYGH: char = '󱦚';
-const END_OF_REVERSE_LONG_GLYPH: char = '󱦛';
+pub(super) const START_OF_LONG_GLYGH: char = '󱦗';
+pub(super) const END_OF_LONG_GLYPH: char = '󱦘';
+pub(super) const START_OF_REVERSE_LONG_GLYGH: char = '󱦚';
+pub(super) const END_OF_REVERSE_LONG_GLYPH: char = '󱦛';
 
-pub(super) fn insert_long_glyph(text: &mut String) {
+/// Insert the long glyphs of pi if `long_pi`, and of the others if `long_glyph`.
+pub(crate) fn insert_long_glyph_with(text: &mut String, long_pi: bool, long_glyph: bool) {
+    let ext_as_ala = |ch| ext_as_ala(ch, long_glyph);
+    let ext_left = |ch| ext_left(ch, long_pi, long_glyph);
+    let ext_right = |ch| ext_right(ch, long_glyph);
     let mut output = String::new();
     let mut open = false;
     let mut general_question = None;
@@ -132,32 +134,26 @@ pub(super) fn insert_long_glyph(text: &mut String) {
 }
 
 
-fn ext_as_ala(ch: char) -> bool {
-    unsafe {
-        ch == ALA && LONG_GLYPH
-    }
+fn ext_as_ala(ch: char, long_glyph: bool) -> bool {
+    ch == ALA && long_glyph
 }
 
 
-fn ext_left(ch: char) -> bool {
-    unsafe {
-        match ch {
-            PI => LONG_PI,
-            AWEN|KEN|KEPEKEN|LON|TAWA => LONG_GLYPH,
-            _ => false
-        }
+fn ext_left(ch: char, long_pi: bool, long_glyph: bool) -> bool {
+    match ch {
+        PI => long_pi,
+        AWEN|KEN|KEPEKEN|LON|TAWA => long_glyph,
+        _ => false
     }
 }
 
 #[allow(unused)]
-fn ext_right(ch: char) -> bool {
-    unsafe {
-        match ch {
-            // KAMA is disabled for now because i don't want to handle "tenpo kama la"
-            LA => LONG_GLYPH,
-            KAMA => false,
-            _ => false
-        }
+fn ext_right(ch: char, long_glyph: bool) -> bool {
+    match ch {
+        // KAMA is disabled for now because i don't want to handle "tenpo kama la"
+        LA => long_glyph,
+        KAMA => false,
+        _ => false
     }
 }
 
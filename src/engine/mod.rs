@@ -1,131 +1,266 @@
 mod long_glyph;
+mod lattice;
 mod sentence;
 mod schema;
-use std::collections::VecDeque;
-use std::{cell::OnceCell, collections::HashSet};
-use self::schema::Schema;
+mod chord;
+mod compile;
+mod generate;
+use std::{cmp::Reverse, collections::HashSet, fs, path::PathBuf, sync::OnceLock};
+use log::warn;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use self::compile::Condition;
+use self::schema::{PunctRule, Schema, WordId};
 use self::schema::Candidate::*;
+pub use self::chord::{ChordOverrides, ChordResolver, Stroke};
+pub use self::lattice::SuggestState;
+pub use self::compile::{compile, TIER_NAMES};
+pub use self::generate::generate;
+pub(crate) use self::long_glyph::insert_long_glyph_with;
 use crate::{EMOJI_SCHEMA, SITELEN_SCHEMA};
-use crate::{conf::CJK_SPACE, CANDI_NUM};
+use crate::{conf::{self, conf, Config, Spacing}, extend::CharExt, pairs::Pairs, CANDI_NUM, CANDI_PAGES};
 
 /// Suggestions from engine
 #[derive(Default, Clone)]
 pub struct Suggestion {
     pub output: String,
     pub groupping: Vec<usize>,
+    /// Only single words are annotated.
+    pub annotation: Option<Annotation>,
 }
 
+/// Shown alongside a candidate, each if turned on in [layout].
+#[derive(Default, Clone)]
+pub struct Annotation {
+    pub latin: String,
+    pub gloss: String,
+    /// The code typed so far and the rest of it, like `("aoa", "ea")`.
+    pub code: (String, String),
+    /// The name of the tier, or empty if it's in none.
+    pub tier: &'static str,
+}
+
+/// A punctuator remapped by the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remapped {
+    pub output: String,
+    /// What the first tap output, which this double tap replaces.
+    pub replaces: Option<String>,
+}
+
+/// Names of the built-in schemas, which are used in conf.toml.
+pub const SCHEMA_NAMES: [&str; 2] = ["sitelen", "emoji"];
+
 /// Engine. A struct to store and query words and punctuators
 pub struct Engine {
-    schemas: VecDeque<Schema>,
-    squote_open: bool,
-    dquote_open: bool,
+    schemas: Vec<(String, Schema)>,
+    current: usize,
 }
 
 impl Engine {
-    fn new() -> Engine {
-        Engine {
-            schemas: VecDeque::from([Schema::from(SITELEN_SCHEMA), Schema::from(EMOJI_SCHEMA)]),
-            squote_open: false,
-            dquote_open: false
+    /// The built-in schemas and the ones compiled by users, which replace the built-in ones of the same name.
+    pub(crate) fn new(order: &[String]) -> Engine {
+        let mut schemas: Vec<(String, Schema)> = SCHEMA_NAMES.into_iter().zip([SITELEN_SCHEMA, EMOJI_SCHEMA])
+            .map(|(name, bytes)| (name.to_string(), Schema::new(bytes).unwrap()))
+            .collect();
+        for (name, schema) in user_schemas() {
+            match schemas.iter_mut().find(|(it, _)| *it == name) {
+                Some(built_in) => built_in.1 = schema,
+                None => schemas.push((name, schema)),
+            }
         }
+        let mut engine = Engine { schemas, current: 0 };
+        engine.order_schemas(order);
+        engine
     }
 
     fn schema(&self) -> &Schema {
-        self.schemas.front().unwrap()
+        &self.schemas[self.current].1
+    }
+
+    /// Put the schemas in the order, the ones not mentioned after the others, the built-in ones
+    /// first and the ones of users by name. The first one is selected.
+    pub fn order_schemas(&mut self, order: &[String]) {
+        let rank = |name: &str| (
+            order.iter().position(|it| it == name).unwrap_or(order.len()),
+            SCHEMA_NAMES.iter().position(|it| *it == name).unwrap_or(SCHEMA_NAMES.len()));
+        self.schemas.sort_by(|(a, _), (b, _)| rank(a).cmp(&rank(b)).then_with(|| a.cmp(b)));
+        self.select_schema(0);
+    }
+
+    /// The names of the schemas in order.
+    pub fn schema_names(&self) -> Vec<&str> {
+        self.schemas.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn current_schema(&self) -> usize {
+        self.current
+    }
+
+    /// What separates words in the current schema.
+    pub fn spacing(&self, conf: &Config) -> Spacing {
+        conf.spacing.of(&self.schemas[self.current].0)
     }
 
     pub fn next_schema(&mut self) {
-        self.schemas.rotate_left(1);
-        self.squote_open = false;
-        self.dquote_open = false;
+        self.select_schema((self.current + 1) % self.schemas.len());
+    }
+
+    pub fn previous_schema(&mut self) {
+        self.select_schema((self.current + self.schemas.len() - 1) % self.schemas.len());
     }
 
-    pub fn remap_punct(&mut self, punct: char) -> char {
-        match punct {
+    /// Does nothing if there's no such schema.
+    pub fn select_schema(&mut self, index: usize) {
+        if index >= self.schemas.len() {
+            return;
+        }
+        self.current = index;
+    }
+
+    /// Remap the punctuator by the first rule of the schema that applies. `after` is the char
+    /// before the caret, if it's known. Quotes are remapped to the opening or the closing one,
+    /// as the pairs of the document go. A space remapped to U+3000 is remapped to what
+    /// separates words instead, unless that's nothing.
+    pub fn remap_punct(&self, punct: char, pairs: &mut Pairs, composing: bool, after: Option<char>, spacing: Spacing) -> Remapped {
+        let repeated = pairs.last.take().filter(|(key, _)| *key == punct).map(|(_, output)| output);
+        let applies = |rule: &PunctRule| (!rule.twice || repeated.is_some()) && match rule.condition {
+            Condition::Always => true,
+            Condition::Composing => composing,
+            Condition::Idle => !composing,
+            Condition::After(ch) => after == Some(ch),
+        };
+        let remapped = match punct {
             '\'' => {
-                let remmaped = match self.squote_open {
+                let remmaped = match pairs.squote_open {
                     false => self.schema().squote.0,
                     true =>  self.schema().squote.1
                 };
-                self.squote_open = !self.squote_open;
-                remmaped
+                pairs.squote_open = !pairs.squote_open;
+                remmaped.to_string()
             }
             '"' => {
-                let remmaped = match self.dquote_open {
+                let remmaped = match pairs.dquote_open {
                     false => self.schema().dquote.0,
                     true =>  self.schema().dquote.1
                 };
-                self.dquote_open = !self.dquote_open;
-                remmaped
+                pairs.dquote_open = !pairs.dquote_open;
+                remmaped.to_string()
             }
-            punct => self.schema().puncts
-                .get(&punct)
-                .cloned()
-                .filter(|it| *it != '\u{3000}' || unsafe { !CJK_SPACE } )
-                .unwrap_or(punct)
-        }
+            punct => match self.schema().punct_rules(punct).find(applies) {
+                // the next tap is a single one again
+                Some(rule) if rule.twice => return Remapped { output: rule.output.to_string(), replaces: repeated },
+                Some(rule) if rule.output == "\u{3000}" && spacing != Spacing::None => spacing.separator().to_string(),
+                Some(rule) => rule.output.to_string(),
+                _ => punct.to_string(),
+            }
+        };
+        pairs.last = Some((punct, remapped.clone()));
+        Remapped { output: remapped, replaces: None }
+    }
+
+    /// If the punctuator is remapped to a joiner, which joins words in the spelling.
+    pub fn is_joiner(&self, punct: char) -> bool {
+        self.schema().punct(punct).is_some_and(|ch| ch.is_joiner())
     }
 
-    pub fn suggest(&self, spelling: &str) -> Vec<Suggestion> {
+    /// Suggest from scratch, for tests.
+    #[cfg(test)]
+    pub fn suggest(&self, spelling: &str, conf: &Config) -> Vec<Suggestion> {
+        self.suggest_with(&mut SuggestState::default(), spelling, conf)
+    }
+
+    /// Suggest with the state kept from the last spelling, so that only
+    /// what's changed since is looked up again. The words are picked and ranked by
+    /// `[behavior] latin_spelling` and the tiers of `[schema]` in the config.
+    pub fn suggest_with(&self, state: &mut SuggestState, spelling: &str, conf: &Config) -> Vec<Suggestion> {
         if !spelling.is_ascii() {
             return Vec::new(); 
         }
-        let mut suggs = Vec::with_capacity(CANDI_NUM);
+        self.walk(state, spelling, conf);
+        let mut suggs = Vec::with_capacity(CANDI_NUM * CANDI_PAGES);
         // suggest a sentence
-        if let Some(sugg) = self.suggest_sentence(spelling) {
+        if let Some(sugg) = self.suggest_sentence(state, conf) {
             suggs.push(sugg);
         }
         // suggest single words
-        let mut remains = CANDI_NUM - suggs.len();
+        let mut remains = CANDI_NUM * CANDI_PAGES - suggs.len();
         let mut exclude: HashSet<&str> = HashSet::new();
-        'outer_loop:
-        for to in (1..=spelling.len()).rev() {
+        let schema = self.schema();
+        // the words from the longest prefix first, each as long as it is less the penalty of its tier
+        let mut ranked: Vec<(usize, WordId)> = Vec::new();
+        for (to, candi) in state.prefixes(schema, 0) {
+            let slice = &spelling[0..to];
+            let mut words: Vec<WordId> = match candi {
+                Exact(word, words) => 
+                    Some(word).into_iter().chain(words).collect(),
+                Unique(word) => 
+                    vec![word],
+                Duplicates(words) => 
+                    words.collect(),
+            };
+            // a word whose Latin name is typed in full goes before the others spelled alike
+            if conf.behavior.latin_spelling {
+                words.sort_by_key(|word| !schema.note(*word).is_some_and(|note| note.latin == slice));
+            }
+            ranked.extend(words.into_iter().filter(|word| self.suggested(*word, conf)).map(|word| (to, word)));
+        }
+        ranked.sort_by_key(|(to, word)| Reverse(to.saturating_sub(self.penalty(*word, conf))));
+        for (to, word) in ranked {
             let slice = &spelling[0..to];
-            let words: Box<dyn Iterator<Item = &String>> = match self.schema().candis.get(slice) {
-                Some(Exact(word, words)) => 
-                    Box::new(Some(word).into_iter().chain(words.iter())),
-                Some(Unique(word)) => 
-                    Box::new(Some(word).into_iter()),
-                Some(Duplicates(words)) => 
-                    Box::new(words.iter()),
-                None => {
+            for word in Some(word).into_iter().chain(schema.alters(word)) {
+                let text = schema.word(word);
+                if exclude.contains(text) {
                     continue;
                 }
-            };
-            for word in words {
-                let words: Box<dyn Iterator<Item = &String>> = if let Some(alters) = self.schema().alters.get(word) {
-                    Box::new(Some(word).into_iter().chain(alters.iter()))
-                } else {
-                    Box::new(Some(word).into_iter())
-                };
-                for word in words {
-                    if exclude.contains(word.as_str()) {
-                        continue;
-                    }
-                    exclude.insert(word);
-                    // append the trailing joiner(s) to the suggestion
-                    let mut output = word.clone();
-                    let mut to = to;
-                    let bytes = spelling.as_bytes();
-                    for i in to..spelling.len() {
-                        if let Some(joiner) = char::try_from(bytes[i]).ok().and_then(|char|self.schema().puncts.get(&char)).cloned() {
-                            output.push(joiner);
-                            to += 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    suggs.push(Suggestion{ output, groupping: vec![to] });
-                    remains -= 1;
-                    if remains <= 0 {
-                        break 'outer_loop;
+                exclude.insert(text);
+                // append the trailing joiner(s) to the suggestion
+                let mut output = text.to_string();
+                let mut to = to;
+                let bytes = spelling.as_bytes();
+                for i in to..spelling.len() {
+                    if let Some(joiner) = char::try_from(bytes[i]).ok().and_then(|char|schema.punct(char)) {
+                        output.push(joiner);
+                        to += 1;
+                    } else {
+                        break;
                     }
                 }
+                let annotation = schema.note(word).map(|note| Annotation {
+                    latin: note.latin.to_string(),
+                    gloss: note.gloss.to_string(),
+                    code: (slice.to_string(), note.spelling.get(slice.len()..).unwrap_or_default().to_string()),
+                    tier: note.tier.map_or("", |tier| TIER_NAMES[tier]),
+                });
+                suggs.push(Suggestion{ output, groupping: vec![to], annotation });
+                remains -= 1;
+                if remains <= 0 {
+                    return suggs;
+                }
             }
         }
         suggs
     }
+
+    /// If the word is in a tier turned on in [schema], or in none.
+    fn suggested(&self, word: WordId, conf: &Config) -> bool {
+        match self.schema().note(word).and_then(|note| note.tier) {
+            Some(tier) => conf.schema.tiers[tier],
+            None => true,
+        }
+    }
+
+    /// How many letters shorter than it's typed the word ranks as, by its tier.
+    fn penalty(&self, word: WordId, conf: &Config) -> usize {
+        self.schema().note(word).and_then(|note| note.tier).map_or(0, |tier| conf.schema.penalty.penalties[tier] as usize)
+    }
+
+    fn walk(&self, state: &mut SuggestState, spelling: &str, conf: &Config) {
+        let (name, schema) = &self.schemas[self.current];
+        match conf.behavior.latin_spelling {
+            true => state.update(name, schema, &spelling.chars().map(|ch| schema.fold(ch)).collect::<String>()),
+            false => state.update(name, schema, spelling),
+        }
+    }
 }
 
 
@@ -135,16 +270,122 @@ impl Engine {
 //
 //----------------------------------------------------------------------------
 
-static mut ENGINE: OnceCell<Engine> = OnceCell::new();
+static ENGINE: OnceLock<RwLock<Engine>> = OnceLock::new();
+
+fn lock() -> &'static RwLock<Engine> {
+    ENGINE.get_or_init(|| RwLock::new(Engine::new(&conf().schema.order)))
+}
+
+/// The engine in use. Don't keep it while calling `engine_mut`.
+pub fn engine() -> RwLockReadGuard<'static, Engine> {
+    lock().read_recursive()
+}
 
-pub fn engine() -> &'static mut Engine {
-    unsafe {ENGINE.get_mut().unwrap()}
+pub fn engine_mut() -> RwLockWriteGuard<'static, Engine> {
+    lock().write()
 }
 
 pub fn setup() {
-    unsafe { 
-        ENGINE.get_or_init(Engine::new);
+    lock();
+}
+
+/// Where the schemas compiled by `ajemi-cli compile` go, %APPDATA%\Ajemi\schemas.
+/// A schema is named after its file, like "custom" for schemas\custom.bin.
+pub fn user_schema_dir() -> anyhow::Result<PathBuf> {
+    Ok(conf::dir()?.join("schemas"))
+}
+
+/// The names of the schemas compiled by users, sorted.
+pub fn user_schema_names() -> Vec<String> {
+    let Ok(entries) = user_schema_dir().and_then(|dir| Ok(fs::read_dir(dir)?)) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            match path.extension()?.to_str()? {
+                "bin" => Some(path.file_stem()?.to_str()?.to_string()),
+                _ => None,
+            }
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+/// If the schema is a built-in one or compiled by the user.
+pub fn schema_exists(name: &str) -> bool {
+    SCHEMA_NAMES.contains(&name) || user_schema_names().iter().any(|it| it == name)
+}
+
+/// Load the schemas compiled by users. The ones that fail the check are left out.
+fn user_schemas() -> Vec<(String, Schema)> {
+    let Ok(dir) = user_schema_dir() else {
+        return Vec::new();
+    };
+    user_schema_names().into_iter()
+        .filter_map(|name| {
+            let path = dir.join(format!("{name}.bin"));
+            match fs::read(&path).map_err(|err| err.to_string()).and_then(Schema::load) {
+                Ok(schema) => Some((name, schema)),
+                Err(err) => {
+                    warn!("Failed to load {}. {err}", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test() {
+    let engine = Engine {
+        schemas: vec![("test".to_string(), Schema::compile(". 󱦜\n.. 󱦝\n. 󱦜#3000 after:󱤀\n, 、 idle\n"))],
+        current: 0,
+    };
+    let mut pairs = Pairs::default();
+    let mut remap = |punct, composing, after| engine.remap_punct(punct, &mut pairs, composing, after, Spacing::None);
+    assert_eq!(remap('.', true, Some('󱤀')), Remapped { output: "󱦜\u{3000}".to_string(), replaces: None });
+    assert_eq!(remap('.', false, None), Remapped { output: "󱦝".to_string(), replaces: Some("󱦜\u{3000}".to_string()) });
+    assert_eq!(remap('.', false, None).output, "󱦜");
+    assert_eq!(remap(',', true, None).output, ",");
+    assert_eq!(remap(',', false, None).output, "、");
+    assert_eq!(pairs.last, Some((',', "、".to_string())));
+    // a key in between is no double tap
+    engine.remap_punct('.', &mut pairs, false, None, Spacing::None);
+    pairs.pass_key();
+    assert_eq!(engine.remap_punct('.', &mut pairs, false, None, Spacing::None), Remapped { output: "󱦜".to_string(), replaces: None });
+    let engine = Engine { schemas: vec![("test".to_string(), Schema::compile("space #3000\n"))], current: 0 };
+    assert_eq!(engine.remap_punct(' ', &mut pairs, false, None, Spacing::None).output, "\u{3000}");
+    assert_eq!(engine.remap_punct(' ', &mut pairs, false, None, Spacing::Ascii).output, " ");
+
+    let engine = Engine {
+        schemas: vec![("test".to_string(), Schema::compile("a=a i=i o=o k=u t=u\nuu 󱥮 | tu\nuu 󱦈 | ku\nuoui 󱥬 | toki\n"))],
+        current: 0,
+    };
+    let mut conf = Config::default();
+    let outputs = |spelling, conf: &Config| engine.suggest(spelling, conf).into_iter().map(|sugg| sugg.output).collect::<Vec<_>>();
+    assert!(outputs("toki", &conf).is_empty());
+    conf.behavior.latin_spelling = true;
+    assert_eq!(outputs("toki", &conf)[0], "󱥬");
+    assert_eq!(outputs("uoui", &conf)[0], "󱥬");
+    assert_eq!(outputs("ku", &conf)[..2], ["󱦈", "󱥮"]);
+    assert_eq!(outputs("tu", &conf)[..2], ["󱥮", "󱦈"]);
+
+    // ku suli words rank a letter shorter, and go when the tier is turned off
+    let engine = Engine {
+        schemas: vec![("test".to_string(), Schema::compile("[ku suli]\nuua 󱦈\n[pu]\nuu 󱥮\nuuoe 󱤞\n"))],
+        current: 0,
     };
+    let outputs = |spelling, conf: &Config| engine.suggest(spelling, conf).into_iter().map(|sugg| sugg.output).collect::<Vec<_>>();
+    let mut conf = Config::default();
+    conf.schema.penalty.penalties = [0; 4];
+    assert_eq!(outputs("uu", &conf), ["󱥮", "󱦈", "󱤞"]);
+    conf.schema.penalty.penalties = [0, 1, 0, 0];
+    assert_eq!(outputs("uu", &conf), ["󱥮", "󱤞", "󱦈"]);
+    assert_eq!(outputs("uua", &conf)[0], "󱦈");
+    conf.schema.tiers = [true, false, true, true];
+    assert_eq!(outputs("uu", &conf), ["󱥮", "󱤞"]);
 }
 
 #[test]
@@ -155,7 +396,7 @@ fn repl() {
     loop {
         buf.clear();
         stdin().read_line(&mut buf).unwrap();
-        let suggs = engine().suggest(&buf);
+        let suggs = engine().suggest(&buf, &conf());
         for sugg in suggs {
             println!("{}", sugg.output);
         }
@@ -1,167 +1,365 @@
-use std::collections::HashMap;
-use log::error;
-use Candidate::*;
+use std::{borrow::Cow, cmp::Ordering, ops::Range};
+use super::compile::*;
+
+//----------------------------------------------------------------------------
+//
+//  Schemas compiled by compile.rs, read in place. The built-in ones are
+//  compiled into the binary, thus nothing is parsed or copied when loaded.
+//
+//----------------------------------------------------------------------------
+
+/// The index of a word in the schema.
+pub type WordId = u32;
 
 /// To expain why a certain spelling is mapped to certain word(s)
-#[derive(Debug)]
-pub enum Candidate {
+pub enum Candidate<'a> {
     /// The spelling is an exact spelling of a certain word.
     /// Meanwhile it can also be a prefix of other words.
     /// For example, `"li"` is `Exact("li", ["lili", "linja", "lipu"])`.
-    Exact(String, Vec<String>),
+    Exact(WordId, Ids<'a>),
     /// The spelling is unique prefix for a certain word. No other words starts with it.
     /// For example, `"kije"` is `Unique("kijetesantakalu")`.
-    Unique(String),
+    Unique(WordId),
     /// The spelling is not an exact spelling or a unique prefix.
     /// For example, `"an"` is `Duplicates(["anpa", "ante", "anu"])`.
-    Duplicates(Vec<String>)
+    Duplicates(Ids<'a>)
+}
+
+/// The code, the Latin name and the meaning of a word, from `spelling word | latin | gloss`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Note<'a> {
+    pub spelling: &'a str,
+    pub latin: &'a str,
+    pub gloss: &'a str,
+    /// The index in `TIER_NAMES`.
+    pub tier: Option<usize>,
+}
+
+/// A rule that remaps a punctuator, see `Source::parse` in compile.rs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PunctRule<'a> {
+    /// If it's for a double tap, whose output replaces that of the first tap.
+    pub twice: bool,
+    pub condition: Condition,
+    pub output: &'a str,
 }
 
 /// Stores the dictionary and the remapped punctuators.
-/// The dicitonary is indexed in a special way.
-#[derive(Debug)]
 pub struct Schema {
-    pub candis: HashMap<String, Candidate>,
-    pub alters: HashMap<String, Vec<String>>,
-    pub puncts: HashMap<char, char>,
+    bytes: Cow<'static, [u8]>,
+    /// The offsets and the counts of the sections.
+    sections: [(usize, usize); SECTIONS],
     pub squote: (char, char),
     pub dquote: (char, char),
 }
 
-//----------------------------------------------------------------------------
-//
-//  Load schemas from files.
-//
-//----------------------------------------------------------------------------
+/// A node of the trie of prefixes, which are the records of the prefixes starting with
+/// the same bytes. They are next to each other since the prefixes are sorted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    range: Range<usize>,
+    depth: usize,
+}
+
+/// Word ids in a range of the IDS section.
+pub struct Ids<'a> {
+    schema: &'a Schema,
+    range: Range<usize>,
+}
+
+impl Iterator for Ids<'_> {
+    type Item = WordId;
 
-#[derive(Clone, Copy)]
-enum Atom<'a> {
-    Text(&'a str),
-    Punct(char)
+    fn next(&mut self) -> Option<WordId> {
+        let index = self.range.next()?;
+        Some(self.schema.field(IDS, index, 0))
+    }
 }
 
-impl<'a> From<&'a str> for Atom<'a> {
-    fn from(str: &'a str) -> Atom<'a> {
-        use Atom::*;
-        let mut chars = str.chars();
-        let first_ch = chars.nth(0).unwrap();
-        if !first_ch.is_alphanumeric() && chars.nth(1).is_none() {
-            Punct(first_ch)
-        } else if first_ch == '#' {
-            match u32::from_str_radix(&str[1..], 16).ok().and_then(|codepoint|char::from_u32(codepoint)) {
-                Some(punct) => Punct(punct),
-                None => Text(str)
+impl Schema {
+    /// Check the header. The rest is trusted.
+    pub fn new(bytes: impl Into<Cow<'static, [u8]>>) -> Result<Schema, String> {
+        let bytes = bytes.into();
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err("not a compiled schema".to_string());
+        }
+        let int = |index: usize| u32::from_le_bytes(bytes[4 + index * 4..][..4].try_into().unwrap());
+        if int(0) != VERSION {
+            return Err(format!("compiled with version {}, expected {VERSION}", int(0)));
+        }
+        let char_at = |index: usize| char::from_u32(int(index)).unwrap_or_default();
+        let mut sections = [(0, 0); SECTIONS];
+        for (section, (offset, count)) in sections.iter_mut().enumerate() {
+            *offset = int(5 + section * 2) as usize;
+            *count = int(6 + section * 2) as usize;
+            let len = if section == STRINGS { *count as u64 } else { *count as u64 * (RECORD_LENS[section] * 4) as u64 };
+            if *offset as u64 + len > bytes.len() as u64 {
+                return Err("truncated".to_string());
             }
-        } else if str == "space" {
-            Punct(' ')
-        } else {
-            Text(str)
         }
+        Ok(Schema {
+            squote: (char_at(1), char_at(2)),
+            dquote: (char_at(3), char_at(4)),
+            bytes,
+            sections,
+        })
     }
-}
 
-impl<'a> ToString for Atom<'a> {
-    fn to_string(&self) -> String {
-        use Atom::*;
-        match self {
-            Text(text) => text.to_string(),
-            Punct(punct) => {
-                let mut string = String::with_capacity(4);
-                string.push(*punct);
-                string
+    /// Check the header and everything referenced, for schemas compiled by users,
+    /// so that a broken file is refused rather than read out of bounds.
+    pub fn load(bytes: Vec<u8>) -> Result<Schema, String> {
+        let schema = Schema::new(bytes)?;
+        schema.check()?;
+        Ok(schema)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let count = |section: usize| self.sections[section].1 as u64;
+        let field = |section, index, at| self.field(section, index, at) as u64;
+        // a range referenced by the field and the next one
+        let within = |section, index, at: usize, target| field(section, index, at) + field(section, index, at + 1) <= count(target);
+        let word = |section, index, at| field(section, index, at) < count(WORDS);
+        let valid = |section: usize, index: usize| match section {
+            WORDS => [0, 4, 6, 8].into_iter().all(|at| within(WORDS, index, at, STRINGS))
+                && within(WORDS, index, 2, IDS)
+                && field(WORDS, index, 10) <= TIER_NAMES.len() as u64,
+            PREFIXES => within(PREFIXES, index, 0, STRINGS)
+                && within(PREFIXES, index, 4, IDS)
+                && (field(PREFIXES, index, 2) == DUPLICATES as u64 || word(PREFIXES, index, 3)),
+            CHORDS => within(CHORDS, index, 0, STRINGS) && within(CHORDS, index, 2, CHORD_ENTRIES),
+            CHORD_ENTRIES => within(CHORD_ENTRIES, index, 0, STRINGS) && word(CHORD_ENTRIES, index, 2),
+            PUNCTS => within(PUNCTS, index, 4, STRINGS),
+            IDS => word(IDS, index, 0),
+            _ => true,
+        };
+        for section in 0..SECTIONS {
+            if let Some(index) = (0..self.sections[section].1).find(|index| !valid(section, *index)) {
+                return Err(format!("record {index} of section {section} is out of bounds"));
             }
         }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn candidate(&self, spelling: &str) -> Option<Candidate<'_>> {
+        let mut cursor = self.root();
+        for byte in spelling.bytes() {
+            cursor = self.step(&cursor, byte)?;
+        }
+        self.candidate_at(&cursor)
+    }
+
+    /// Compile the text. Panics on mistakes, for tests.
+    #[cfg(test)]
+    pub fn compile(text: &str) -> Schema {
+        Schema::new(compile(text).unwrap()).unwrap()
+    }
+
+    /// The field of the record, by the index in u32.
+    fn field(&self, section: usize, index: usize, field: usize) -> u32 {
+        let offset = self.sections[section].0 + (index * RECORD_LENS[section] + field) * 4;
+        u32::from_le_bytes(self.bytes[offset..][..4].try_into().unwrap())
+    }
+
+    /// The string referenced by the field and the next one.
+    fn string(&self, section: usize, index: usize, field: usize) -> &str {
+        let offset = self.sections[STRINGS].0 + self.field(section, index, field) as usize;
+        let len = self.field(section, index, field + 1) as usize;
+        std::str::from_utf8(&self.bytes[offset..][..len]).unwrap_or_default()
+    }
+
+    /// The ids referenced by the field and the next one.
+    fn ids(&self, section: usize, index: usize, field: usize) -> Ids<'_> {
+        let start = self.field(section, index, field) as usize;
+        let len = self.field(section, index, field + 1) as usize;
+        Ids { schema: self, range: start..start + len }
     }
-}
 
-impl From<&str> for Schema {
-    fn from(value: &str) -> Schema {
-        use Atom::*;
-        let mut candis = HashMap::new();
-        let mut alters = HashMap::new();
-        let mut puncts = HashMap::new();
-        let mut squote = ('\'', '\'');
-        let mut dquote = ('"', '"');
-
-        let mut atoms = Vec::new();
-        for list in value.lines() {
-            if list.is_empty() || list.starts_with("//") {
-                continue;
+    /// Binary search the section, whose records are sorted by the key.
+    fn search(&self, section: usize, key: impl Fn(usize) -> Ordering) -> Option<usize> {
+        let (mut low, mut high) = (0, self.sections[section].1);
+        while low < high {
+            let middle = (low + high) / 2;
+            match key(middle) {
+                Ordering::Less => low = middle + 1,
+                Ordering::Greater => high = middle,
+                Ordering::Equal => return Some(middle),
             }
-            atoms.clear();
-            atoms.extend(list.split(char::is_whitespace).filter(|str|!str.is_empty()).map(Atom::from));
-            match atoms[..] {
-                [Punct('\''), Punct(open), Punct(close)] => {
-                    squote = (open, close);
-                }
-                [Punct('"'), Punct(open), Punct(close)] => {
-                    dquote = (open, close);
-                }
-                [Punct('\''), Punct(dumb)] => {
-                    squote = (dumb, dumb);
-                }
-                [Punct('"'), Punct(dumb)] => {
-                    dquote = (dumb, dumb);
-                }
-                [Punct(punct), Punct(remapped)] => {
-                    puncts.insert(punct, remapped);
-                }
-                [Text(spelling), word, .. ] => {
-                    // store exact spelling -> word
-                    candis.insert(spelling.to_string(), Exact(word.to_string(), Vec::new()));
-                    // store prefixes -> word
-                    for len in 1..spelling.len() {
-                        let prefix = &spelling[0..len];
-                        match candis.get_mut(prefix) {
-                            None => {
-                                candis.insert(prefix.to_string(), Unique(word.to_string()));
-                            },
-                            Some(Unique(unique)) => {
-                                let mut duplicates = Vec::new();
-                                duplicates.push(unique.clone());
-                                duplicates.push(word.to_string());
-                                candis.insert(prefix.to_string(), Duplicates(duplicates));
-                            },
-                            Some(Duplicates(duplicates)) | Some(Exact(_, duplicates)) => {
-                                duplicates.push(word.to_string());
-                            }
-                        }
-                    }
-                    // store word -> alternatives
-                    let word = word.to_string();
-                    for alter in atoms.iter().skip(2) {
-                        match alters.get_mut(&word) {
-                            None => { 
-                                alters.insert(word.clone(), vec![alter.to_string()]); 
-                            }
-                            Some(alters) => { 
-                                alters.push(alter.to_string()); 
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    error!("Unrecogniable pattern: {list}");
+        }
+        None
+    }
+
+    pub fn word(&self, id: WordId) -> &str {
+        self.string(WORDS, id as usize, 0)
+    }
+
+    #[cfg(test)]
+    pub fn word_id(&self, word: &str) -> Option<WordId> {
+        self.search(WORDS, |index| self.string(WORDS, index, 0).cmp(word))
+            .map(|index| index as WordId)
+    }
+
+    pub fn alters(&self, id: WordId) -> Ids<'_> {
+        self.ids(WORDS, id as usize, 2)
+    }
+
+    /// The first note if a word is spelled in many ways.
+    pub fn note(&self, id: WordId) -> Option<Note<'_>> {
+        let index = id as usize;
+        let spelling = self.string(WORDS, index, 4);
+        if spelling.is_empty() {
+            return None;
+        }
+        let tier = (self.field(WORDS, index, 10) as usize).checked_sub(1);
+        Some(Note { spelling, latin: self.string(WORDS, index, 6), gloss: self.string(WORDS, index, 8), tier })
+    }
+
+    /// The root of the trie, where no byte is walked.
+    pub fn root(&self) -> Cursor {
+        Cursor { range: 0..self.sections[PREFIXES].1, depth: 0 }
+    }
+
+    /// Walk down the trie by the byte. Returns `None` if no prefix goes on with it.
+    pub fn step(&self, cursor: &Cursor, byte: u8) -> Option<Cursor> {
+        // the prefix ending at the depth comes first, as `None` is less than any byte
+        let byte_at = |index: usize| self.string(PREFIXES, index, 0).as_bytes().get(cursor.depth).copied();
+        let partition = |pred: &dyn Fn(Option<u8>) -> bool| {
+            let (mut low, mut high) = (cursor.range.start, cursor.range.end);
+            while low < high {
+                let middle = (low + high) / 2;
+                if pred(byte_at(middle)) {
+                    low = middle + 1;
+                } else {
+                    high = middle;
                 }
             }
+            low
+        };
+        let start = partition(&|it| it < Some(byte));
+        let end = partition(&|it| it <= Some(byte));
+        (start < end).then(|| Cursor { range: start..end, depth: cursor.depth + 1 })
+    }
+
+    /// The candidate of the prefix walked so far.
+    pub fn candidate_at(&self, cursor: &Cursor) -> Option<Candidate<'_>> {
+        let index = cursor.range.start;
+        if cursor.depth == 0 || self.field(PREFIXES, index, 1) as usize != cursor.depth {
+            return None;
+        }
+        let exact = self.field(PREFIXES, index, 3);
+        let others = self.ids(PREFIXES, index, 4);
+        Some(match self.field(PREFIXES, index, 2) {
+            EXACT => Candidate::Exact(exact, others),
+            UNIQUE => Candidate::Unique(exact),
+            _ => Candidate::Duplicates(others),
+        })
+    }
+
+    /// The rules of the punctuator, the more specific ones first.
+    pub fn punct_rules(&self, punct: char) -> impl Iterator<Item = PunctRule<'_>> {
+        let key = |index: usize| self.field(PUNCTS, index, 0).cmp(&(punct as u32));
+        let range = self.search(PUNCTS, key).map_or(0..0, |index| {
+            let start = (0..index).rev().take_while(|index| key(*index).is_eq()).last().unwrap_or(index);
+            let end = (index..self.sections[PUNCTS].1).take_while(|index| key(*index).is_eq()).count() + index;
+            start..end
+        });
+        range.map(|index| PunctRule {
+            twice: self.field(PUNCTS, index, 1) != 0,
+            condition: Condition::decode([self.field(PUNCTS, index, 2), self.field(PUNCTS, index, 3)]),
+            output: self.string(PUNCTS, index, 4),
+        })
+    }
+
+    /// The output the punctuator always has, if it's a single char, like a joiner.
+    pub fn punct(&self, punct: char) -> Option<char> {
+        let rule = self.punct_rules(punct).find(|rule| !rule.twice && rule.condition == Condition::Always)?;
+        let mut chars = rule.output.chars();
+        match (chars.next(), chars.next()) {
+            (Some(ch), None) => Some(ch),
+            _ => None,
         }
-        Schema {candis, alters, puncts, squote, dquote}
+    }
+
+    /// The char the Latin letter is spelled with, or the letter itself if it's not in the schema.
+    pub fn fold(&self, letter: char) -> char {
+        self.search(LETTERS, |index| self.field(LETTERS, index, 0).cmp(&(letter as u32)))
+            .and_then(|index| char::from_u32(self.field(LETTERS, index, 1)))
+            .unwrap_or(letter)
+    }
+
+    /// The spellings and words of a key set, in the order they're defined.
+    pub fn chords(&self, key_set: &str) -> impl Iterator<Item = (&str, &str)> {
+        let index = self.search(CHORDS, |index| self.string(CHORDS, index, 0).cmp(key_set));
+        let range = index.map_or(0..0, |index| {
+            let start = self.field(CHORDS, index, 2) as usize;
+            start..start + self.field(CHORDS, index, 3) as usize
+        });
+        range.map(|entry| (self.string(CHORD_ENTRIES, entry, 0), self.word(self.field(CHORD_ENTRIES, entry, 2))))
     }
 }
 
 #[test]
 fn test() {
-    test_schema(crate::SITELEN_SCHEMA);
-    test_schema(crate::EMOJI_SCHEMA);
+    for bytes in [crate::SITELEN_SCHEMA, crate::EMOJI_SCHEMA] {
+        let start = std::time::Instant::now();
+        let schema = Schema::new(bytes).unwrap();
+        println!("Elapsed: {:?}", start.elapsed());
+        assert!(matches!(schema.candidate("a"), Some(Candidate::Exact(..))));
+    }
+    let schema = Schema::compile("aueei 🦎 🐸 | akesi | reptile, amphibian\naoa ❌\naoaea 🏹\n. 。\n' 「 」\n");
+    let frog = schema.word_id("🐸").unwrap();
+    assert_eq!(schema.note(frog), Some(Note { spelling: "aueei", latin: "akesi", gloss: "reptile, amphibian", tier: None }));
+    assert_eq!(schema.note(schema.word_id("❌").unwrap()).unwrap().latin, "");
+    assert_eq!(schema.alters(schema.word_id("🦎").unwrap()).collect::<Vec<_>>(), vec![frog]);
+    let words = |ids: Ids| ids.map(|id| schema.word(id)).collect::<Vec<_>>();
+    match schema.candidate("aoa") {
+        Some(Candidate::Exact(word, others)) => assert_eq!((schema.word(word), words(others)), ("❌", vec!["🏹"])),
+        _ => panic!(),
+    }
+    assert!(matches!(schema.candidate("aue"), Some(Candidate::Unique(_))));
+    // the words spelled alike or before it are kept
+    let alike = Schema::compile("aoaea 🏹\ne 󱤉\ne 󱦆\naoa ❌\neu 󱤌\n");
+    let exact = |spelling| match alike.candidate(spelling) {
+        Some(Candidate::Exact(word, others)) => Some(word).into_iter().chain(others).map(|id| alike.word(id)).collect::<Vec<_>>(),
+        _ => panic!(),
+    };
+    assert_eq!(exact("e"), vec!["󱤉", "󱦆", "󱤌"]);
+    assert_eq!(exact("aoa"), vec!["❌", "🏹"]);
+    // the schemas of users are checked through
+    let mut bytes = compile("aoa ❌\naoaea 🏹\n").unwrap();
+    assert!(Schema::load(bytes.clone()).is_ok());
+    let ids = Schema::new(bytes.clone()).unwrap().sections[IDS].0;
+    bytes[ids..ids + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert!(Schema::new(bytes.clone()).is_ok());
+    assert_eq!(Schema::load(bytes).err().unwrap(), format!("record 0 of section {IDS} is out of bounds"));
+    // the last word of a spelling used to replace the ones before it, so that "e" of
+    // sitelen.schema typed 󱦆 alone. now the first one is exact and the rest follow
+    let twice = Schema::compile("e 󱤉\ne 󱦆\n");
+    match twice.candidate("e") {
+        Some(Candidate::Exact(word, others)) => assert_eq!(
+            (twice.word(word), others.map(|id| twice.word(id)).collect::<Vec<_>>()), ("󱤉", vec!["󱦆"])),
+        _ => panic!(),
+    }
+    assert!(matches!(schema.candidate("a"), Some(Candidate::Duplicates(_))));
+    assert!(schema.candidate("o").is_none());
+    assert_eq!(schema.chords("ao").collect::<Vec<_>>(), vec![("aoa", "❌")]);
+    assert_eq!(schema.punct('.'), Some('。'));
+    assert_eq!(schema.punct(','), None);
+    assert_eq!(schema.squote, ('「', '」'));
+    let schema = Schema::compile("a=a t=u k=u\nuu 󱥷 | tu\n");
+    assert_eq!("tkab".chars().map(|ch| schema.fold(ch)).collect::<String>(), "uuab");
+    let schema = Schema::compile("a 󱤀\n[pu]\nuu 󱥮 | tu\n[ku suli]\nuu 󱦈 | ku\n[ #F1990\n");
+    let tier = |word| schema.note(schema.word_id(word).unwrap()).unwrap().tier;
+    assert_eq!((tier("󱤀"), tier("󱥮"), tier("󱦈")), (None, Some(0), Some(1)));
+    assert_eq!(schema.punct('['), Some('\u{F1990}'));
+    assert!(compile("[pu]\n[ku pi ale]\n").is_err());
+    let schema = Schema::compile(". 󱦜\n.. 󱦝\n. 󱦜#3000 after:#F1900\n: #F199D composing\n");
+    let rules = |punct| schema.punct_rules(punct).map(|rule| (rule.twice, rule.condition, rule.output)).collect::<Vec<_>>();
+    assert_eq!(rules('.'), vec![
+        (true, Condition::Always, "󱦝"), (false, Condition::After('\u{F1900}'), "󱦜\u{3000}"), (false, Condition::Always, "󱦜")]);
+    assert_eq!(rules(':'), vec![(false, Condition::Composing, "󱦝")]);
+    assert_eq!(schema.punct(':'), None);
+    assert!(compile(". 󱦜 sometimes").is_err());
+    assert!(compile("aoa").is_err());
+    assert!(Schema::new(&b"AJMS"[..]).is_err());
 }
-
-
-#[allow(unused)]
-fn test_schema(str: &str) {
-    let start = std::time::Instant::now();
-    let schema = Schema::from(str);
-    let elapsed = std::time::Instant::now() - start;
-    println!("Elapsed: {:?}", elapsed);
-    println!("{:?}", schema);
-    println!()
-}
\ No newline at end of file
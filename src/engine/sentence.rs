@@ -1,29 +1,79 @@
 use crate::extend::CharExt;
-use super::{long_glyph::insert_long_glyph, schema::Candidate::*, Engine, Suggestion};
+use crate::conf::Config;
+use super::{lattice::SuggestState, long_glyph::*, schema::WordId, Engine, Suggestion};
 
-#[derive(Default, Clone)]
-struct Sentence {
-    output: String,
-    groupping: Vec<usize>,
-    score: usize,
-    wc: u8,
+//----------------------------------------------------------------------------
+//
+//  Sentences. From each position, a sentence goes on with the longest exact
+//  match, or the longest unique match if it's longer. Among all of them the
+//  one of the highest score with more than one word is suggested, and ties
+//  go to the one that prefers exact matches first.
+//
+//  Instead of trying every sentence, the best ones from each position on are
+//  worked out from the end of the spelling backwards, which takes time in
+//  proportion to the length, with the matches taken from the lattice. As a
+//  letter typed at the end changes the best ones from every position, they
+//  are worked out anew on each keystroke rather than kept in the state.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Clone, Copy)]
+enum Match {
+    Exact,
+    Unique,
 }
 
-impl Sentence {
-    fn push_unique(&mut self, unique: &str, len: usize) {
-        self.push_word(unique, len);
-        self.score += len * 20;
+impl Match {
+    fn score(self, len: usize) -> usize {
+        len * match (self, len) {
+            (Match::Unique, _) => 20,
+            (Match::Exact, 1) => 10, // a, e and n can be very annoying
+            (Match::Exact, 2) => 29, // a unique prefix of length 3 is favored over an exact match of length 2 (so pim > pi'm)
+            (Match::Exact, _) => 30, // use a 3 : 2 ratio by default
+        }
     }
+}
 
-    fn push_exact(&mut self, exact: &str, len: usize) {
-        self.push_word(exact, len);
-        self.score += len * match len {
-            1 => 10, // a, e and n can be very annoying
-            2 => 29, // a unique prefix of length 3 is favored over an exact match of length 2 (so pim > pi'm)
-            _ => 30, // use a 3 : 2 ratio by default
-        };
+/// The sentences from a position on. They're ordered: the one taking the
+/// first of the matches all the way comes first, then the rest, which are
+/// the ones taking the unique match here, then the rest of the exact match.
+#[derive(Default, Clone, Copy)]
+struct Node {
+    /// Where the words start, after the leading joiners.
+    start: usize,
+    /// The matches and their lengths. The first one is the exact one if any.
+    first: Option<(Match, usize, WordId)>,
+    second: Option<(Match, usize, WordId)>,
+    /// The score and the word count of the first sentence.
+    first_score: usize,
+    first_wc: usize,
+    /// The best of the rest with at least 0, 1 and 2 words, by the score and
+    /// whether it takes the second match, then goes on with the best there.
+    /// Otherwise it takes the first match, then goes on with the rest there.
+    rest: [Option<(usize, bool)>; 3],
+}
+
+impl Node {
+    /// The best with at least `wc` words, by the score and whether it's the first one.
+    fn best(&self, wc: usize) -> Option<(usize, bool)> {
+        let first = (self.first_wc >= wc).then_some((self.first_score, true));
+        let rest = self.rest[wc].map(|(score, _)| (score, false));
+        match (first, rest) {
+            (Some(first), Some(rest)) => Some(if first.0 >= rest.0 { first } else { rest }),
+            (first, rest) => first.or(rest),
+        }
     }
+}
+
+#[derive(Default)]
+struct Sentence {
+    output: String,
+    groupping: Vec<usize>,
+    /// The words and the joiners in the output, by the length in chars and if it's a joiner.
+    pieces: Vec<(usize, bool)>,
+}
 
+impl Sentence {
     fn push_word(&mut self, word: &str, len: usize) {
         if self.output.chars().last().map(|char|char.is_joiner()).unwrap_or(false) {
             *self.groupping.last_mut().unwrap() += len;
@@ -31,11 +81,12 @@ impl Sentence {
             self.groupping.push(self.groupping.last().cloned().unwrap_or(0) + len);
         }
         self.output.push_str(word);
-        self.wc += 1;
+        self.pieces.push((word.chars().count(), false));
     }
 
     fn push_joiner(&mut self, joiner: char) {
         self.output.push(joiner);
+        self.pieces.push((1, true));
         if let Some(last) = self.groupping.last_mut() {
             *last += 1;
         } else {
@@ -43,117 +94,136 @@ impl Sentence {
         }
     }
 }
-  
-#[allow(unused)]
+
 impl Engine {
-    pub(super) fn suggest_sentence(&self, spelling: &str) -> Option<Suggestion>{
-        let mut sents = self.suggest_sentences(spelling);
-        let mut best_sent = None;
-        let mut highest_score = 0;
-        while !sents.is_empty() {
-            let sent = sents.pop().unwrap();
-            if sent.wc <= 1 {
-                continue;
+    pub(super) fn suggest_sentence(&self, state: &SuggestState, conf: &Config) -> Option<Suggestion> {
+        let schema = self.schema();
+        let spelling = state.spelling().as_bytes();
+        let joiner = |pos: usize| schema.punct(char::from(spelling[pos]));
+        let mut nodes = vec![Node { start: spelling.len(), ..Node::default() }; spelling.len() + 1];
+        for pos in (0..spelling.len()).rev() {
+            let mut node = Node { start: pos, ..Node::default() };
+            while node.start < spelling.len() && joiner(node.start).is_some() {
+                node.start += 1;
+            }
+            if node.start < spelling.len() {
+                let (exact, unique) = state.matches(schema, node.start, |word| self.suggested(word, conf));
+                let exact = exact.map(|(len, word)| (Match::Exact, len, word));
+                let unique = unique.map(|(len, word)| (Match::Unique, len, word));
+                (node.first, node.second) = if exact.is_some() { (exact, unique) } else { (unique, None) };
+            }
+            if let Some((which, len, _)) = node.first {
+                let next = &nodes[node.start + len];
+                node.first_score = which.score(len) + next.first_score;
+                node.first_wc = 1 + next.first_wc;
             }
-            if sent.score > highest_score {
-                highest_score = sent.score;
-                best_sent = Some(sent);
+            for wc in 0..3usize {
+                let left = wc.saturating_sub(1);
+                node.rest[wc] = match (node.first, node.second) {
+                    (Some((exact, exact_len, _)), Some((unique, unique_len, _))) => {
+                        let by_unique = nodes[node.start + unique_len].best(left)
+                            .map(|(score, _)| (unique.score(unique_len) + score, true));
+                        let by_exact = nodes[node.start + exact_len].rest[left]
+                            .map(|(score, _)| (exact.score(exact_len) + score, false));
+                        match (by_unique, by_exact) {
+                            (Some(by_unique), Some(by_exact)) => Some(if by_unique.0 >= by_exact.0 { by_unique } else { by_exact }),
+                            (by_unique, by_exact) => by_unique.or(by_exact),
+                        }
+                    }
+                    (Some((which, len, _)), None) => nodes[node.start + len].rest[left]
+                        .map(|(score, _)| (which.score(len) + score, false)),
+                    _ => None,
+                };
             }
+            nodes[pos] = node;
         }
-        let Some(mut best_sent) = best_sent else {
-            return None;
-        };
-        insert_long_glyph(&mut best_sent.output);
-        Some(Suggestion{output:best_sent.output, groupping: best_sent.groupping})
-    }
-    
-    fn suggest_sentences(&self, spelling: &str) -> Vec<Sentence> {
+        // follow the best one with more than one word
+        let (_, mut first) = nodes[0].best(2)?;
         let mut sent = Sentence::default();
-        let mut sents = Vec::new();
-        self.suggest_sentences_recursive(spelling, &mut sent, &mut sents);
-        sents.push(sent);
-        sents
-    }
-
-    fn suggest_sentences_recursive(
-        &self, 
-        spelling: &str, 
-        sent: &mut Sentence, 
-        sents: &mut Vec<Sentence>
-    ) 
-    {
-        // push leading joiners into the sentence directly
-        let mut spelling = spelling;
-        for (i, byte) in spelling.as_bytes().iter().cloned().enumerate() {
-            if let Some(joiner) = char::try_from(spelling.as_bytes()[i]).ok().and_then(|char|self.schema().puncts.get(&char)).cloned() {
-                sent.push_joiner(joiner);
-                continue;
-            } else {
-                spelling = &spelling[i..];
-                break;
+        let (mut pos, mut wc) = (0, 2);
+        loop {
+            let node = &nodes[pos];
+            for pos in pos..node.start {
+                sent.push_joiner(joiner(pos).unwrap());
             }
+            let edge = match first || !node.rest[wc].unwrap().1 {
+                true => node.first,
+                false => {
+                    let len = node.second.unwrap().1;
+                    first = nodes[node.start + len].best(wc.saturating_sub(1)).unwrap().1;
+                    node.second
+                }
+            };
+            let Some((_, len, word)) = edge else {
+                break;
+            };
+            sent.push_word(schema.word(word), len);
+            pos = node.start + len;
+            wc = wc.saturating_sub(1);
         }
-        // find the longest exact match and the longest unique match
-        // however if the exact one is longer than the unique one, ignore the unique one.
-        let mut exact = None;
-        let mut exact_len = 0;
-        let mut unique = None;
-        let mut unique_len = 0;
+        insert_long_glyph_with(&mut sent.output, conf.behavior.long_pi, conf.behavior.long_glyph);
+        let output = separate_words(&sent.output, &sent.pieces, self.spacing(conf).separator());
+        Some(Suggestion{output, groupping: sent.groupping, annotation: None})
+    }
+}
 
-        let mut found_unique = false;
-        for len in (1..=spelling.len()).rev() {
-            match self.schema().candis.get(&spelling[..len]) {
-                Some(Exact(word, _)) => {
-                    exact = Some(word.as_str());
-                    exact_len = len;
-                    break;
+/// Put the separator between the words of the output, which has long glyphs inserted.
+/// Words joined by joiners or long glyphs are not separated.
+fn separate_words(output: &str, pieces: &[(usize, bool)], separator: &str) -> String {
+    if separator.is_empty() {
+        return output.to_string();
+    }
+    let mut text = String::with_capacity(output.len());
+    let mut chars = output.chars().peekable();
+    let mut open = false;
+    // nothing to separate from before the first one
+    let mut after_joiner = true;
+    for &(len, joiner) in pieces {
+        // the long glyphs ended go before the separator, the ones started after it
+        let (mut ending, mut starting) = (String::new(), String::new());
+        // extended by the word before, or extending backwards over it
+        let mut attached = false;
+        while let Some(ch) = chars.next_if(|ch| matches!(*ch,
+            START_OF_LONG_GLYGH | END_OF_LONG_GLYPH | START_OF_REVERSE_LONG_GLYGH | END_OF_REVERSE_LONG_GLYPH))
+        {
+            match ch {
+                END_OF_LONG_GLYPH | END_OF_REVERSE_LONG_GLYPH => {
+                    open = false;
+                    attached |= ch == END_OF_REVERSE_LONG_GLYPH;
+                    ending.push(ch);
                 }
-                Some(Unique(word)) => {
-                    if found_unique {
-                        continue;
-                    }
-                    found_unique = true;
-                    unique = Some(word.as_str());
-                    unique_len = len;
+                _ => {
+                    attached |= ch == START_OF_LONG_GLYGH;
+                    starting.push(ch);
                 }
-                _ => ()
             }
         }
-        // clone if needed
-        let mut extra_sent = if exact.is_some() && unique.is_some() {
-            Some(sent.clone())
-        } else {
-            None
-        };
-        if let Some(exact) = exact {
-            sent.push_exact(exact, exact_len);
-            self.suggest_sentences_recursive(&spelling[exact_len..], sent, sents)
-        }
-        if let Some(unique) = unique {
-            let sent = if extra_sent.is_some() {
-                extra_sent.as_mut().unwrap()
-            } else {
-                sent
-            };
-            sent.push_unique(unique, unique_len);
-            self.suggest_sentences_recursive(&spelling[unique_len..], sent, sents)
-        }
-        if let Some(extra_sent) = extra_sent {
-            sents.push(extra_sent);
+        text.push_str(&ending);
+        if !open && !attached && !after_joiner && !joiner {
+            text.push_str(separator);
         }
+        open |= !starting.is_empty();
+        text.push_str(&starting);
+        text.extend(chars.by_ref().take(len));
+        after_joiner = joiner;
     }
+    text.extend(chars);
+    text
 }
 
 #[test]
 fn repl() {
     use std::io::stdin;
     use super::{setup, engine};
+    use crate::conf::conf;
     setup();
     let mut buf = String::new();
     loop {
         buf.clear();
         stdin().read_line(&mut buf).unwrap();
-        let sugg = engine().suggest_sentence(&buf);
+        let mut state = SuggestState::default();
+        engine().walk(&mut state, buf.trim_end(), &conf());
+        let sugg = engine().suggest_sentence(&state, &conf());
         if let Some(sugg) = sugg {
             println!("{}", sugg.output)
         } else {
@@ -163,17 +233,27 @@ fn repl() {
 }
 #[test]
 fn test() {
-    use super::{setup, engine};
-    fn assert_sent(spelling: &str, expected: &str) {
-        let sent = engine().suggest_sentence(spelling).unwrap().output;
+    let engine = Engine::new(&[]);
+    let mut conf = Config::default();
+    conf.behavior.latin_spelling = true;
+    let assert_sent = |spelling: &str, expected: &str| {
+        let mut state = SuggestState::default();
+        engine.walk(&mut state, spelling, &conf);
+        let sent = engine.suggest_sentence(&state, &conf).unwrap().output;
         let mut buf =  String::new();
         for word in expected.split(' ') {
-            buf.push_str(&engine().suggest(word)[0].output)
+            buf.push_str(&engine.suggest(word, &conf)[0].output)
         }
         assert_eq!(sent, buf)
-    }
-    setup();
+    };
+    // pi 󱦗a󱦘 with a stacked word, then 󱦚ken󱦛 ala
+    let output = "\u{F194D}\u{F1997}\u{F1900}\u{F1998}\u{F1901}\u{F1995}\u{F1902}\u{F199A}\u{F1918}\u{F199B}\u{F1902}";
+    let pieces = [(1, false), (1, false), (1, false), (1, true), (1, false), (1, false), (1, false)];
+    assert_eq!(separate_words(output, &pieces, " "),
+        "\u{F194D}\u{F1997}\u{F1900}\u{F1998} \u{F1901}\u{F1995}\u{F1902} \u{F199A}\u{F1918}\u{F199B}\u{F1902}");
+    assert_eq!(separate_words(output, &pieces, ""), output);
+    // whole Latin names, which the schemas only spell with `latin_spelling` on
     assert_sent("lilonsewi", "li lon sewi");
-    assert_sent("pimaka", "pi ma");
-    assert_sent("pimkule", "pimeja kule");
+    assert_sent("pima", "pi ma");
+    assert_sent("pimejakule", "pimeja kule");
 }
\ No newline at end of file
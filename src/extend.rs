@@ -1,5 +1,6 @@
 use std::{ffi::{OsString, OsStr}, os::windows::ffi::OsStrExt};
 use toml::{Table, Value};
+use crate::conf::Problem;
 use windows::{core::GUID, Win32::{Foundation::E_FAIL, UI::Input::KeyboardAndMouse::{GetKeyState, VIRTUAL_KEY}}};
 pub trait GUIDExt {
     fn to_rfc4122(&self) -> String;
@@ -76,46 +77,100 @@ impl<T> IntoWinResult<T> for anyhow::Result<T> {
     }
 }
 
+/// Values that can be loaded from conf.toml.
 pub trait LoadValue where Self: Sized {
-    fn load(&mut self, value: Value);
+    /// Load the value in place. Invalid values are reported as problems at the path
+    /// (like `behavior.long_glyph`) and leave the original value untouched.
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>);
 }
 
 impl LoadValue for bool {
-    fn load(&mut self, value: Value) {
-        if let Value::Boolean(value) = value {
-            *self = value;
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match value {
+            Value::Boolean(value) => *self = value,
+            value => problems.push(Problem::mistyped(path, "a boolean", &value)),
         }
     }
 }
 
 impl LoadValue for String {
-    fn load(&mut self, value: Value) {
-        if let Value::String(value) = value {
-            *self = value;
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match value {
+            Value::String(value) => *self = value,
+            value => problems.push(Problem::mistyped(path, "a string", &value)),
         }
     }
 }
 
 impl LoadValue for i32 {
-    fn load(&mut self, value: Value) {
-        if let Value::Integer(value) = value {
-            *self = value as i32;
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match value {
+            Value::Integer(value) => match i32::try_from(value) {
+                Ok(value) => *self = value,
+                Err(_) => problems.push(Problem::error(path, format!("{value} is out of range"))),
+            }
+            value => problems.push(Problem::mistyped(path, "an integer", &value)),
         }
     }
 }
 
+impl LoadValue for u64 {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match value {
+            Value::Integer(value) => match u64::try_from(value) {
+                Ok(value) => *self = value,
+                Err(_) => problems.push(Problem::error(path, format!("{value} is out of range"))),
+            }
+            value => problems.push(Problem::mistyped(path, "an integer", &value)),
+        }
+    }
+}
+
+impl LoadValue for Vec<String> {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Array(values) = value else {
+            problems.push(Problem::mistyped(path, "an array of strings", &value));
+            return;
+        };
+        self.clear();
+        for (index, value) in values.into_iter().enumerate() {
+            match value {
+                Value::String(value) => self.push(value),
+                value => problems.push(Problem::mistyped(&format!("{path}[{index}]"), "a string", &value)),
+            }
+        }
+    }
+}
 
-pub trait TableExt {
-    fn give<T: LoadValue>(&mut self, key: &str, out: &mut T);
+/// Takes the entries out of a table one by one. Whatever is left in the end is reported as unknown keys.
+pub struct TableLoader<'a> {
+    table: Table,
+    path: &'a str,
+    problems: &'a mut Vec<Problem>,
 }
 
-impl TableExt for Table {
-    fn give<T: LoadValue>(&mut self, key: &str, out: &mut T) {
-        if let Some(value) = self.remove(key) {
-            out.load(value)
+impl <'a> TableLoader<'a> {
+    pub fn new(value: Value, path: &'a str, problems: &'a mut Vec<Problem>) -> Option<TableLoader<'a>> {
+        match value {
+            Value::Table(table) => Some(TableLoader { table, path, problems }),
+            value => {
+                problems.push(Problem::mistyped(path, "a table", &value));
+                None
+            }
+        }
+    }
+
+    pub fn give<T: LoadValue>(&mut self, key: &str, out: &mut T) {
+        if let Some(value) = self.table.remove(key) {
+            out.load(value, &Problem::join(self.path, key), self.problems)
+        }
+    }
+
+    pub fn finish(self) {
+        for key in self.table.keys() {
+            self.problems.push(Problem::warning(&Problem::join(self.path, key), "unknown key"));
         }
     }
-    
 }
 
 pub trait VKExt {
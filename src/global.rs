@@ -53,12 +53,14 @@ pub const LITE_TRAY_ICON_INDEX: u32 = 0;
 pub const DARK_TRAY_ICON_INDEX: u32 = 1;
 // customization
 pub const CANDI_NUM: usize = 5;
+pub const CANDI_PAGES: usize = 6;
 pub const CANDI_INDEXES: [&str; CANDI_NUM] = ["1", "2", "3", "4", "5"];
 pub const CANDI_INDEX_SUFFIX: &str = ". ";
 pub const CANDI_INDEX_SUFFIX_MONO: &str = ".";
 pub const PREEDIT_DELIMITER: &str = "'";
 // included text
 pub const DEFAULT_CONF: &str = include_str!("../res/conf.toml");
-pub const SITELEN_SCHEMA: &str = include_str!("../res/schema/sitelen.schema");
-pub const EMOJI_SCHEMA: &str = include_str!("../res/schema/emoji.schema");
+// compiled by build.rs
+pub const SITELEN_SCHEMA: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/sitelen.bin"));
+pub const EMOJI_SCHEMA: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/emoji.bin"));
 
@@ -0,0 +1,142 @@
+use toml::Value;
+use crate::{conf::Problem, extend::LoadValue};
+
+//----------------------------------------------------------------------------
+//
+//  The keymap layer. Sitelen aeiou needs nothing more than 18 keys, thus
+//  any physical key can be assigned to any of them regardless of the
+//  keyboard layout of the OS. Keys are identified by their scan codes.
+//  Extended keys (like the Enter on the numpad) are prefixed by 0xE0.
+//
+//----------------------------------------------------------------------------
+
+/// The 18 logical inputs.
+pub const INPUTS: [char; 18] = [
+    'a', 'e', 'i', 'o', 'u',
+    '(', ')', '[', ']', '{', '}',
+    '-', '^', '*',
+    ' ', '.', ':', '"',
+];
+
+/// The vowels sit on the left home row and the brackets on the right.
+const HOME_ROW: [(u16, char); 18] = [
+    (0x1E, 'a'), (0x1F, 'e'), (0x20, 'i'), (0x21, 'o'), (0x22, 'u'),
+    (0x24, '('), (0x25, ')'), (0x16, '['), (0x17, ']'), (0x32, '{'), (0x33, '}'),
+    (0x26, '-'), (0x18, '^'), (0x19, '*'),
+    (0x39, ' '), (0x34, '.'), (0x27, ':'), (0x28, '"'),
+];
+
+/// Everything but the curly brackets fits in the numpad.
+const NUMPAD: [(u16, char); 16] = [
+    (0x4B, 'a'), (0x4C, 'e'), (0x4D, 'i'), (0x4F, 'o'), (0x50, 'u'),
+    (0x47, '('), (0x48, ')'), (0xE035, '['), (0x37, ']'),
+    (0x4A, '-'), (0x4E, '^'), (0xE01C, '*'),
+    (0x52, ' '), (0x51, '.'), (0x53, ':'), (0x49, '"'),
+];
+
+/// Everything within the reach of the left hand.
+const LEFT_HAND: [(u16, char); 18] = [
+    (0x1E, 'a'), (0x1F, 'e'), (0x20, 'i'), (0x21, 'o'), (0x22, 'u'),
+    (0x10, '('), (0x11, ')'), (0x12, '['), (0x13, ']'), (0x2C, '{'), (0x2D, '}'),
+    (0x14, '-'), (0x2E, '^'), (0x2F, '*'),
+    (0x39, ' '), (0x30, '.'), (0x29, ':'), (0x23, '"'),
+];
+
+/// Maps scan codes to logical inputs. Keys that are not mapped are left as they are.
+#[derive(Default, Clone)]
+pub struct Keymap {
+    keys: Vec<(u16, char)>,
+}
+
+impl Keymap {
+    pub const fn new() -> Keymap {
+        Keymap { keys: Vec::new() }
+    }
+
+    pub fn preset(name: &str) -> Option<Keymap> {
+        let keys: &[(u16, char)] = match name {
+            "none" => &[],
+            "home_row" => &HOME_ROW,
+            "numpad" => &NUMPAD,
+            "left_hand" => &LEFT_HAND,
+            _ => return None
+        };
+        Some(Keymap { keys: keys.to_vec() })
+    }
+
+    pub fn get(&self, scan_code: u16) -> Option<char> {
+        self.keys.iter()
+            .find(|(key, _)| *key == scan_code)
+            .map(|(_, input)| *input)
+    }
+
+    /// Assign the keys to the input, replacing the keys previously assigned to it.
+    pub fn assign(&mut self, input: char, scan_codes: &[u16]) {
+        self.keys.retain(|(key, assigned)| *assigned != input && !scan_codes.contains(key));
+        self.keys.extend(scan_codes.iter().map(|key| (*key, input)));
+    }
+}
+
+impl LoadValue for Keymap {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(mut table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        if let Some(preset) = table.remove("preset") {
+            match preset.as_str().and_then(Keymap::preset) {
+                Some(preset) => *self = preset,
+                None => problems.push(Problem::error(&Problem::join(path, "preset"), format!("unknown preset {preset}"))),
+            }
+        }
+        for (name, keys) in table {
+            let path = Problem::join(path, &name);
+            // like in schemas, space is spelled out
+            let mut chars = name.chars();
+            let input = match (chars.next(), chars.next()) {
+                _ if name == "space" => ' ',
+                (Some(input), None) if INPUTS.contains(&input) => input,
+                _ => {
+                    problems.push(Problem::warning(&path, "unknown input"));
+                    continue;
+                }
+            };
+            let keys = match keys {
+                Value::Array(keys) => keys,
+                key => vec![key]
+            };
+            let mut scan_codes = Vec::with_capacity(keys.len());
+            for key in keys {
+                match key.as_integer().and_then(|key| u16::try_from(key).ok()) {
+                    Some(scan_code) => scan_codes.push(scan_code),
+                    None => problems.push(Problem::error(&path, format!("invalid scan code {key}"))),
+                }
+            }
+            self.assign(input, &scan_codes);
+        }
+    }
+}
+
+#[test]
+fn test() {
+    for preset in [&HOME_ROW[..], &NUMPAD[..], &LEFT_HAND[..]] {
+        for (i, (key, input)) in preset.iter().enumerate() {
+            assert!(INPUTS.contains(input));
+            assert!(preset[i + 1..].iter().all(|(other_key, other_input)| other_key != key && other_input != input));
+        }
+    }
+    let mut keymap = Keymap::new();
+    keymap.load(r#"
+        preset = "home_row"
+        a = 0x1C
+        "(" = [0x1E, 0xE01C]
+        space = 0x52
+    "#.parse::<toml::Table>().map(Value::Table).unwrap(), "keymap", &mut Vec::new());
+    assert_eq!(keymap.get(0x1C), Some('a'));
+    assert_eq!(keymap.get(0x1E), Some('('));
+    assert_eq!(keymap.get(0xE01C), Some('('));
+    assert_eq!(keymap.get(0x24), None);
+    assert_eq!(keymap.get(0x52), Some(' '));
+    assert_eq!(keymap.get(0x39), None);
+    assert_eq!(keymap.get(0x1F), Some('e'));
+}
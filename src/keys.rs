@@ -0,0 +1,303 @@
+use std::{fmt::{self, Display}, str::FromStr};
+use crate::conf::Problem;
+use toml::Value;
+use crate::extend::LoadValue;
+use Action::*;
+
+//----------------------------------------------------------------------------
+//
+//  Key bindings. Chords like "Ctrl+Shift+N" are bound to named actions in
+//  the [keys] table of conf.toml. See res/conf.toml for the defaults.
+//  A lone modifier like "Shift" is triggered by tapping the key alone.
+//
+//----------------------------------------------------------------------------
+
+/// A key and the modifiers held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Chord {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// The virtual-key code.
+    pub key: usize,
+}
+
+/// Things a key chord can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    NextSchema,
+    PreviousSchema,
+    SelectSchema(usize),
+    Commit,
+    Release,
+    ReleaseWithSpace,
+    DeleteSegment,
+    ToggleLongGlyph,
+    ToggleIme,
+    NextPage,
+    PreviousPage,
+    CycleCandidate,
+}
+
+#[derive(Default, Clone)]
+pub struct KeyBindings {
+    bindings: Vec<(Action, Vec<Chord>)>,
+}
+
+impl KeyBindings {
+    pub const fn new() -> KeyBindings {
+        KeyBindings { bindings: Vec::new() }
+    }
+
+    /// Find the action bound to the chord. When there's a conflict, the one bound later wins.
+    pub fn action_of(&self, chord: Chord) -> Option<Action> {
+        self.bindings.iter()
+            .rfind(|(_, chords)| chords.contains(&chord))
+            .map(|(action, _)| *action)
+    }
+
+    /// Bind the action to the chords, replacing the previous binding of the same action.
+    pub fn bind(&mut self, action: Action, chords: Vec<Chord>) {
+        self.bindings.retain(|(bound, _)| *bound != action);
+        self.bindings.push((action, chords));
+    }
+
+    /// Chords that are bound to more than one action, along with the overridden action and the overriding one.
+    pub fn conflicts(&self) -> Vec<(Chord, Action, Action)> {
+        let mut conflicts = Vec::new();
+        for (i, (action, chords)) in self.bindings.iter().enumerate() {
+            for (other, other_chords) in &self.bindings[i + 1..] {
+                for chord in chords.iter().filter(|chord| other_chords.contains(chord)) {
+                    conflicts.push((*chord, *action, *other));
+                }
+            }
+        }
+        conflicts
+    }
+
+}
+
+impl LoadValue for KeyBindings {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        for (name, chords) in table {
+            let path = Problem::join(path, &name);
+            let Ok(action) = name.parse::<Action>() else {
+                problems.push(Problem::warning(&path, "unknown action"));
+                continue;
+            };
+            let chords = match chords {
+                Value::String(chord) => vec![Value::String(chord)],
+                Value::Array(chords) => chords,
+                chords => {
+                    problems.push(Problem::mistyped(&path, "a string or an array of strings", &chords));
+                    continue;
+                }
+            };
+            let count = chords.len();
+            let mut parsed = Vec::with_capacity(count);
+            for chord in chords {
+                match chord.as_str().map(str::parse::<Chord>) {
+                    Some(Ok(chord)) => parsed.push(chord),
+                    _ => problems.push(Problem::error(&path, format!("unrecognizable chord {chord}"))),
+                }
+            }
+            // the action keeps its chords unless all of the new ones are understood
+            if parsed.len() == count {
+                self.bind(action, parsed);
+            }
+        }
+    }
+}
+
+
+//----------------------------------------------------------------------------
+//
+//  Names of actions and keys.
+//
+//----------------------------------------------------------------------------
+
+const ACTION_NAMES: [(&str, Action); 11] = [
+    ("next_schema", NextSchema),
+    ("previous_schema", PreviousSchema),
+    ("commit", Commit),
+    ("release", Release),
+    ("release_with_space", ReleaseWithSpace),
+    ("delete_segment", DeleteSegment),
+    ("toggle_long_glyph", ToggleLongGlyph),
+    ("toggle_ime", ToggleIme),
+    ("next_page", NextPage),
+    ("previous_page", PreviousPage),
+    ("cycle_candidate", CycleCandidate),
+];
+const SELECT_SCHEMA: &str = "select_schema_";
+
+impl FromStr for Action {
+    type Err = ();
+    fn from_str(name: &str) -> Result<Action, ()> {
+        if let Some(number) = name.strip_prefix(SELECT_SCHEMA) {
+            return match number.parse::<usize>() {
+                Ok(number) if number >= 1 => Ok(SelectSchema(number - 1)),
+                _ => Err(())
+            };
+        }
+        ACTION_NAMES.iter()
+            .find(|(action_name, _)| *action_name == name)
+            .map(|(_, action)| *action)
+            .ok_or(())
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let SelectSchema(index) = self {
+            return write!(f, "{SELECT_SCHEMA}{}", index + 1);
+        }
+        let name = ACTION_NAMES.iter()
+            .find(|(_, action)| action == self)
+            .map(|(name, _)| *name)
+            .unwrap();
+        f.write_str(name)
+    }
+}
+
+/// See https://learn.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes for keycodes.
+const KEY_NAMES: [(&str, usize); 39] = [
+    ("Backspace", 0x08), ("Tab", 0x09), ("Enter", 0x0D), ("CapsLock", 0x14), ("Escape", 0x1B),
+    ("Shift", 0x10), ("Ctrl", 0x11), ("Alt", 0x12),
+    ("LShift", 0xA0), ("RShift", 0xA1), ("LCtrl", 0xA2), ("RCtrl", 0xA3), ("LAlt", 0xA4), ("RAlt", 0xA5),
+    ("Space", 0x20), ("PageUp", 0x21), ("PageDown", 0x22), ("End", 0x23), ("Home", 0x24),
+    ("Left", 0x25), ("Up", 0x26), ("Right", 0x27), ("Down", 0x28), ("Insert", 0x2D), ("Delete", 0x2E),
+    ("Multiply", 0x6A), ("Add", 0x6B), ("Subtract", 0x6D), ("Divide", 0x6F),
+    (";", 0xBA), ("=", 0xBB), (",", 0xBC), ("-", 0xBD), (".", 0xBE),
+    ("/", 0xBF), ("`", 0xC0), ("[", 0xDB), ("\\", 0xDC), ("]", 0xDD),
+];
+const QUOTE: usize = 0xDE;
+
+impl FromStr for Chord {
+    type Err = ();
+    fn from_str(text: &str) -> Result<Chord, ()> {
+        let mut chord = Chord { ctrl: false, alt: false, shift: false, key: 0 };
+        let mut parts = text.split('+').map(str::trim).peekable();
+        while let Some(part) = parts.next() {
+            let last = parts.peek().is_none();
+            let lowercase = part.to_ascii_lowercase();
+            match lowercase.as_str() {
+                "ctrl" | "control" if !last => chord.ctrl = true,
+                "alt" if !last => chord.alt = true,
+                "shift" if !last => chord.shift = true,
+                _ if last => chord.key = key_code(part).ok_or(())?,
+                _ => return Err(())
+            }
+        }
+        Ok(chord)
+    }
+}
+
+fn key_code(name: &str) -> Option<usize> {
+    let lowercase = name.to_ascii_lowercase();
+    let bytes = lowercase.as_bytes();
+    match bytes {
+        [letter @ b'a'..=b'z'] => return Some(0x41 + (letter - b'a') as usize),
+        [digit @ b'0'..=b'9'] => return Some(0x30 + (digit - b'0') as usize),
+        [b'\''] => return Some(QUOTE),
+        _ => ()
+    }
+    if let Some(number) = lowercase.strip_prefix('f').and_then(|it| it.parse::<usize>().ok()) {
+        return (1..=24).contains(&number).then(|| 0x70 + number - 1);
+    }
+    if let Some(number) = lowercase.strip_prefix("numpad").and_then(|it| it.parse::<usize>().ok()) {
+        return (0..=9).contains(&number).then(|| 0x60 + number);
+    }
+    match lowercase.as_str() {
+        "esc" => return Some(0x1B),
+        "return" => return Some(0x0D),
+        "del" => return Some(0x2E),
+        "control" => return Some(0x11),
+        _ => ()
+    }
+    KEY_NAMES.iter()
+        .find(|(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|(_, key_code)| *key_code)
+}
+
+/// Shift, Ctrl and Alt, either generic or sided.
+pub fn is_modifier(key_code: usize) -> bool {
+    matches!(key_code, 0x10..=0x12 | 0xA0..=0xA5)
+}
+
+fn key_name(key_code: usize) -> String {
+    match key_code {
+        0x41..=0x5A | 0x30..=0x39 => char::from(key_code as u8).to_string(),
+        0x70..=0x87 => format!("F{}", key_code - 0x70 + 1),
+        0x60..=0x69 => format!("Numpad{}", key_code - 0x60),
+        QUOTE => "'".to_string(),
+        _ => KEY_NAMES.iter()
+            .find(|(_, code)| *code == key_code)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| format!("{key_code:#04X}"))
+    }
+}
+
+impl Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            f.write_str("Ctrl+")?;
+        }
+        if self.alt {
+            f.write_str("Alt+")?;
+        }
+        if self.shift {
+            f.write_str("Shift+")?;
+        }
+        f.write_str(&key_name(self.key))
+    }
+}
+
+#[test]
+fn test() {
+    fn chord(text: &str) -> Chord {
+        text.parse().unwrap()
+    }
+    assert_eq!(chord("Ctrl+Shift+N"), Chord { ctrl: true, alt: false, shift: true, key: 0x4E });
+    assert_eq!(chord("shift + ctrl + n"), chord("Ctrl+Shift+N"));
+    assert_eq!(chord("Ctrl+Shift+N").to_string(), "Ctrl+Shift+N");
+    assert_eq!(chord("PageDown").key, 0x22);
+    assert_eq!(chord("F12").key, 0x7B);
+    assert_eq!(chord("Ctrl+'").to_string(), "Ctrl+'");
+    assert_eq!(chord("Shift"), Chord { ctrl: false, alt: false, shift: false, key: 0x10 });
+    assert_eq!(chord("RCtrl").to_string(), "RCtrl");
+    assert!("Ctrl+".parse::<Chord>().is_err());
+    assert!("Ctrl+Hyper".parse::<Chord>().is_err());
+    assert_eq!("select_schema_2".parse(), Ok(SelectSchema(1)));
+    assert_eq!(SelectSchema(1).to_string(), "select_schema_2");
+    assert!("select_schema_0".parse::<Action>().is_err());
+
+    let mut keys = KeyBindings::new();
+    let mut problems = Vec::new();
+    keys.load(r#"
+        commit = "Space"
+        release = ["Enter", "Space"]
+        next_schema = "Ctrl+Shift+N"
+        next_shema = "Ctrl+Shift+M"
+    "#.parse::<toml::Table>().map(Value::Table).unwrap(), "keys", &mut problems);
+    assert_eq!(problems, vec![Problem::warning("keys.next_shema", "unknown action")]);
+    assert_eq!(keys.action_of(chord("Space")), Some(Release));
+    assert_eq!(keys.action_of(chord("Enter")), Some(Release));
+    assert_eq!(keys.conflicts(), vec![(chord("Space"), Commit, Release)]);
+    keys.bind(Release, vec![chord("Enter")]);
+    assert!(keys.conflicts().is_empty());
+    // a chord not understood leaves the action bound as it was
+    let mut problems = Vec::new();
+    keys.load(r#"
+        commit = "Ctrl+Hyper"
+        release = ["Tab", "Ctrl+Hyper"]
+    "#.parse::<toml::Table>().map(Value::Table).unwrap(), "keys", &mut problems);
+    assert_eq!(problems.len(), 2);
+    assert_eq!(keys.action_of(chord("Space")), Some(Commit));
+    assert_eq!(keys.action_of(chord("Enter")), Some(Release));
+    assert_eq!(keys.action_of(chord("Tab")), None);
+}
@@ -2,8 +2,16 @@ mod register;
 mod install;
 mod global;
 mod log;
-mod conf;
+pub mod conf;
+mod compose;
+mod pairs;
+pub mod record;
+pub mod stats;
+pub mod ucsur;
 mod extend;
+mod keys;
+mod keymap;
+mod profile;
 mod tsf;
 mod engine;
 mod ui;
@@ -16,6 +24,9 @@ use global::*;
 use register::*;
 
 use crate::{extend::{IntoWinResult, GUIDExt}, install::install, tsf::TextService};
+pub use engine::compile as compile_schema;
+pub use engine::generate as generate_schemas;
+pub use engine::user_schema_dir;
 
 //----------------------------------------------------------------------------
 //
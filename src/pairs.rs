@@ -0,0 +1,89 @@
+//----------------------------------------------------------------------------
+//
+//  Paired punctuators, kept for each document, so that a quote opened in one
+//  text box isn't closed by typing in another one. Brackets of cartouches
+//  and long glyphs can be closed as they're opened, with the caret inside.
+//  The last punctuator is kept as well, for the rules of double taps.
+//
+//----------------------------------------------------------------------------
+
+/// What typing a punctuator does, besides inserting it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Closing {
+    /// Nothing else.
+    #[default]
+    None,
+    /// Insert the closer as well, after the caret.
+    Open(char),
+    /// The closer is already ahead of the caret. Step over it instead of inserting.
+    Over(char),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Pairs {
+    pub squote_open: bool,
+    pub dquote_open: bool,
+    /// The closers put ahead of the caret by auto-closing, the innermost last.
+    /// Forgotten once the caret might have moved.
+    pub closers: Vec<char>,
+    /// The last punctuator and what it's remapped to, while nothing else is typed after it.
+    pub last: Option<(char, String)>,
+}
+
+/// The closer of a cartouche or a long glyph.
+fn closer(opener: char) -> Option<char> {
+    match opener {
+        '\u{F1990}' => Some('\u{F1991}'),
+        '\u{F1997}' => Some('\u{F1998}'),
+        '\u{F199A}' => Some('\u{F199B}'),
+        _ => None,
+    }
+}
+
+impl Pairs {
+    /// Quotes are opened again after switching schemas, which have quotes of their own.
+    pub fn reset_quotes(&mut self) {
+        self.squote_open = false;
+        self.dquote_open = false;
+    }
+
+    /// A key goes to the client, which might type something or move the caret.
+    /// The closers are forgotten, and the next punctuator is no double tap.
+    pub fn pass_key(&mut self) {
+        self.closers.clear();
+        self.last = None;
+    }
+
+    /// Type what a punctuator is remapped to. Brackets are closed if `auto_close`.
+    pub fn close(&mut self, output: &str, auto_close: bool) -> Closing {
+        let mut chars = output.chars();
+        let (Some(punct), None) = (chars.next(), chars.next()) else {
+            return Closing::None;
+        };
+        if self.closers.last() == Some(&punct) {
+            self.closers.pop();
+            return Closing::Over(punct);
+        }
+        match closer(punct) {
+            Some(closer) if auto_close => {
+                self.closers.push(closer);
+                Closing::Open(closer)
+            }
+            _ => Closing::None,
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let mut pairs = Pairs::default();
+    assert_eq!(pairs.close("\u{F1990}", false), Closing::None);
+    assert_eq!(pairs.close("\u{F1990}", true), Closing::Open('\u{F1991}'));
+    assert_eq!(pairs.close("\u{F1997}", true), Closing::Open('\u{F1998}'));
+    assert_eq!(pairs.close("\u{F1991}", true), Closing::None);
+    assert_eq!(pairs.close("\u{F1998}", true), Closing::Over('\u{F1998}'));
+    assert_eq!(pairs.close("\u{F1991}", true), Closing::Over('\u{F1991}'));
+    assert_eq!(pairs.close("\u{F1991}", true), Closing::None);
+    assert!(pairs.closers.is_empty());
+    assert_eq!(pairs.close("\u{F1990}\u{3000}", true), Closing::None);
+}
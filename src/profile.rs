@@ -0,0 +1,113 @@
+use toml::{Table, Value};
+use crate::{conf::Problem, extend::LoadValue};
+
+//----------------------------------------------------------------------------
+//
+//  Per-application profiles. Each [[profile]] in conf.toml matches programs
+//  by the executable name, the window class or both, and overrides the rest
+//  of the config with its own tables, like `behavior.long_glyph = true`.
+//  The first matching profile is used.
+//
+//----------------------------------------------------------------------------
+
+/// The program being typed in.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct App {
+    /// The file name of the executable, like "WINWORD.EXE".
+    pub exe: String,
+    /// The class of the top-level window.
+    pub class: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub exe: Option<String>,
+    pub class: Option<String>,
+    /// Tables of the config to be overridden.
+    pub overrides: Table,
+}
+
+impl Profile {
+    /// Names are compared case-insensitively, as they are by Windows.
+    /// A profile matching nothing matches nothing.
+    pub fn matches(&self, app: &App) -> bool {
+        if self.exe.is_none() && self.class.is_none() {
+            return false;
+        }
+        let exe = self.exe.as_ref().map_or(true, |exe| exe.eq_ignore_ascii_case(&app.exe));
+        let class = self.class.as_ref().map_or(true, |class| class.eq_ignore_ascii_case(&app.class));
+        exe && class
+    }
+}
+
+/// The index of the first profile matching the app.
+pub fn find(profiles: &[Profile], app: &App) -> Option<usize> {
+    profiles.iter().position(|profile| profile.matches(app))
+}
+
+impl LoadValue for Profile {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(mut table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        for (key, out) in [("exe", &mut self.exe), ("class", &mut self.class)] {
+            match table.remove(key) {
+                Some(Value::String(name)) => *out = Some(name),
+                Some(value) => problems.push(Problem::mistyped(&Problem::join(path, key), "a string", &value)),
+                None => (),
+            }
+        }
+        if self.exe.is_none() && self.class.is_none() {
+            problems.push(Problem::error(path, "expected exe or class to match"));
+        }
+        if table.remove("profile").is_some() {
+            problems.push(Problem::error(&Problem::join(path, "profile"), "profiles can't be nested"));
+        }
+        self.overrides = table;
+    }
+}
+
+impl LoadValue for Vec<Profile> {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Array(profiles) = value else {
+            problems.push(Problem::mistyped(path, "an array of tables", &value));
+            return;
+        };
+        self.clear();
+        for (index, value) in profiles.into_iter().enumerate() {
+            let mut profile = Profile::default();
+            profile.load(value, &format!("{path}[{index}]"), problems);
+            self.push(profile);
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let mut profiles = Vec::<Profile>::new();
+    let mut problems = Vec::new();
+    let table = r#"
+        [[profile]]
+        exe = "Discord.exe"
+        schema.order = ["emoji"]
+        [[profile]]
+        class = "CASCADIA_HOSTING_WINDOW_CLASS"
+        behavior.enabled = false
+        [[profile]]
+        exe = "WINWORD.EXE"
+        class = "OpusApp"
+        [[profile]]
+        behavior.long_glyph = true
+    "#.parse::<Table>().unwrap();
+    profiles.load(table["profile"].clone(), "profile", &mut problems);
+    assert_eq!(problems, vec![Problem::error("profile[3]", "expected exe or class to match")]);
+    assert_eq!(profiles[0].overrides.len(), 1);
+
+    let app = |exe: &str, class: &str| App { exe: exe.to_string(), class: class.to_string() };
+    assert_eq!(find(&profiles, &app("discord.exe", "Chrome_WidgetWin_1")), Some(0));
+    assert_eq!(find(&profiles, &app("WindowsTerminal.exe", "CASCADIA_HOSTING_WINDOW_CLASS")), Some(1));
+    assert_eq!(find(&profiles, &app("WINWORD.EXE", "OpusApp")), Some(2));
+    assert_eq!(find(&profiles, &app("WINWORD.EXE", "bosa")), None);
+    assert_eq!(find(&profiles, &app("notepad.exe", "Notepad")), None);
+}
@@ -0,0 +1,331 @@
+use std::{fs::{self, OpenOptions}, io::Write, slice};
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use log::warn;
+use toml::{Table, Value};
+use crate::{compose::{self, Composer, Effect, Event}, conf::{self, Config, Problem, Spacing, TierPenalty}, engine::{self, ChordOverrides, Engine, TIER_NAMES}, extend::{LoadValue, TableLoader}, pairs::Pairs};
+
+//----------------------------------------------------------------------------
+//
+//  Recordings of compositions, for reproducing bugs. If [record] is enabled,
+//  each composition is appended to recording.toml as a [[composition]], with
+//  the state it started in, the events that drove it and what it output.
+//  `ajemi-cli replay` feeds them through the composer again.
+//
+//----------------------------------------------------------------------------
+
+/// Start over once the file gets this large, like the log.
+const MAX_SIZE: u64 = 5 * 1024 * 1024;
+
+/// A composition and the state it started in.
+#[derive(Default, Clone)]
+pub struct Recording {
+    pub time: String,
+    pub schema_order: Vec<String>,
+    pub schema: String,
+    pub long_pi: bool,
+    pub long_glyph: bool,
+    pub auto_close: bool,
+    pub latin_spelling: bool,
+    /// If each tier is suggested, in the order of `TIER_NAMES`.
+    pub tiers: [bool; TIER_NAMES.len()],
+    pub penalty: TierPenalty,
+    pub squote_open: bool,
+    pub dquote_open: bool,
+    /// The closers ahead of the caret, the innermost last.
+    pub closers: String,
+    /// The spacing of the schema.
+    pub spacing: Spacing,
+    /// If the composition directly follows a commit.
+    pub follows_commit: bool,
+    pub chord_overrides: ChordOverrides,
+    pub events: Vec<Event>,
+    pub output: String,
+}
+
+impl Recording {
+    fn capture(composer: &Composer, engine: &Engine, conf: &Config) -> Recording {
+        let names = engine.schema_names();
+        let behavior = &conf.behavior;
+        Recording {
+            time: Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            schema_order: names.iter().map(|name| name.to_string()).collect(),
+            schema: names[engine.current_schema()].to_string(),
+            long_pi: behavior.long_pi,
+            long_glyph: behavior.long_glyph,
+            auto_close: behavior.auto_close,
+            latin_spelling: behavior.latin_spelling,
+            tiers: conf.schema.tiers,
+            penalty: conf.schema.penalty.clone(),
+            squote_open: composer.pairs.squote_open,
+            dquote_open: composer.pairs.dquote_open,
+            closers: composer.pairs.closers.iter().collect(),
+            spacing: engine.spacing(conf),
+            follows_commit: composer.follows_commit,
+            chord_overrides: conf.chord.overrides.clone(),
+            events: Vec::new(),
+            output: String::new(),
+        }
+    }
+
+    /// An engine and a config in the state the composition started in, and a composer to start it over.
+    /// What the recording leaves out is as it is by default.
+    fn restore(&self) -> (Composer, Engine, Config) {
+        let mut conf = Config::default();
+        let behavior = &mut conf.behavior;
+        behavior.long_pi = self.long_pi;
+        behavior.long_glyph = self.long_glyph;
+        behavior.auto_close = self.auto_close;
+        behavior.latin_spelling = self.latin_spelling;
+        conf.schema.tiers = self.tiers;
+        conf.schema.penalty = self.penalty.clone();
+        conf.chord.overrides = self.chord_overrides.clone();
+        conf.spacing.set(&self.schema, self.spacing);
+        let mut engine = Engine::new(&self.schema_order);
+        let current = engine.schema_names().iter().position(|name| *name == self.schema);
+        engine.select_schema(current.unwrap_or(0));
+        let mut composer = Composer::default();
+        composer.follows_commit = self.follows_commit;
+        composer.pairs = Pairs {
+            squote_open: self.squote_open,
+            dquote_open: self.dquote_open,
+            closers: self.closers.chars().collect(),
+            last: None,
+        };
+        (composer, engine, conf)
+    }
+
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        table.insert("time".into(), Value::String(self.time.clone()));
+        table.insert("schema_order".into(), Value::Array(self.schema_order.iter().cloned().map(Value::String).collect()));
+        table.insert("schema".into(), Value::String(self.schema.clone()));
+        table.insert("long_pi".into(), Value::Boolean(self.long_pi));
+        table.insert("long_glyph".into(), Value::Boolean(self.long_glyph));
+        table.insert("auto_close".into(), Value::Boolean(self.auto_close));
+        table.insert("latin_spelling".into(), Value::Boolean(self.latin_spelling));
+        let tiers = TIER_NAMES.iter().zip(self.tiers).filter(|(_, on)| *on);
+        table.insert("tiers".into(), Value::Array(tiers.map(|(name, _)| Value::String(name.to_string())).collect()));
+        let penalty = TIER_NAMES.iter().zip(self.penalty.penalties)
+            .map(|(name, penalty)| (name.to_string(), Value::Integer(penalty.into())))
+            .collect();
+        table.insert("penalty".into(), Value::Table(penalty));
+        table.insert("squote_open".into(), Value::Boolean(self.squote_open));
+        table.insert("dquote_open".into(), Value::Boolean(self.dquote_open));
+        table.insert("closers".into(), Value::String(self.closers.clone()));
+        table.insert("spacing".into(), Value::String(self.spacing.name().to_string()));
+        table.insert("follows_commit".into(), Value::Boolean(self.follows_commit));
+        let overrides: Table = self.chord_overrides.iter()
+            .map(|(keys, spelling)| (keys.to_string(), Value::String(spelling.to_string())))
+            .collect();
+        if !overrides.is_empty() {
+            table.insert("chord_overrides".into(), Value::Table(overrides));
+        }
+        table.insert("events".into(), Value::Array(self.events.iter().map(|event| Value::String(event.to_string())).collect()));
+        table.insert("output".into(), Value::String(self.output.clone()));
+        table
+    }
+}
+
+impl LoadValue for Recording {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        let mut events: Vec<String> = Vec::new();
+        // recordings from before tiers suggested them all
+        let mut tiers: Vec<String> = TIER_NAMES.iter().map(|name| name.to_string()).collect();
+        table.give("time", &mut self.time);
+        table.give("schema_order", &mut self.schema_order);
+        table.give("schema", &mut self.schema);
+        table.give("long_pi", &mut self.long_pi);
+        table.give("long_glyph", &mut self.long_glyph);
+        table.give("auto_close", &mut self.auto_close);
+        table.give("latin_spelling", &mut self.latin_spelling);
+        table.give("tiers", &mut tiers);
+        table.give("penalty", &mut self.penalty);
+        table.give("squote_open", &mut self.squote_open);
+        table.give("dquote_open", &mut self.dquote_open);
+        table.give("closers", &mut self.closers);
+        table.give("spacing", &mut self.spacing);
+        table.give("follows_commit", &mut self.follows_commit);
+        table.give("chord_overrides", &mut self.chord_overrides);
+        table.give("events", &mut events);
+        table.give("output", &mut self.output);
+        table.finish();
+        self.tiers = conf::tiers_named(&tiers, &Problem::join(path, "tiers"), problems);
+        for name in self.schema_order.iter().chain(Some(&self.schema)) {
+            if !engine::schema_exists(name) {
+                problems.push(Problem::error(path, format!("unknown schema {name:?}")));
+            }
+        }
+        self.events.clear();
+        for (index, event) in events.iter().enumerate() {
+            match event.parse() {
+                Ok(event) => self.events.push(event),
+                Err(()) => problems.push(Problem::error(&format!("{}[{index}]", Problem::join(path, "events")),
+                    format!("unknown event {event:?}"))),
+            }
+        }
+    }
+}
+
+impl LoadValue for Vec<Recording> {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Array(recordings) = value else {
+            problems.push(Problem::mistyped(path, "an array of tables", &value));
+            return;
+        };
+        self.clear();
+        for (index, value) in recordings.into_iter().enumerate() {
+            let mut recording = Recording::default();
+            recording.load(value, &format!("{path}[{index}]"), problems);
+            self.push(recording);
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//  Recording.
+//
+//----------------------------------------------------------------------------
+
+/// Records the composition in progress, if any.
+#[derive(Default)]
+pub struct Recorder {
+    current: Option<Recording>,
+}
+
+impl Recorder {
+    /// Does nothing unless [record] is enabled.
+    pub fn start(&mut self, composer: &Composer, engine: &Engine, conf: &Config) {
+        self.current = conf.record.enabled.then(|| Recording::capture(composer, engine, conf));
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        if let Some(recording) = self.current.as_mut() {
+            recording.events.push(event.clone());
+        }
+    }
+
+    pub fn finish(&mut self, output: &str) {
+        let Some(mut recording) = self.current.take() else {
+            return;
+        };
+        recording.output = output.to_string();
+        if let Err(err) = append(&recording) {
+            warn!("Failed to write recording.toml. {err}");
+        }
+    }
+}
+
+fn append(recording: &Recording) -> Result<()> {
+    let dir = conf::dir()?;
+    fs::create_dir_all(&dir)?;
+    let path = dir.join("recording.toml");
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() >= MAX_SIZE) {
+        fs::remove_file(&path)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", to_text(slice::from_ref(recording))?)?;
+    Ok(())
+}
+
+/// The text of recording.toml.
+fn to_text(recordings: &[Recording]) -> Result<String> {
+    let mut table = Table::new();
+    let recordings = recordings.iter().map(|recording| Value::Table(recording.to_table())).collect();
+    table.insert("composition".into(), Value::Array(recordings));
+    Ok(toml::to_string(&table)?)
+}
+
+//----------------------------------------------------------------------------
+//
+//  Replaying.
+//
+//----------------------------------------------------------------------------
+
+/// A recording and what it outputs this time.
+pub struct Replay {
+    pub recording: Recording,
+    pub output: String,
+}
+
+impl Replay {
+    pub fn matches(&self) -> bool {
+        self.output == self.recording.output
+    }
+}
+
+/// Replay the compositions in the text of recording.toml one by one.
+/// Syntax errors and invalid recordings are returned as `Err`.
+pub fn replay(text: &str) -> Result<Vec<Replay>> {
+    let recordings = parse(text)?;
+    Ok(recordings.into_iter().map(|recording| {
+        let output = run(&recording);
+        Replay { recording, output }
+    }).collect())
+}
+
+fn parse(text: &str) -> Result<Vec<Recording>> {
+    let table = text.parse::<Table>()?;
+    let mut problems = Vec::new();
+    let mut recordings = Vec::new();
+    if let Some(mut table) = TableLoader::new(Value::Table(table), "", &mut problems) {
+        table.give("composition", &mut recordings);
+        table.finish();
+    }
+    if problems.is_empty() {
+        Ok(recordings)
+    } else {
+        let problems: Vec<String> = problems.iter().map(|problem| problem.to_string()).collect();
+        Err(anyhow!(problems.join("\n")))
+    }
+}
+
+fn run(recording: &Recording) -> String {
+    let (mut composer, mut engine, mut conf) = recording.restore();
+    for event in &recording.events {
+        let mut effect = composer.handle(event, &mut engine, &conf);
+        if effect == Effect::ToggleLongGlyph {
+            compose::toggle_long_glyph(&mut conf);
+            effect = composer.refresh(&engine, &conf);
+        }
+        if let Effect::Finish(output) | Effect::Abort(output) = effect {
+            return output;
+        }
+    }
+    // cut short, as the composition never finished
+    String::new()
+}
+
+#[test]
+fn test() {
+    let mut composer = Composer::default();
+    composer.follows_commit = true;
+    let mut recording = Recording::capture(&composer, &Engine::new(&[]), &Config::default());
+    recording.schema = "emoji".to_string();
+    recording.long_glyph = true;
+    recording.spacing = Spacing::Ascii;
+    recording.tiers = [true, false, true, true];
+    recording.penalty.penalties = [0, 0, 1, 2];
+    recording.events = ["letter:a", "letter:o", "letter:a", "action:commit"].iter()
+        .map(|event| event.parse().unwrap())
+        .collect();
+    recording.output = run(&recording);
+    assert_eq!(recording.output, " ❌");
+
+    let text = to_text(&[recording.clone(), Recording { output: "?".to_string(), ..recording.clone() }]).unwrap();
+    let replays = replay(&text).unwrap();
+    assert_eq!(replays.len(), 2);
+    assert_eq!(replays[0].output, recording.output);
+    assert!(replays[0].matches());
+    assert!(!replays[1].matches());
+    assert_eq!(replays[1].recording.events, recording.events);
+    assert_eq!(replays[1].recording.tiers, recording.tiers);
+    assert_eq!(replays[1].recording.penalty.penalties, recording.penalty.penalties);
+
+    let err = replay("[[composition]]\nschema = \"toki\"\nevents = [\"letter:a\", \"jump\"]").err().unwrap();
+    assert_eq!(err.to_string().lines().count(), 2);
+}
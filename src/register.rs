@@ -6,9 +6,9 @@ use windows::core::{Result, GUID};
 use windows::Win32::UI::Input::KeyboardAndMouse::{ActivateKeyboardLayout, GetKeyboardLayoutList, GetKeyboardLayoutNameA, KLF_SETFORPROCESS};
 use windows::Win32::UI::TextServices::{self, HKL};
 use windows::Win32::{System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER}, UI::TextServices::{ITfInputProcessorProfiles, CLSID_TF_InputProcessorProfiles, ITfCategoryMgr, CLSID_TF_CategoryMgr}};
-use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+use winreg::enums::HKEY_LOCAL_MACHINE;
 use winreg::RegKey;
-use crate::extend::GUIDExt;
+use crate::{conf::{self, conf}, extend::GUIDExt, ui};
 use crate::{global::*, extend::OsStrExt2};
 use Layout::*;
 
@@ -94,12 +94,10 @@ pub unsafe fn register_ime() -> Result<()> {
     let ime_name: Vec<u16> = OsStr::new(IME_NAME).null_terminated_wchars();
     let icon_file: Vec<u16> = dll_path()?.null_terminated_wchars();
     let icon_index = {
-        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-        let path = "Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize";
-        hkcu.open_subkey(path)
-            .and_then(|subkey| subkey.get_value("SystemUsesLightTheme"))
-            .map(|light_theme: u32| if light_theme == 1 { LITE_TRAY_ICON_INDEX } else { DARK_TRAY_ICON_INDEX })
-            .unwrap_or(LITE_TRAY_ICON_INDEX)
+        // the icon follows the theme, light or dark
+        conf::reload();
+        ui::refresh_system_dark();
+        if conf().color.theme.is_dark(ui::taskbar_dark()) { DARK_TRAY_ICON_INDEX } else { LITE_TRAY_ICON_INDEX }
     };
     input_processor_profiles.AddLanguageProfile(
         &IME_ID, lang_id, &LANG_PROFILE_ID, &ime_name, 
@@ -0,0 +1,349 @@
+use std::{cmp::Reverse, collections::BTreeMap, fmt::Write, fs::{self, OpenOptions}, io::{Read, Seek, Write as _}, path::PathBuf, time::{Duration, Instant}};
+use anyhow::{anyhow, Result};
+use log::warn;
+use parking_lot::Mutex;
+use toml::{Table, Value};
+use crate::{compose::Tally, conf::{self, conf, Problem, Severity}, extend::{LoadValue, TableLoader}};
+
+//----------------------------------------------------------------------------
+//
+//  Typing statistics, kept in stats.toml in %APPDATA%\Ajemi. Only counters
+//  are kept, plus how many times each word is selected if [stats] words is
+//  on. Nothing else that is typed is written. See `ajemi-cli stats`.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Compositions finished. Interrupted ones are not counted.
+    pub compositions: u64,
+    pub keystrokes: u64,
+    /// Words selected, each word of a sentence counted.
+    pub words: u64,
+    pub selections: u64,
+    /// Selections of the 1st suggestion.
+    pub first_selections: u64,
+    /// Compositions with the raw spelling left in the output.
+    pub released: u64,
+    /// Times each word is selected. Sentences are not counted.
+    pub word_counts: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    const EMPTY: Stats = Stats {
+        compositions: 0, keystrokes: 0, words: 0, selections: 0, first_selections: 0, released: 0,
+        word_counts: BTreeMap::new(),
+    };
+
+    pub fn add(&mut self, tally: &Tally, count_words: bool) {
+        self.compositions += 1;
+        self.keystrokes += tally.keystrokes as u64;
+        self.released += tally.released as u64;
+        for selection in &tally.selections {
+            self.words += selection.words as u64;
+            self.selections += 1;
+            self.first_selections += (selection.index == 0) as u64;
+            if count_words && selection.words == 1 {
+                *self.word_counts.entry(selection.output.clone()).or_default() += 1;
+            }
+        }
+    }
+
+    /// Add the counts of another, like the ones kept by another program.
+    pub fn merge(&mut self, other: &Stats) {
+        self.compositions += other.compositions;
+        self.keystrokes += other.keystrokes;
+        self.words += other.words;
+        self.selections += other.selections;
+        self.first_selections += other.first_selections;
+        self.released += other.released;
+        for (word, count) in &other.word_counts {
+            *self.word_counts.entry(word.clone()).or_default() += count;
+        }
+    }
+
+    pub fn keystrokes_per_word(&self) -> Option<f64> {
+        ratio(self.keystrokes, self.words)
+    }
+
+    pub fn first_selection_rate(&self) -> Option<f64> {
+        ratio(self.first_selections, self.selections)
+    }
+
+    pub fn release_rate(&self) -> Option<f64> {
+        ratio(self.released, self.compositions)
+    }
+
+    /// The words selected the most, the most first.
+    pub fn top_words(&self, count: usize) -> Vec<(&str, u64)> {
+        let mut words: Vec<(&str, u64)> = self.word_counts.iter().map(|(word, count)| (word.as_str(), *count)).collect();
+        words.sort_by_key(|(_, count)| Reverse(*count));
+        words.truncate(count);
+        words
+    }
+
+    fn counters(&self) -> [(&'static str, u64); 6] {
+        [
+            ("compositions", self.compositions),
+            ("keystrokes", self.keystrokes),
+            ("words", self.words),
+            ("selections", self.selections),
+            ("first_selections", self.first_selections),
+            ("released", self.released),
+        ]
+    }
+
+    fn rates(&self) -> [(&'static str, Option<f64>); 3] {
+        [
+            ("keystrokes_per_word", self.keystrokes_per_word()),
+            ("first_selection_rate", self.first_selection_rate()),
+            ("release_rate", self.release_rate()),
+        ]
+    }
+
+    /// A summary for people, with the words selected the most.
+    pub fn summary(&self, top: usize) -> String {
+        let percent = |rate: Option<f64>| rate.map_or("-".to_string(), |rate| format!("{:.1}%", rate * 100.0));
+        let mut text = String::new();
+        let _ = writeln!(text, "Compositions:            {}", self.compositions);
+        let _ = writeln!(text, "Keystrokes:              {}", self.keystrokes);
+        let _ = writeln!(text, "Words:                   {}", self.words);
+        let _ = writeln!(text, "Keystrokes per word:     {}",
+            self.keystrokes_per_word().map_or("-".to_string(), |rate| format!("{rate:.2}")));
+        let _ = writeln!(text, "1st suggestion selected: {} ({} of {})",
+            percent(self.first_selection_rate()), self.first_selections, self.selections);
+        let _ = writeln!(text, "Released:                {} ({} of {})",
+            percent(self.release_rate()), self.released, self.compositions);
+        let words = self.top_words(top);
+        if !words.is_empty() {
+            let _ = writeln!(text, "\nMost selected words:");
+            for (word, count) in words {
+                let _ = writeln!(text, "    {word}\t{count}");
+            }
+        }
+        text
+    }
+
+    /// Rows of `kind,name,value`, where the kind is one of counter, rate or word.
+    pub fn to_csv(&self) -> String {
+        let mut text = String::from("kind,name,value\n");
+        for (name, value) in self.counters() {
+            let _ = writeln!(text, "counter,{name},{value}");
+        }
+        for (name, rate) in self.rates() {
+            let _ = writeln!(text, "rate,{name},{}", rate.map_or(String::new(), |rate| rate.to_string()));
+        }
+        for (word, count) in &self.word_counts {
+            let _ = writeln!(text, "word,{},{count}", csv_field(word));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut text = String::from("{\n");
+        for (name, value) in self.counters() {
+            let _ = writeln!(text, "  \"{name}\": {value},");
+        }
+        for (name, rate) in self.rates() {
+            let _ = writeln!(text, "  \"{name}\": {},", rate.map_or("null".to_string(), |rate| rate.to_string()));
+        }
+        let words: Vec<String> = self.word_counts.iter()
+            .map(|(word, count)| format!("{}: {count}", json_string(word)))
+            .collect();
+        let _ = writeln!(text, "  \"word_counts\": {{{}}}", words.join(", "));
+        text.push('}');
+        text
+    }
+
+    fn to_text(&self) -> Result<String> {
+        let mut table = Table::new();
+        for (name, value) in self.counters() {
+            table.insert(name.into(), Value::Integer(value as i64));
+        }
+        if !self.word_counts.is_empty() {
+            let words = self.word_counts.iter().map(|(word, count)| (word.clone(), Value::Integer(*count as i64))).collect();
+            table.insert("word_counts".into(), Value::Table(words));
+        }
+        Ok(toml::to_string(&table)?)
+    }
+}
+
+fn ratio(numerator: u64, denominator: u64) -> Option<f64> {
+    (denominator != 0).then(|| numerator as f64 / denominator as f64)
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn json_string(string: &str) -> String {
+    let mut json = String::from("\"");
+    for ch in string.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            ch if ch.is_control() => { let _ = write!(json, "\\u{:04x}", ch as u32); }
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl LoadValue for Stats {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("compositions", &mut self.compositions);
+        table.give("keystrokes", &mut self.keystrokes);
+        table.give("words", &mut self.words);
+        table.give("selections", &mut self.selections);
+        table.give("first_selections", &mut self.first_selections);
+        table.give("released", &mut self.released);
+        table.give("word_counts", &mut self.word_counts);
+        table.finish();
+    }
+}
+
+impl LoadValue for BTreeMap<String, u64> {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        for (key, value) in table {
+            let mut count = 0;
+            count.load(value, &Problem::join(path, &key), problems);
+            self.insert(key, count);
+        }
+    }
+}
+
+//----------------------------------------------------------------------------
+//
+//  The file. Every program with a text box has its own copy of the text
+//  service, so each keeps its counts in memory for a while and then adds
+//  them to the file, which is locked meanwhile.
+//
+//----------------------------------------------------------------------------
+
+/// The counts not added to the file yet, and when the first of them is counted.
+struct Pending {
+    stats: Stats,
+    since: Option<Instant>,
+}
+
+static PENDING: Mutex<Pending> = Mutex::new(Pending { stats: Stats::EMPTY, since: None });
+/// The counts are added to the file after this many compositions or this long, whichever comes first.
+const FLUSH_COMPOSITIONS: u64 = 20;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn path() -> Result<PathBuf> {
+    Ok(conf::dir()?.join("stats.toml"))
+}
+
+/// Parse the text of stats.toml. Syntax errors and invalid values are returned as `Err`.
+/// Unknown keys, like the ones of later versions, are ignored.
+pub fn parse(text: &str) -> Result<Stats> {
+    let table = text.parse::<Table>()?;
+    let mut stats = Stats::default();
+    let mut problems = Vec::new();
+    stats.load(Value::Table(table), "", &mut problems);
+    let errors: Vec<String> = problems.iter()
+        .filter(|problem| problem.severity == Severity::Error)
+        .map(|problem| problem.to_string())
+        .collect();
+    if errors.is_empty() {
+        Ok(stats)
+    } else {
+        Err(anyhow!(errors.join("\n")))
+    }
+}
+
+/// Count a finished composition, unless [stats] is disabled.
+pub fn count(tally: &Tally) {
+    let (enabled, words) = {
+        let options = &conf().stats;
+        (options.enabled, options.words)
+    };
+    if !enabled {
+        return;
+    }
+    let due = {
+        let mut pending = PENDING.lock();
+        pending.stats.add(tally, words);
+        let since = *pending.since.get_or_insert_with(Instant::now);
+        pending.stats.compositions >= FLUSH_COMPOSITIONS || since.elapsed() >= FLUSH_INTERVAL
+    };
+    if due {
+        flush();
+    }
+}
+
+/// Add the counts kept in memory to stats.toml.
+pub fn flush() {
+    let stats = {
+        let mut pending = PENDING.lock();
+        pending.since = None;
+        std::mem::replace(&mut pending.stats, Stats::EMPTY)
+    };
+    if stats == Stats::EMPTY {
+        return;
+    }
+    if let Err(err) = merge(&stats) {
+        warn!("Failed to update stats.toml. {err}");
+        // try again with the next ones
+        let mut pending = PENDING.lock();
+        pending.stats.merge(&stats);
+        pending.since.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Add to the counts in the file, which is locked against other programs meanwhile.
+fn merge(stats: &Stats) -> Result<()> {
+    fs::create_dir_all(conf::dir()?)?;
+    let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path()?)?;
+    file.lock()?;
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let mut merged = parse(&text)?;
+    merged.merge(stats);
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(merged.to_text()?.as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn test() {
+    use crate::compose::Selection;
+    let selection = |output: &str, index, words| Selection { output: output.to_string(), index, words };
+    let mut stats = Stats::default();
+    stats.add(&Tally { keystrokes: 5, selections: vec![selection("a", 0, 1), selection("b c", 2, 2)], released: false }, true);
+    stats.add(&Tally { keystrokes: 3, selections: vec![selection("a", 0, 1)], released: true }, true);
+    stats.add(&Tally { keystrokes: 4, selections: vec![selection("d", 0, 1)], released: false }, false);
+    assert_eq!(stats.counters(), [
+        ("compositions", 3), ("keystrokes", 12), ("words", 5),
+        ("selections", 4), ("first_selections", 3), ("released", 1),
+    ]);
+    assert_eq!(stats.top_words(5), vec![("a", 2)]);
+    assert_eq!(stats.keystrokes_per_word(), Some(12.0 / 5.0));
+    assert_eq!(Stats::default().release_rate(), None);
+
+    assert_eq!(parse(&stats.to_text().unwrap()).unwrap(), stats);
+    assert!(parse("keystrokes = -1").is_err());
+    assert_eq!(parse("keystrokes = 1\nfuture = true").unwrap().keystrokes, 1);
+    let mut merged = stats.clone();
+    merged.merge(&stats);
+    assert_eq!(merged.counters()[1], ("keystrokes", 24));
+    assert_eq!(merged.top_words(5), vec![("a", 4)]);
+    assert!(stats.to_csv().contains("\ncounter,words,5\n"));
+    assert!(stats.to_csv().ends_with("\nword,a,2\n"));
+    assert!(stats.to_json().contains("\"word_counts\": {\"a\": 2}"));
+    assert_eq!(json_string("\"\n"), r#""\"\u000a""#);
+}
@@ -1,28 +1,32 @@
 use std::ffi::OsString;
 use log::{debug, trace};
-use windows::Win32::Foundation::E_FAIL;
+use windows::Win32::Foundation::{E_FAIL, RECT};
 use windows::Win32::UI::TextServices::{ITfComposition, ITfCompositionSink_Impl};
 use windows::core::Result;
-use crate::PREEDIT_DELIMITER;
-use crate::{extend::OsStrExt2, engine::engine};
+use crate::{compose::{self, Composer, Effect, Event}, conf::{conf, conf_mut}, engine::{engine, engine_mut}, extend::OsStrExt2, keys::Action, pairs::Closing, stats, ui::candidate_list::MouseEvent};
 use super::{edit_session, TextService, TextServiceInner};
 
 //----------------------------------------------------------------------------
 //
 //  Composition is the texts held by the input method waiting to be "composed"
 //  into proper output, or more straightforwardly, those quirky underscored
-//  text.
+//  text. The composing itself is done in compose.rs, while this is where its
+//  effects are reflected in the client.
 //
 //----------------------------------------------------------------------------
 
 impl TextServiceInner {
     // there are only two states: composing or not
     pub fn start_composition(&mut self) -> Result<()> {
+        self.reload_conf()?;
         let composition = edit_session::start_composition(
             self.tid, self.context()?, &self.interface()?)?;
-        self.composition = Some(composition); 
-        if let Some((x, y)) = self.get_pos() {
-            self.candidate_list()?.locate(x, y)?;
+        self.composition = Some(composition);
+        self.composer.follows_commit = self.follows_commit;
+        self.composer.pairs = self.pairs().clone();
+        self.recorder.start(&self.composer, &engine(), &conf());
+        if let Some(caret) = self.get_pos() {
+            self.candidate_list()?.locate(caret)?;
         }
         Ok(())
     }
@@ -33,62 +37,126 @@ impl TextServiceInner {
             let _ = edit_session::end_composition(self.tid, context, composition);
         }
         self.composition = None;
-        self.spelling.clear();
-        self.selected.clear();
-        self.suggestions.clear();
+        self.composer = Composer::default();
         self.candidate_list()?.hide();
         Ok(())
     }
 
-    fn udpate_preedit(&mut self) -> Result<()> {
-        self.preedit.clear();
-        self.preedit.push_str(&self.selected);
-        if self.suggestions.is_empty() {
-            self.preedit.push_str(&self.spelling);
-        } else {
-            let mut from = 0;
-            for to in &self.suggestions[0].groupping {
-                self.preedit.push_str(&self.spelling[from..*to]);
-                self.preedit.push_str(PREEDIT_DELIMITER);
-                from = *to;
+    /// Pass the event to the composer and reflect what it does.
+    /// Calling this while not composing would cause the program to crash.
+    pub fn handle(&mut self, event: Event) -> Result<()> {
+        trace!("handle({event})");
+        let effect = self.composer.handle(&event, &mut engine_mut(), &conf());
+        self.recorder.record(&event);
+        self.apply(effect)
+    }
+
+    fn apply(&mut self, effect: Effect) -> Result<()> {
+        match effect {
+            Effect::None => Ok(()),
+            Effect::Respelled => {
+                self.udpate_preedit()?;
+                self.update_candidate_list()
+            }
+            Effect::CaretMoved => self.udpate_preedit(),
+            Effect::Paged => self.update_candidate_list(),
+            Effect::Finish(text) => {
+                self.recorder.finish(&text);
+                stats::count(&self.composer.tally);
+                self.follows_commit = compose::ends_with_word(&text);
+                *self.pairs() = self.composer.pairs.clone();
+                let closing = self.composer.closing;
+                // the caret goes inside the brackets just closed
+                let caret = match closing {
+                    Closing::Open(closer) => Some(text.encode_utf16().count() - closer.len_utf16()),
+                    _ => None,
+                };
+                self.set_text(&text, caret)?;
+                self.end_composition()?;
+                match closing {
+                    Closing::Over(closer) => self.step_over(closer),
+                    _ => Ok(()),
+                }
             }
-            if from != self.spelling.len() {
-                self.preedit.push_str(&self.spelling[from..])
-            } else {
-                self.preedit.pop();
+            Effect::ToggleLongGlyph => {
+                compose::toggle_long_glyph(&mut conf_mut());
+                let effect = self.composer.refresh(&engine(), &conf());
+                self.apply(effect)
             }
+            Effect::Abort(text) => {
+                self.recorder.finish(&text);
+                self.follows_commit = false;
+                *self.pairs() = self.composer.pairs.clone();
+                let _ = self.set_text(&text, None);
+                self.end_composition()
+            }
+        }
+    }
+
+    // Interupted. Abort everything.
+    pub fn abort(&mut self) -> Result<()> {
+        if self.composition.is_some() {
+            self.handle(Event::Abort)
+        } else {
+            self.end_composition()
+        }
+    }
+
+    /// Clicks and scrolls on the candidate list, which work like number keys and paging keys.
+    pub fn handle_mouse(&mut self, event: MouseEvent) -> Result<()> {
+        if self.composition.is_none() {
+            return Ok(());
         }
+        match event {
+            MouseEvent::Select(index) => self.handle(Event::Click(index)),
+            MouseEvent::NextPage => self.handle(Event::Action(Action::NextPage)),
+            MouseEvent::PreviousPage => self.handle(Event::Action(Action::PreviousPage)),
+        }
+    }
+
+    fn udpate_preedit(&mut self) -> Result<()> {
+        let (preedit, caret) = self.composer.preedit();
         let range = unsafe { self.composition()?.GetRange()? };
-        let text = OsString::from(&self.preedit).wchars();
-        edit_session::set_text(self.tid, self.context()?, range, &text, self.display_attribute.as_ref())
+        let text = OsString::from(&preedit).wchars();
+        edit_session::set_text(self.tid, self.context()?, range, &text, Some(caret), self.display_attribute.as_ref())
     }
 
     fn update_candidate_list(&mut self) -> Result<()> {
         self.assure_candidate_list()?;
         let candidate_list = self.candidate_list()?;
-        if self.suggestions.is_empty() {
+        let candidates = self.composer.candidates();
+        if candidates.is_empty() || conf().layout.hidden {
             candidate_list.hide();
         } else {
-            candidate_list.show(&self.suggestions)?;
-            if let Some((x, y)) = self.get_pos() {
-                candidate_list.locate(x, y)?;
+            candidate_list.show(candidates, self.composer.highlighted_on_page())?;
+            if let Some(caret) = self.get_pos() {
+                candidate_list.locate(caret)?;
             }
         }
         Ok(())
     }
 
 
-    fn set_text(&self, text: &str) -> Result<()> {
+    fn set_text(&self, text: &str, caret: Option<usize>) -> Result<()> {
         let text = OsString::from(text).wchars();
         let range = unsafe { self.composition()?.GetRange()? };
-        edit_session::set_text(self.tid, self.context()?, range, &text, None)
+        edit_session::set_text(self.tid, self.context()?, range, &text, caret, None)
     }
 
-    fn get_pos(&self) -> Option<(i32, i32)> {
+    /// Step over the closer ahead of the caret, or type it if it's not there after all.
+    pub fn step_over(&self, closer: char) -> Result<()> {
+        let text = OsString::from(closer.to_string()).wchars();
+        if !edit_session::step_over(self.tid, self.context()?, &text)? {
+            edit_session::insert_text(self.tid, self.context()?, &text, None, &[])?;
+        }
+        Ok(())
+    }
+
+    fn get_pos(&self) -> Option<RECT> {
         let range = unsafe{ self.composition().ok()?.GetRange().ok()? };
         let pos = edit_session::get_pos(self.tid, self.context().ok()?, &range).ok()?;
-        if pos.0 <= 0 && pos.1 <= 0 {
-            debug!("Abnormal position: ({}, {})", pos.0, pos.1);
+        if pos.left <= 0 && pos.bottom <= 0 {
+            debug!("Abnormal position: ({}, {})", pos.left, pos.bottom);
             None
         } else {
             Some(pos)
@@ -100,122 +168,6 @@ impl TextServiceInner {
     }
 }
 
-// handle input and transit state
-// calling these function while not composing would cause the program to crash
-impl TextServiceInner {
-    pub fn push(&mut self, ch: char) -> Result<()>{
-        self.spelling.push(ch);
-        self.suggestions = engine().suggest(&self.spelling);
-        self.udpate_preedit()?;
-        self.update_candidate_list()?;
-        Ok(())
-    }
-
-    pub fn pop(&mut self) -> Result<()>{
-        // todo pop can be used to revert selection
-        self.spelling.pop();
-        if self.spelling.is_empty() {
-            return self.abort();
-        }
-        self.suggestions = engine().suggest(&self.spelling);
-        self.udpate_preedit()?;
-        self.update_candidate_list()?;
-        Ok(())
-    }
-
-    /// Commit the 1st suggestion, keeping the unrecognizable trailing characters
-    pub fn commit(&mut self) -> Result<()>{
-        if self.suggestions.is_empty() {
-            self.force_release(' ')
-        } else {
-            self.select(0)         
-        }
-    }
-
-    /// Commit the 1st suggestion and release the unrecognizable trailing characters.
-    pub fn force_commit(&mut self, ch: char) -> Result<()>{
-        if self.suggestions.is_empty() {
-            self.force_release(ch)
-        } else {
-            let sugg = self.suggestions.get(0).unwrap();
-            self.selected.push_str(&sugg.output);
-            let last = *sugg.groupping.last().unwrap();
-            if last != self.spelling.len() {
-                self.selected.push(' ');
-                self.selected.push_str(&self.spelling[last..])
-            }
-            self.selected.push(ch);
-            self.set_text(&self.selected)?;
-            self.end_composition()
-        }
-    }
-
-    /// Select the desired suggestion by pressing numbers.
-    pub fn select(&mut self, index: usize) -> Result<()> {
-        if index >= self.suggestions.len() {
-            return Ok(());
-        }
-        let sugg = self.suggestions.get(index).unwrap();
-        let last = *sugg.groupping.last().unwrap();
-        if last == self.spelling.len() {
-            if self.selected.is_empty() {
-                self.set_text(&sugg.output)?;
-            } else {
-                self.selected.push_str(&sugg.output);
-                self.set_text(&self.selected)?;
-            };
-            self.end_composition()
-        } else {
-            self.selected.push_str(&sugg.output);
-            // TODO strip off the begining instead of re allocate
-            self.spelling = self.spelling[last..].to_string(); 
-            self.suggestions = engine().suggest(&self.spelling);
-            self.udpate_preedit()?;
-            self.update_candidate_list()
-        }
-    }
-
-    // Release the raw ascii chars
-    pub fn release(&mut self) -> Result<()> {
-        if self.selected.is_empty() {
-            self.set_text(&self.spelling)?;
-        } else {
-            self.selected.push(' ');
-            self.selected.push_str(&self.spelling);
-            self.set_text(&self.selected)?;
-        }
-        self.end_composition()
-    }
-
-    fn force_release(&mut self, ch: char) -> Result<()> {
-        if self.selected.is_empty() {
-            self.spelling.push(ch);
-            self.set_text(&self.spelling)?;
-        } else {
-            self.selected.push(' ');
-            self.selected.push_str(&self.spelling);
-            self.selected.push(ch);
-            self.set_text(&self.selected)?;
-        }
-        self.end_composition()
-    }
-
-    // Interupted. Abort everything.
-    pub fn abort(&mut self) -> Result<()> {
-        if self.selected.is_empty() {
-            let _ = self.set_text(&self.spelling);
-        } else {
-            if !self.spelling.is_empty() {
-                self.selected.push(' ');
-                self.selected.push_str(&self.spelling);
-            }
-            let _ = self.set_text(&self.selected)?;
-        }
-        self.end_composition()
-    }
-}
-
-
 
 #[allow(non_snake_case)]
 impl ITfCompositionSink_Impl for TextService {
@@ -228,4 +180,4 @@ impl ITfCompositionSink_Impl for TextService {
         // `self.try_lock()` avoids such issue
         self.try_write()?.abort()
     }
-}
\ No newline at end of file
+}
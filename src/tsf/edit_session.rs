@@ -1,9 +1,10 @@
 use std::cell::Cell;
 use std::mem::ManuallyDrop;
+use std::ptr;
 use log::{error, trace};
 use windows::Win32::Foundation::{BOOL, FALSE, RECT, S_OK};
 use windows::core::{Interface, implement, AsImpl, Result, VARIANT};
-use windows::Win32::UI::TextServices::{ITfComposition, ITfCompositionSink, ITfContext, ITfContextComposition, ITfEditSession, ITfEditSession_Impl, ITfInsertAtSelection, ITfRange, GUID_PROP_ATTRIBUTE, TF_AE_NONE, TF_ANCHOR_END, TF_ES_READWRITE, TF_IAS_QUERYONLY, TF_SELECTION, TF_ST_CORRECTION};
+use windows::Win32::UI::TextServices::{ITfComposition, ITfCompositionSink, ITfContext, ITfContextComposition, ITfEditSession, ITfEditSession_Impl, ITfInsertAtSelection, ITfRange, GUID_PROP_ATTRIBUTE, TF_AE_NONE, TF_ANCHOR_END, TF_ANCHOR_START, TF_ES_READWRITE, TF_IAS_QUERYONLY, TF_SELECTION, TF_ST_CORRECTION};
 
 //----------------------------------------------------------------------------
 //
@@ -79,12 +80,14 @@ pub fn end_composition(tid:u32, context: &ITfContext, composition: &ITfCompositi
     }
 }
 
-pub fn set_text(tid:u32, context: &ITfContext, range: ITfRange, text: &[u16], dispaly_attribute: Option<&VARIANT>) -> Result<()> {
+/// `caret` is the offset (in UTF-16 units) to put the cursor at. `None` puts it at the end.
+pub fn set_text(tid:u32, context: &ITfContext, range: ITfRange, text: &[u16], caret: Option<usize>, dispaly_attribute: Option<&VARIANT>) -> Result<()> {
     #[implement(ITfEditSession)]
     struct Session<'a> {
         context: &'a ITfContext,
         range: ITfRange,
         text: &'a [u16],
+        caret: Option<usize>,
         dispaly_attribute: Option<&'a VARIANT>
     }
 
@@ -106,8 +109,16 @@ pub fn set_text(tid:u32, context: &ITfContext, range: ITfRange, text: &[u16], di
                 if self.text.is_empty() {
                     return Ok(())
                 }
-                // move the cursor to the end
-                self.range.Collapse(ec, TF_ANCHOR_END)?;
+                // move the cursor to the end, or to the caret if there's one
+                match self.caret {
+                    Some(caret) if caret < self.text.len() => {
+                        let mut shifted = 0;
+                        self.range.Collapse(ec, TF_ANCHOR_START)?;
+                        self.range.ShiftEnd(ec, caret as i32, &mut shifted, ptr::null())?;
+                        self.range.Collapse(ec, TF_ANCHOR_END)?;
+                    }
+                    _ => self.range.Collapse(ec, TF_ANCHOR_END)?
+                }
                 let mut selection = TF_SELECTION::default();
                 selection.range = ManuallyDrop::new(Some(self.range.clone()));
                 selection.style.ase = TF_AE_NONE;
@@ -118,7 +129,7 @@ pub fn set_text(tid:u32, context: &ITfContext, range: ITfRange, text: &[u16], di
         }
     }
 
-    let session = ITfEditSession::from(Session{context, range, text, dispaly_attribute});
+    let session = ITfEditSession::from(Session{context, range, text, caret, dispaly_attribute});
     unsafe {
         let result = context.RequestEditSession(tid, &session, TF_ES_READWRITE)?;
         if result != S_OK {
@@ -129,11 +140,15 @@ pub fn set_text(tid:u32, context: &ITfContext, range: ITfRange, text: &[u16], di
     }
 }
 
-pub fn insert_text(tid:u32, context: &ITfContext, text: &[u16]) -> Result<()>{
+/// `caret` is the offset (in UTF-16 units) to put the cursor at. `None` puts it at the end.
+/// `replacing` is replaced as well if it's right before the cursor.
+pub fn insert_text(tid:u32, context: &ITfContext, text: &[u16], caret: Option<usize>, replacing: &[u16]) -> Result<()>{
     #[implement(ITfEditSession)]
     struct Session<'a> {
         context: &'a ITfContext,
         text: &'a [u16],
+        caret: Option<usize>,
+        replacing: &'a [u16],
     }
 
     impl ITfEditSession_Impl for Session<'_> {
@@ -142,10 +157,30 @@ pub fn insert_text(tid:u32, context: &ITfContext, text: &[u16]) -> Result<()>{
             unsafe {
                 let range = self.context.cast::<ITfInsertAtSelection>()?
                     .InsertTextAtSelection(ec, TF_IAS_QUERYONLY, &[])?;
+                if !self.replacing.is_empty() {
+                    let before = range.Clone()?;
+                    let mut shifted = 0;
+                    before.Collapse(ec, TF_ANCHOR_START)?;
+                    before.ShiftStart(ec, -(self.replacing.len() as i32), &mut shifted, ptr::null())?;
+                    let mut buf = vec![0; self.replacing.len()];
+                    let mut fetched = 0;
+                    before.GetText(ec, 0, &mut buf, &mut fetched)?;
+                    if buf[..fetched as usize] == *self.replacing {
+                        range.ShiftStartToRange(ec, &before, TF_ANCHOR_START)?;
+                    }
+                }
                 // insert text via InsertTextAtSelection directly would crash the client
                 // what's wrong with these magical APIs
                 range.SetText(ec, TF_ST_CORRECTION, self.text)?;
-                range.Collapse(ec, TF_ANCHOR_END)?;
+                match self.caret {
+                    Some(caret) if caret < self.text.len() => {
+                        let mut shifted = 0;
+                        range.Collapse(ec, TF_ANCHOR_START)?;
+                        range.ShiftEnd(ec, caret as i32, &mut shifted, ptr::null())?;
+                        range.Collapse(ec, TF_ANCHOR_END)?;
+                    }
+                    _ => range.Collapse(ec, TF_ANCHOR_END)?
+                }
                 let mut selection = TF_SELECTION::default();
                 selection.range = ManuallyDrop::new(Some(range.clone()));
                 selection.style.ase = TF_AE_NONE;
@@ -155,7 +190,7 @@ pub fn insert_text(tid:u32, context: &ITfContext, text: &[u16]) -> Result<()>{
         }
     }
 
-    let session = ITfEditSession::from(Session{context, text});
+    let session = ITfEditSession::from(Session{context, text, caret, replacing});
     unsafe {
         let result = context.RequestEditSession(tid, &session, TF_ES_READWRITE)?;
         if result != S_OK {
@@ -166,12 +201,99 @@ pub fn insert_text(tid:u32, context: &ITfContext, text: &[u16]) -> Result<()>{
     }
 }
 
-pub fn get_pos(tid:u32, context: &ITfContext, range: &ITfRange) -> Result<(i32, i32)> {
+/// Up to so many UTF-16 units right before the cursor.
+pub fn text_before(tid:u32, context: &ITfContext, len: usize) -> Result<Vec<u16>> {
+    #[implement(ITfEditSession)]
+    struct Session<'a> {
+        context: &'a ITfContext,
+        text: Cell<Vec<u16>>,   // out
+    }
+
+    impl ITfEditSession_Impl for Session<'_> {
+        #[allow(non_snake_case)]
+        fn DoEditSession(&self, ec:u32) -> Result<()> {
+            unsafe {
+                let range = self.context.cast::<ITfInsertAtSelection>()?
+                    .InsertTextAtSelection(ec, TF_IAS_QUERYONLY, &[])?;
+                let mut text = self.text.take();
+                let mut shifted = 0;
+                range.Collapse(ec, TF_ANCHOR_START)?;
+                range.ShiftStart(ec, -(text.len() as i32), &mut shifted, ptr::null())?;
+                let mut fetched = 0;
+                range.GetText(ec, 0, &mut text, &mut fetched)?;
+                text.truncate(fetched as usize);
+                self.text.set(text);
+                Ok(())
+            }
+        }
+    }
+
+    let session = ITfEditSession::from(Session{context, text: Cell::new(vec![0; len])});
+    unsafe {
+        let result = context.RequestEditSession(tid, &session, TF_ES_READWRITE)?;
+        if result != S_OK {
+            Err(result.into())
+        } else {
+            let session: &Session = session.as_impl();
+            Ok(session.text.take())
+        }
+    }
+}
+
+/// Move the cursor over the text if it's right after the cursor. Returns false if it's not.
+pub fn step_over(tid:u32, context: &ITfContext, text: &[u16]) -> Result<bool> {
+    #[implement(ITfEditSession)]
+    struct Session<'a> {
+        context: &'a ITfContext,
+        text: &'a [u16],
+        stepped: Cell<bool>,   // out
+    }
+
+    impl ITfEditSession_Impl for Session<'_> {
+        #[allow(non_snake_case)]
+        fn DoEditSession(&self, ec:u32) -> Result<()> {
+            unsafe {
+                let range = self.context.cast::<ITfInsertAtSelection>()?
+                    .InsertTextAtSelection(ec, TF_IAS_QUERYONLY, &[])?;
+                let mut shifted = 0;
+                range.Collapse(ec, TF_ANCHOR_END)?;
+                range.ShiftEnd(ec, self.text.len() as i32, &mut shifted, ptr::null())?;
+                let mut buf = vec![0; self.text.len()];
+                let mut fetched = 0;
+                range.GetText(ec, 0, &mut buf, &mut fetched)?;
+                if buf[..fetched as usize] != *self.text {
+                    return Ok(());
+                }
+                range.Collapse(ec, TF_ANCHOR_END)?;
+                let mut selection = TF_SELECTION::default();
+                selection.range = ManuallyDrop::new(Some(range.clone()));
+                selection.style.ase = TF_AE_NONE;
+                selection.style.fInterimChar = FALSE;
+                self.context.SetSelection(ec, &[selection])?;
+                self.stepped.set(true);
+                Ok(())
+            }
+        }
+    }
+
+    let session = ITfEditSession::from(Session{context, text, stepped: Cell::new(false)});
+    unsafe {
+        let result = context.RequestEditSession(tid, &session, TF_ES_READWRITE)?;
+        if result != S_OK {
+            Err(result.into())
+        } else {
+            let session: &Session = session.as_impl();
+            Ok(session.stepped.get())
+        }
+    }
+}
+
+pub fn get_pos(tid:u32, context: &ITfContext, range: &ITfRange) -> Result<RECT> {
     #[implement(ITfEditSession)]
     struct Session<'a> {
         context: &'a ITfContext,
         range: &'a ITfRange,
-        pos: Cell<(i32, i32)>,
+        pos: Cell<RECT>,
     }
 
     impl ITfEditSession_Impl for Session<'_> {
@@ -182,13 +304,13 @@ pub fn get_pos(tid:u32, context: &ITfContext, range: &ITfRange) -> Result<(i32,
                 let mut clipped = BOOL::default();
                 let view = self.context.GetActiveView()?;
                 view.GetTextExt(ec, self.range, &mut rect, &mut clipped)?;
-                self.pos.set((rect.left, rect.bottom));
+                self.pos.set(rect);
                 Ok(())
             }
         }
     }
 
-    let session = ITfEditSession::from(Session{context, range, pos: Cell::new((0, 0))});
+    let session = ITfEditSession::from(Session{context, range, pos: Cell::new(RECT::default())});
     unsafe {
         let result = context.RequestEditSession(tid, &session, TF_ES_READWRITE)?;
         if result != S_OK {
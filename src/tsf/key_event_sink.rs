@@ -1,8 +1,8 @@
-use std::ffi::OsString;
+use std::{ffi::OsString, time::{SystemTime, UNIX_EPOCH}};
 use log::{trace, warn};
-use windows::{core::GUID, Win32::{Foundation::{BOOL, FALSE, LPARAM, TRUE, WPARAM}, UI::{Input::KeyboardAndMouse::{VK_CAPITAL, VK_CONTROL, VK_LCONTROL, VK_LSHIFT, VK_MENU, VK_RCONTROL, VK_RSHIFT, VK_SHIFT}, TextServices::{ITfContext, ITfKeyEventSink_Impl}}}};
+use windows::{core::GUID, Win32::{Foundation::{BOOL, FALSE, LPARAM, TRUE, WPARAM}, UI::{Input::KeyboardAndMouse::{VK_CAPITAL, VK_CONTROL, VK_LCONTROL, VK_LSHIFT, VK_MENU, VK_RCONTROL, VK_RMENU, VK_RSHIFT, VK_SHIFT}, TextServices::{ITfContext, ITfKeyEventSink_Impl}}}};
 use windows::core::Result;
-use crate::{engine::engine, extend::{CharExt, GUIDExt, OsStrExt2, VKExt}};
+use crate::{compose::{self, Event, Input}, conf::{conf, conf_mut}, engine::{engine, engine_mut, Remapped}, extend::{GUIDExt, OsStrExt2, VKExt}, keys::{self, Action, Chord}, pairs::Closing};
 use super::{edit_session, TextService, TextServiceInner};
 use Input::*;
 use Shortcut::*;
@@ -26,21 +26,29 @@ impl ITfKeyEventSink_Impl for TextService {
     /// may be your only chance.
     /// 
     /// `wparam` indicates the key that is pressed.
-    /// The 0-15 bits of `_lparam` indicates the repeat count (ignored here because it's actually always 1). 
+    /// The 0-15 bits of `lparam` indicates the repeat count (ignored here because it's actually always 1). 
+    /// The 16-23 bits of `lparam` is the scan code, which is used by the keymap.
     /// (See https://learn.microsoft.com/en-us/windows/win32/inputdev/wm-keydown for detail).
-    fn OnTestKeyDown(&self, _context: Option<&ITfContext>, wparam: WPARAM, _lparam: LPARAM) -> Result<BOOL> {
+    fn OnTestKeyDown(&self, _context: Option<&ITfContext>, wparam: WPARAM, lparam: LPARAM) -> Result<BOOL> {
         trace!("OnTestKeyDown({:#04X})", wparam.0);
         let mut inner = self.write()?;
-        // disable the IME completly when CapsLock is on
-        if VK_CAPITAL.is_toggled() {
+        inner.track_tapping(wparam.0);
+        // disable the IME completly when CapsLock is on, unless configured otherwise
+        if conf().behavior.caps_lock_ascii && VK_CAPITAL.is_toggled() {
             inner.abort()?;
             return Ok(FALSE);
         }
+        let (key_code, mapped) = translate(wparam, lparam);
         // detect shortcut
-        if let Some(shortcut) = Shortcut::try_from(wparam.0) {
-            return inner.test_shortcut(shortcut);
+        if mapped.is_none() {
+            if let Some(shortcut) = Shortcut::try_from(key_code, &inner) {
+                return inner.test_shortcut(shortcut);
+            }
+        }
+        if !inner.enabled {
+            return Ok(FALSE);
         }
-        let input = Input::from(wparam.0);
+        let input = mapped.unwrap_or_else(|| Input::from(key_code));
         inner.test_input(input)
     }
 
@@ -48,29 +56,64 @@ impl ITfKeyEventSink_Impl for TextService {
     /// The client might call `OnKeyDown` directly without calling `OnTestKeyDown` beforehand.
     /// The client might call `OnKeyDown` even if `OnTestKeyDown` returned `false`.
     /// The client can be an asshole. Remember that.
-    fn OnKeyDown(&self, context: Option<&ITfContext>, wparam: WPARAM, _lparam: LPARAM) -> Result<BOOL> {
+    fn OnKeyDown(&self, context: Option<&ITfContext>, wparam: WPARAM, lparam: LPARAM) -> Result<BOOL> {
         trace!("OnKeyDown({:#04X})", wparam.0);
         let mut inner = self.write()?;
-        if VK_CAPITAL.is_toggled() {
+        inner.track_tapping(wparam.0);
+        if conf().behavior.caps_lock_ascii && VK_CAPITAL.is_toggled() {
+            inner.follows_commit = false;
             inner.abort()?;
             return Ok(FALSE);
         }
-        if let Some(shortcut) = Shortcut::try_from(wparam.0) {
-            return inner.handle_shortcut(shortcut);
+        inner.refresh_context(context);
+        let (key_code, mapped) = translate(wparam, lparam);
+        if mapped.is_none() {
+            if let Some(shortcut) = Shortcut::try_from(key_code, &inner) {
+                return inner.handle_shortcut(shortcut);
+            }
         }
-        let input = Input::from(wparam.0);
-        inner.handle_input(input, context)
+        let eaten = if inner.enabled {
+            let input = mapped.unwrap_or_else(|| Input::from(key_code));
+            inner.handle_input(input, context)?
+        } else {
+            FALSE
+        };
+        // the key goes to the client, which might type or move the caret
+        if !eaten.as_bool() && !keys::is_modifier(wparam.0) {
+            inner.follows_commit = false;
+            inner.pairs().pass_key();
+        }
+        Ok(eaten)
     }
 
-    /// Flip the modifiers back
-    fn OnTestKeyUp(&self, _context: Option<&ITfContext>, wparam: WPARAM, _lparam: LPARAM) -> Result<BOOL> {
+    /// Key-ups are only eaten when a modifier bound to some action is tapped alone,
+    /// or when a key of a chorded stroke is released.
+    fn OnTestKeyUp(&self, _context: Option<&ITfContext>, wparam: WPARAM, lparam: LPARAM) -> Result<BOOL> {
         trace!("OnTestKeyUp({:#04X})", wparam.0);
-        Ok(FALSE)
+        let inner = self.write()?;
+        if inner.chord_key(wparam, lparam).is_some() {
+            return Ok(TRUE);
+        }
+        Ok(inner.tapped(wparam.0).is_some().into())
     }
 
-    fn OnKeyUp(&self, _context: Option<&ITfContext>, wparam: WPARAM, _lparam: LPARAM) -> Result<BOOL> {
+    fn OnKeyUp(&self, context: Option<&ITfContext>, wparam: WPARAM, lparam: LPARAM) -> Result<BOOL> {
         trace!("OnKeyUp({:#04X})", wparam.0);
-        Ok(FALSE)
+        let mut inner = self.write()?;
+        if let Some(key) = inner.chord_key(wparam, lparam) {
+            return inner.release_chord_key(key, context);
+        }
+        let tapped = inner.tapped(wparam.0);
+        if inner.tapping.is_some_and(|(key, _)| key == wparam.0) {
+            inner.tapping = None;
+        }
+        match tapped {
+            Some(action) => {
+                inner.refresh_context(context);
+                inner.handle_shortcut(Bound(action))
+            }
+            None => Ok(FALSE)
+        }
     }
 
     /// I 've never seen this thing called.
@@ -82,43 +125,62 @@ impl ITfKeyEventSink_Impl for TextService {
     fn OnSetFocus(&self, foreground:BOOL) -> Result<()> {
         trace!("OnSetFocus({})", foreground.as_bool());
         if !foreground.as_bool() {
-            self.write()?.abort()
+            let mut inner = self.write()?;
+            inner.chord.reset();
+            inner.follows_commit = false;
+            inner.abort()
         } else {
             Ok(())
         }
     }
 }
 
+/// Keys assigned in the keymap are translated to the inputs they stand for, unless Ctrl or Alt is held.
+/// An assigned space is translated to the space key instead, so that it does whatever space is bound to.
+fn translate(wparam: WPARAM, lparam: LPARAM) -> (usize, Option<Input>) {
+    if VK_CONTROL.is_down() || VK_MENU.is_down() {
+        return (wparam.0, None);
+    }
+    let extended = if (lparam.0 >> 24) & 1 == 1 { 0xE000 } else { 0 };
+    let scan_code = ((lparam.0 >> 16) & 0xFF) as u16 | extended;
+    match conf().keymap.get(scan_code) {
+        None => (wparam.0, None),
+        Some(' ') => (0x20, None),
+        Some(input @ ('a' | 'e' | 'i' | 'o' | 'u')) => (wparam.0, Some(Letter(input))),
+        Some(input) => (wparam.0, Some(Punct(input))),
+    }
+}
+
+/// Milliseconds since the epoch, for timing chorded strokes.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 #[derive(Debug)]
 enum Shortcut {
-    NextSchema,
+    Bound(Action),
     Undefine,
 }
 
 impl Shortcut {
-    fn try_from(key_code: usize) -> Option<Shortcut> {
+    /// Key bindings are defined in conf.toml. Actions that can't be done in the 
+    /// current state fall back to inputs, unless Ctrl or Alt is held.
+    fn try_from(key_code: usize, inner: &TextServiceInner) -> Option<Shortcut> {
         let ctrl = VK_CONTROL.is_down() || VK_LCONTROL.is_down() || VK_RCONTROL.is_down();
         let alt = VK_MENU.is_down();
         let shift = VK_SHIFT.is_down() || VK_LSHIFT.is_down() || VK_RSHIFT.is_down();
-        match (ctrl, alt, shift, key_code) {
-            (true, false, true, 0x4E) => Some(NextSchema), // Ctrl + Shift + N
-            (true, ..) | (_, true, ..) => Some(Undefine),
+        let chord = Chord { ctrl, alt, shift, key: key_code };
+        match conf().keys.action_of(chord) {
+            Some(action) if inner.accepts(action) => Some(Bound(action)),
+            _ if ctrl || alt => Some(Undefine),
             _ => None,
         }
     }
 }
 
-
-/// Inputs that are easier to understand and handle.
 /// See https://learn.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes for keycodes.
-#[derive(Debug)]
-enum Input {
-    Letter(char), Number(usize), Punct(char),
-    Space, Backspace, Enter, Tab,
-    Left, Up, Right, Down,
-    Unknown(usize)
-}
-
 impl Input {
     fn from(key_code: usize) -> Input {
         fn offset(key_code: usize, from: usize ) -> u8 {
@@ -180,11 +242,15 @@ impl Input {
             (0x08, _    ) => Backspace,
             (0x09, _    ) => Tab,
             (0x0D, _    ) => Enter,
+            (0x1B, _    ) => Escape,
             (0x20, _    ) => Space,
+            (0x23, _    ) => End,
+            (0x24, _    ) => Home,
             (0x25, _    ) => Left,
             (0x26, _    ) => Up,
             (0x27, _    ) => Right,
             (0x28, _    ) => Down,
+            (0x2E, _    ) => Delete,
             _ => Unknown(key_code)
         }
     }
@@ -217,81 +283,166 @@ impl TextServiceInner {
             return Ok(FALSE);
         };
         self.context = Some(context.clone());
+        // in chorded input, vowels are held until the stroke is complete
+        if let Letter(letter @ ('a' | 'e' | 'i' | 'o' | 'u')) = input {
+            if conf().chord.enabled {
+                self.chord.key_down(letter, now());
+                return Ok(TRUE);
+            }
+        }
         if self.composition.is_none() {
             match input {
                 // letters start compositions. punctuators need to be re-mapped.
                 Letter(letter) => {
                     self.start_composition()?;
-                    self.push(letter)?
+                    self.handle(Event::Input(Letter(letter)))?
                 },
-                Punct(punct) => {
-                    self.insert_char(engine().remap_punct(punct))?
-                },
-                Space => {
-                    self.insert_char(engine().remap_punct(' '))?
-                }
+                Punct(punct) => self.insert_punct(punct)?,
+                Space => self.insert_punct(' ')?,
                 _ => {return Ok(FALSE)}
             }
         } else {
             match input {
-                Letter(letter) => self.push(letter)?,
-                Number(0) => (),
-                Number(number) => self.select(number - 1)?,
-                Punct(punct) => {
-                    let remmaped = engine().remap_punct(punct);
-                    if remmaped.is_joiner() {
-                        self.push(punct)?;
-                    } else {
-                        self.force_commit(remmaped)?;
-                    }
-                },
-                Space => self.commit()?,
-                Enter => self.release()?,
-                Backspace => self.pop()?,
-                Tab => {
-                    self.push(' ')?;
-                    self.release()?
-                } 
-                // disable cursor movement because I am lazy.
-                Left | Up | Right | Down => (),
-                Unknown(_) => {
-                    return Ok(FALSE);
-                }
+                Unknown(_) => return Ok(FALSE),
+                input => self.handle(Event::Input(input))?,
             }
         }
         return Ok(TRUE);
     }
 
-    fn insert_char(&mut self, ch: char) -> Result<()> {
+    /// The vowel being released if it's part of a chorded stroke.
+    fn chord_key(&self, wparam: WPARAM, lparam: LPARAM) -> Option<char> {
+        let (key_code, mapped) = translate(wparam, lparam);
+        match mapped.unwrap_or_else(|| Input::from(key_code)) {
+            Letter(letter) if self.chord.is_holding(letter) => Some(letter),
+            _ => None
+        }
+    }
+
+    fn release_chord_key(&mut self, key: char, context: Option<&ITfContext>) -> Result<BOOL> {
+        let Some(stroke) = self.chord.key_up(key, now()) else {
+            return Ok(TRUE);
+        };
+        trace!("push_stroke({:?})", stroke);
+        let Some(context) = context else {
+            warn!("Context is None");
+            return Ok(TRUE);
+        };
+        self.context = Some(context.clone());
+        if self.composition.is_none() {
+            self.start_composition()?;
+        }
+        self.handle(Event::Stroke(stroke.keys))?;
+        Ok(TRUE)
+    }
+
+    /// Insert the punctuator as it's remapped, while not composing.
+    fn insert_punct(&mut self, punct: char) -> Result<()> {
+        let after = edit_session::text_before(self.tid, self.context()?, 2).ok()
+            .and_then(|text| char::decode_utf16(text).filter_map(|ch| ch.ok()).last());
+        let spacing = engine().spacing(&conf());
+        let Remapped { output, replaces } = engine().remap_punct(punct, self.pairs(), false, after, spacing);
+        // sentence punctuators are separated like words, unless they replace one
+        let separator = match output.starts_with(compose::is_sentence_punct) && self.follows_commit && replaces.is_none() {
+            true => spacing.separator(),
+            false => "",
+        };
+        let auto_close = conf().behavior.auto_close;
+        match self.pairs().close(&output, auto_close) {
+            Closing::Over(closer) => self.step_over(closer)?,
+            Closing::Open(closer) => self.insert_text(separator, &output, Some(closer), replaces.as_deref())?,
+            Closing::None => self.insert_text(separator, &output, None, replaces.as_deref())?,
+        }
+        self.follows_commit = output.ends_with(compose::is_sentence_punct);
+        Ok(())
+    }
+
+    /// Insert the text after the prefix, and the closer if any after the caret.
+    /// What it replaces is removed if it's right before the caret.
+    fn insert_text(&mut self, prefix: &str, text: &str, closer: Option<char>, replaces: Option<&str>) -> Result<()> {
         self.char_buf.clear();
-        self.char_buf.push(ch);
+        self.char_buf.push_str(prefix);
+        self.char_buf.push_str(text);
+        let caret = self.char_buf.encode_utf16().count();
+        self.char_buf.extend(closer);
         let text = OsString::from(&self.char_buf).wchars();
-        edit_session::insert_text(self.tid, self.context()?, &text)
+        let replaces = OsString::from(replaces.unwrap_or_default()).wchars();
+        edit_session::insert_text(self.tid, self.context()?, &text, Some(caret), &replaces)
+    }
+
+    /// Remember the modifier being pressed, so that a lone tap of it can be told on key-up.
+    /// Pressing any other key makes it no longer a lone tap.
+    fn track_tapping(&mut self, key_code: usize) {
+        if !keys::is_modifier(key_code) {
+            self.tapping = None;
+            return;
+        }
+        if self.tapping.is_some_and(|(key, _)| key == key_code) {
+            // repeated key-downs from holding the modifier
+            return;
+        }
+        let sided = match key_code {
+            0x10 => if VK_RSHIFT.is_down() { 0xA1 } else { 0xA0 },
+            0x11 => if VK_RCONTROL.is_down() { 0xA3 } else { 0xA2 },
+            0x12 => if VK_RMENU.is_down() { 0xA5 } else { 0xA4 },
+            _ => key_code
+        };
+        self.tapping = Some((key_code, sided));
+    }
+
+    /// The action bound to the modifier if it's tapped alone.
+    fn tapped(&self, key_code: usize) -> Option<Action> {
+        let (key, sided) = self.tapping?;
+        if key != key_code {
+            return None;
+        }
+        let lone = |key| Chord { ctrl: false, alt: false, shift: false, key };
+        conf().keys.action_of(lone(sided)).or_else(|| conf().keys.action_of(lone(key)))
+            .filter(|action| self.accepts(*action))
+    }
+
+    /// If the action can be done in the current state.
+    fn accepts(&self, action: Action) -> bool {
+        use Action::*;
+        if !self.enabled {
+            return action == ToggleIme;
+        }
+        match action {
+            NextSchema | PreviousSchema | SelectSchema(_) | ToggleLongGlyph | ToggleIme => true,
+            Commit | Release | ReleaseWithSpace | DeleteSegment | NextPage | PreviousPage | CycleCandidate => self.composition.is_some(),
+        }
     }
 
     fn test_shortcut(&self, shortcut: Shortcut) -> Result<BOOL> {
-        if self.composition.is_none() {
-            match shortcut {
-                NextSchema => Ok(TRUE),
-                _ => Ok(FALSE),
-            }
-        } else {
-            Ok(FALSE)
+        match shortcut {
+            Bound(_) => Ok(TRUE),
+            Undefine => Ok(FALSE),
         }
     }
 
-    fn handle_shortcut(&self, shortcut: Shortcut) -> Result<BOOL> {
-        if self.composition.is_none() {
-            match shortcut {
-                NextSchema => {    
-                    engine().next_schema();
-                    Ok(TRUE)
-                }
-                _ => Ok(FALSE),
-            }
-        } else {
-            Ok(FALSE)
+    /// Keep the context of the key event, so that shortcuts go to the pairs of the document typed in.
+    /// The one of a composition in progress is kept.
+    fn refresh_context(&mut self, context: Option<&ITfContext>) {
+        if let (Some(context), None) = (context, &self.composition) {
+            self.context = Some(context.clone());
         }
     }
-}
 
+    fn handle_shortcut(&mut self, shortcut: Shortcut) -> Result<BOOL> {
+        let Bound(action) = shortcut else {
+            return Ok(FALSE);
+        };
+        trace!("handle_action({:?})", action);
+        // a punctuator typed after a shortcut is no double tap
+        self.pairs().last = None;
+        use Action::*;
+        match action {
+            ToggleIme => self.set_enabled(!self.enabled)?,
+            // the composer switches schemas and behaviors itself and suggests again
+            _ if self.composition.is_some() => self.handle(Event::Action(action))?,
+            ToggleLongGlyph => compose::toggle_long_glyph(&mut conf_mut()),
+            _ => { compose::switch(action, self.pairs(), &mut engine_mut()); }
+        }
+        Ok(TRUE)
+    }
+}
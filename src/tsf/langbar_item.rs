@@ -1,8 +1,9 @@
-use log::trace;
+use log::{debug, trace};
 use windows::Win32::Foundation::{POINT, RECT, BOOL};
-use windows::Win32::UI::WindowsAndMessaging::HICON;
-use windows::core::{Result, BSTR};
-use windows::Win32::UI::TextServices::{ITfLangBarItemButton_Impl, ITfMenu, TfLBIClick, ITfLangBarItem_Impl, TF_LANGBARITEMINFO, TF_LBI_STYLE_BTN_BUTTON};
+use windows::Win32::System::Ole::{CONNECT_E_ADVISELIMIT, CONNECT_E_CANNOTCONNECT, CONNECT_E_NOCONNECTION};
+use windows::Win32::UI::WindowsAndMessaging::{CopyIcon, HICON};
+use windows::core::{Interface, IUnknown, Result, BSTR, GUID};
+use windows::Win32::UI::TextServices::{ITfLangBarItemButton_Impl, ITfLangBarItemSink, ITfMenu, ITfSource_Impl, TfLBIClick, ITfLangBarItem_Impl, TF_LANGBARITEMINFO, TF_LBI_STYLE_BTN_BUTTON};
 use crate::{IME_ID, LANGBAR_ITEM_ID};
 
 use super::TextService;
@@ -46,9 +47,39 @@ impl ITfLangBarItemButton_Impl for TextService {
         Ok(())
     }
     fn GetIcon(&self) -> Result<HICON> {
-        Ok(self.write()?.icon)
+        // the caller destroys the icon
+        unsafe { CopyIcon(self.langbar.lock().icon) }
     }
     fn GetText(&self) -> Result<BSTR> {
         Ok(BSTR::default())
     }
-}
\ No newline at end of file
+}
+
+// The language bar advises a sink to be told when the icon changes.
+// There's only one language bar, so there's only one sink.
+const SINK_COOKIE: u32 = 1;
+
+#[allow(non_snake_case)]
+impl ITfSource_Impl for TextService {
+    fn AdviseSink(&self, riid: *const GUID, punk: Option<&IUnknown>) -> Result<u32> {
+        if unsafe { *riid } != ITfLangBarItemSink::IID {
+            return Err(CONNECT_E_CANNOTCONNECT.into());
+        }
+        let sink: ITfLangBarItemSink = punk.ok_or(CONNECT_E_CANNOTCONNECT)?.cast()?;
+        let mut langbar = self.langbar.lock();
+        if langbar.sink.is_some() {
+            return Err(CONNECT_E_ADVISELIMIT.into());
+        }
+        langbar.sink = Some(sink);
+        debug!("Added langbar item sink.");
+        Ok(SINK_COOKIE)
+    }
+    fn UnadviseSink(&self, cookie: u32) -> Result<()> {
+        let mut langbar = self.langbar.lock();
+        if cookie != SINK_COOKIE || langbar.sink.take().is_none() {
+            return Err(CONNECT_E_NOCONNECTION.into());
+        }
+        debug!("Removed langbar item sink.");
+        Ok(())
+    }
+}
@@ -7,12 +7,13 @@ mod edit_session;
 mod langbar_item;
 
 
+use std::{collections::HashMap, env};
 use std::time::{Instant, Duration};
-use parking_lot::{RwLock, RwLockWriteGuard};
-use log::{debug, error, warn};
+use parking_lot::{Mutex, RwLock, RwLockWriteGuard};
+use log::{debug, error, info, warn};
 
-use windows::{core::{Interface, implement, AsImpl, Result, VARIANT}, Win32::{Foundation::E_FAIL, UI::{TextServices::{ITfComposition, ITfCompositionSink, ITfContext, ITfDisplayAttributeProvider, ITfKeyEventSink, ITfLangBarItem, ITfTextInputProcessor, ITfTextInputProcessorEx, ITfThreadMgr, ITfThreadMgrEventSink}, WindowsAndMessaging::HICON}}};
-use crate::{engine::Suggestion, ui::candidate_list::CandidateList};
+use windows::{core::{Interface, implement, AsImpl, Result, VARIANT}, Win32::{Foundation::{E_FAIL, HWND}, UI::{TextServices::{ITfComposition, ITfCompartmentMgr, ITfCompositionSink, ITfContext, ITfDisplayAttributeProvider, ITfDocumentMgr, ITfKeyEventSink, ITfLangBarItem, ITfLangBarItemButton, ITfLangBarItemSink, ITfSource, ITfTextInputProcessor, ITfTextInputProcessorEx, ITfThreadMgr, ITfThreadMgrEventSink, GUID_COMPARTMENT_KEYBOARD_OPENCLOSE, TF_LBI_ICON}, WindowsAndMessaging::{DestroyIcon, GetAncestor, GetClassNameW, IsWindow, PrivateExtractIconsW, GA_ROOT, HICON}}}};
+use crate::{conf::{self, conf}, compose::Composer, engine::{engine_mut, ChordResolver}, extend::OsStrExt2, global, pairs::Pairs, profile::App, record::Recorder, ui::{self, candidate_list::CandidateList}, DARK_TRAY_ICON_INDEX, LITE_TRAY_ICON_INDEX};
 
 //----------------------------------------------------------------------------
 //
@@ -30,6 +31,8 @@ use crate::{engine::Suggestion, ui::candidate_list::CandidateList};
     ITfKeyEventSink,
     ITfCompositionSink,
     ITfLangBarItem,
+    ITfLangBarItemButton,
+    ITfSource,
     ITfDisplayAttributeProvider
 )]
 
@@ -38,6 +41,13 @@ use crate::{engine::Suggestion, ui::candidate_list::CandidateList};
 /// inputs from users can be frequent. 
 pub struct TextService {
     inner: RwLock<TextServiceInner>,
+    // TSF asks for the icon while the inner lock is held, when the item is added.
+    langbar: Mutex<Langbar>,
+}
+/// The icon on the language bar, and the sink to tell when it's changed.
+struct Langbar {
+    icon: HICON,
+    sink: Option<ITfLangBarItemSink>,
 }
 struct TextServiceInner {
     // Some basic info about the clinet (the program where user is typing)
@@ -48,17 +58,25 @@ struct TextServiceInner {
     cookie: Option<u32>,
     // KeyEventSink
     char_buf: String,
+    enabled: bool,
+    tapping: Option<(usize, usize)>,
+    chord: ChordResolver,
+    // the IME can be turned on and off for each window
+    window: HWND,
+    window_states: HashMap<isize, bool>,
     // Composition
     composition: Option<ITfComposition>,
-    spelling: String,
-    selected: String,
-    suggestions: Vec<Suggestion>,
-    preedit: String,
+    composer: Composer,
+    // if the last thing typed is a commit, so that the next one is separated from it
+    follows_commit: bool,
+    // the quotes and brackets of each document, by the address of its context, which is held
+    // on to so that the address can't be taken by another context while the entry is around
+    pairs: HashMap<usize, (Option<ITfContext>, Pairs)>,
+    recorder: Recorder,
     // display attribute provider
     display_attribute: Option<VARIANT>,
     // UI
     candidate_list: Option<CandidateList>,
-    icon: HICON,
     // An Arc-like smart pointer pointing to TextService
     interface: Option<ITfTextInputProcessor>,
 }
@@ -70,19 +88,24 @@ impl TextService {
             thread_mgr: None,
             context: None,
             char_buf: String::with_capacity(4),
+            enabled: true,
+            tapping: None,
+            chord: ChordResolver::default(),
+            window: HWND::default(),
+            window_states: HashMap::new(),
             cookie: None,
             composition: None,
-            spelling: String::with_capacity(32),
-            suggestions: Vec::new(),
-            selected: String::with_capacity(32),
-            preedit: String::with_capacity(32),
-            icon: HICON::default(),
+            composer: Composer::default(),
+            follows_commit: false,
+            pairs: HashMap::new(),
+            recorder: Recorder::default(),
             candidate_list: None,
             display_attribute: None,
             interface: None,
         };
         let text_service = TextService{
-            inner: RwLock::new(inner)
+            inner: RwLock::new(inner),
+            langbar: Mutex::new(Langbar { icon: HICON::default(), sink: None }),
         };
         // from takes ownership of the object and returns a smart pointer
         let interface = ITfTextInputProcessor::from(text_service);
@@ -129,6 +152,13 @@ impl TextServiceInner {
         })
     }
 
+    /// The paired punctuators of the document being typed in.
+    fn pairs(&mut self) -> &mut Pairs {
+        let key = self.context.as_ref().map_or(0, |context| context.as_raw() as usize);
+        let context = &self.context;
+        &mut self.pairs.entry(key).or_insert_with(|| (context.clone(), Pairs::default())).1
+    }
+
     fn candidate_list(&self) -> Result<&CandidateList> {
         self.candidate_list.as_ref().ok_or(E_FAIL.into())
     }
@@ -137,10 +167,105 @@ impl TextServiceInner {
         let parent_window = unsafe{ 
             self.thread_mgr()?.GetFocus()?.GetTop()?.GetActiveView()?.GetWnd()? 
         };
-        self.candidate_list = Some(CandidateList::create(parent_window)?);
+        // the interface keeps the text service alive until the candidate list is destroyed
+        let interface: ITfTextInputProcessor = self.interface()?;
+        let on_mouse = move |event| {
+            let text_service: &TextService = unsafe { interface.as_impl() };
+            if let Err(e) = text_service.write().and_then(|mut inner| inner.handle_mouse(event)) {
+                warn!("Failed to handle {event:?}. {e}");
+            }
+        };
+        self.candidate_list = Some(CandidateList::create(parent_window, on_mouse)?);
+        Ok(())
+    }
+
+    /// Turn the IME on or off. The state is reflected by the input indicator of the OS.
+    fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+        if !enabled && self.composition.is_some() {
+            self.abort()?;
+        }
+        self.enabled = enabled;
+        let value = VARIANT::from(enabled as i32);
+        let result = unsafe {
+            self.thread_mgr()?.cast::<ITfCompartmentMgr>()?
+                .GetCompartment(&GUID_COMPARTMENT_KEYBOARD_OPENCLOSE)?
+                .SetValue(self.tid, &value)
+        };
+        if let Err(e) = result {
+            warn!("Failed to update the open/close compartment. {e}");
+        }
         Ok(())
     }
 
+    /// Remember the on/off state of the previously focused window and restore the one of the newly focused window.
+    fn switch_window(&mut self, focus: Option<&ITfDocumentMgr>) -> Result<()> {
+        let window = focus.and_then(|doc_mgr| unsafe {
+            doc_mgr.GetTop().ok()?.GetActiveView().ok()?.GetWnd().ok()
+        }).unwrap_or_default();
+        if window == self.window {
+            return Ok(());
+        }
+        // the states of the windows closed since are dropped
+        self.window_states.retain(|window, _| unsafe { IsWindow(HWND(*window)) }.as_bool());
+        self.window_states.insert(self.window.0, self.enabled);
+        self.window = window;
+        if conf::use_profile(app_of(window)) {
+            self.apply_conf()?;
+        }
+        let enabled = self.window_states.get(&window.0).cloned().unwrap_or(conf().behavior.enabled);
+        self.set_enabled(enabled)
+    }
+
+    /// Apply the changes in conf.toml and the light/dark setting of the system.
+    fn reload_conf(&mut self) -> Result<()> {
+        if ui::refresh_system_dark() {
+            self.update_icon();
+        }
+        if !conf::reload() {
+            return Ok(());
+        }
+        info!("Reloaded conf.toml.");
+        self.apply_conf()
+    }
+
+    /// Apply the config after it's replaced. The candidate list is recreated for the fonts.
+    fn apply_conf(&mut self) -> Result<()> {
+        engine_mut().order_schemas(&conf().schema.order);
+        self.update_icon();
+        if let Some(candidate_list) = self.candidate_list.take() {
+            candidate_list.destroy()?;
+        }
+        self.create_candidate_list()
+    }
+
+    /// The icon follows the theme, light or dark, against the taskbar.
+    fn update_icon(&mut self) {
+        let dark = conf().color.theme.is_dark(ui::taskbar_dark());
+        let index = if dark { DARK_TRAY_ICON_INDEX } else { LITE_TRAY_ICON_INDEX };
+        let icon = match load_icon(index) {
+            Ok(icon) => icon,
+            Err(e) => {
+                warn!("Failed to load the icon. {e}");
+                return;
+            }
+        };
+        let text_service: &TextService = unsafe { self.interface.as_ref().unwrap().as_impl() };
+        let sink = {
+            let mut langbar = text_service.langbar.lock();
+            if !langbar.icon.is_invalid() {
+                let _ = unsafe { DestroyIcon(langbar.icon) };
+            }
+            langbar.icon = icon;
+            langbar.sink.clone()
+        };
+        // the sink asks for the icon right away
+        if let Some(sink) = sink {
+            if let Err(e) = unsafe { sink.OnUpdate(TF_LBI_ICON) } {
+                warn!("Failed to update the langbar item. {e}");
+            }
+        }
+    }
+
     fn assure_candidate_list(&mut self) -> Result<()>{
         if self.candidate_list.is_some() {
             return Ok(());
@@ -152,6 +277,31 @@ impl TextServiceInner {
 
 }
 
+/// Load the icon from the resources of the dll.
+fn load_icon(index: u32) -> Result<HICON> {
+    let mut file = [0u16; 260];
+    let path = global::dll_path()?.wchars();
+    let len = usize::min(path.len(), file.len() - 1);
+    file[..len].copy_from_slice(&path[..len]);
+    let mut icons = [HICON::default()];
+    let count = unsafe { PrivateExtractIconsW(&file, index as i32, 16, 16, Some(&mut icons), None, 0) };
+    if count == 0 || icons[0].is_invalid() {
+        return Err(E_FAIL.into());
+    }
+    Ok(icons[0])
+}
+
+/// The program the window belongs to. The IME lives in the process of the program.
+fn app_of(window: HWND) -> App {
+    let exe = env::current_exe().ok()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .unwrap_or_default();
+    let mut buf = [0u16; 256];
+    let len = unsafe { GetClassNameW(GetAncestor(window, GA_ROOT), &mut buf) };
+    let class = String::from_utf16_lossy(&buf[..len.max(0) as usize]);
+    App { exe, class }
+}
+
 //----------------------------------------------------------------------------
 //
 //  Now see tsf/text_input_processor.rs for the implementation.
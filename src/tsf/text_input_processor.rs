@@ -1,9 +1,9 @@
 use log::{trace, debug, warn};
 use windows::Win32::Foundation::E_FAIL;
 use windows::Win32::System::Com::{CoCreateInstance, CLSCTX_INPROC_SERVER};
-use windows::Win32::UI::TextServices::{ CLSID_TF_CategoryMgr, ITfCategoryMgr, ITfKeyEventSink, ITfKeystrokeMgr, ITfSource, ITfTextInputProcessorEx_Impl, ITfTextInputProcessor_Impl, ITfThreadMgr, ITfThreadMgrEventSink};
+use windows::Win32::UI::TextServices::{ CLSID_TF_CategoryMgr, ITfCategoryMgr, ITfKeyEventSink, ITfKeystrokeMgr, ITfLangBarItem, ITfLangBarItemMgr, ITfSource, ITfTextInputProcessorEx_Impl, ITfTextInputProcessor_Impl, ITfThreadMgr, ITfThreadMgrEventSink};
 use windows::core::{Interface, Result, VARIANT};
-use crate::{conf, DISPLAY_ATTR_ID};
+use crate::{conf::{self, conf}, engine::engine_mut, stats, ui, DISPLAY_ATTR_ID};
 
 use super::TextService;
 
@@ -11,7 +11,6 @@ use super::TextService;
 impl ITfTextInputProcessor_Impl for TextService {
     fn Activate(&self, thread_mgr: Option<&ITfThreadMgr>, tid: u32) -> Result<()> {
         trace!("Activate({tid})");
-        conf::reload();
         let mut inner = self.write()?;
         let thread_mgr = thread_mgr.ok_or(E_FAIL)?;
         inner.tid = tid;
@@ -24,10 +23,17 @@ impl ITfTextInputProcessor_Impl for TextService {
             inner.cookie = Some(thread_mgr.cast::<ITfSource>()?.AdviseSink(
                 &ITfThreadMgrEventSink::IID, &inner.interface::<ITfThreadMgrEventSink>()?)?);
             debug!("Added thread manager event sink.");
+            if conf::reload() {
+                engine_mut().order_schemas(&conf().schema.order);
+            }
+            ui::refresh_system_dark();
+            inner.update_icon();
             let _ = inner.create_candidate_list();
-            // thread_mgr.cast::<ITfLangBarItemMgr>()?.AddItem(
-            //     &inner.interface::<ITfLangBarItem>()?)?;
-            // debug!("Added langbar item.");
+            let enabled = inner.enabled;
+            inner.set_enabled(enabled)?;
+            thread_mgr.cast::<ITfLangBarItemMgr>()?.AddItem(
+                &inner.interface::<ITfLangBarItem>()?)?;
+            debug!("Added langbar item.");
             if inner.display_attribute.is_none() {
                 let category_mgr: ITfCategoryMgr = CoCreateInstance(
                     &CLSID_TF_CategoryMgr, None, CLSCTX_INPROC_SERVER)?;
@@ -41,7 +47,7 @@ impl ITfTextInputProcessor_Impl for TextService {
     fn Deactivate(&self) -> Result<()> {
         trace!("Deactivate");
         let mut inner = self.write()?;
-        let thread_mgr = inner.thread_mgr()?;
+        let thread_mgr = inner.thread_mgr()?.clone();
         unsafe {
             thread_mgr.cast::<ITfKeystrokeMgr>()?.UnadviseKeyEventSink(inner.tid)?;
             debug!("Removed key event sink.");
@@ -55,11 +61,14 @@ impl ITfTextInputProcessor_Impl for TextService {
             if let Some(candidate_list) = inner.candidate_list.as_ref() {
                 candidate_list.destroy()?;
             }
-            // thread_mgr.cast::<ITfLangBarItemMgr>()?.RemoveItem(&inner.interface::<ITfLangBarItem>()?)?;
-            // debug!("Removed langbar item.")
+            thread_mgr.cast::<ITfLangBarItemMgr>()?.RemoveItem(&inner.interface::<ITfLangBarItem>()?)?;
+            debug!("Removed langbar item.");
         }
         inner.thread_mgr = None;
         inner.candidate_list = None;
+        // let go of the contexts held for their quotes and brackets
+        inner.pairs.clear();
+        stats::flush();
         Ok(())
     }
 }
@@ -1,5 +1,5 @@
 use windows::Win32::UI::TextServices::{ITfThreadMgrEventSink_Impl, ITfDocumentMgr, ITfContext};
-use windows::core::Result;
+use windows::core::{Interface, Result};
 use super::TextService;
 
 #[allow(non_snake_case, unused)]
@@ -8,15 +8,37 @@ impl ITfThreadMgrEventSink_Impl for TextService {
         Ok(())
     }
     fn OnUninitDocumentMgr(&self, pdim: Option<&ITfDocumentMgr>) -> Result<()> {
+        // the contexts still on the stack are gone with the document, without being popped
+        let Some(document_mgr) = pdim else {
+            return Ok(());
+        };
+        let contexts = unsafe { document_mgr.EnumContexts()? };
+        let mut inner = self.write()?;
+        loop {
+            let mut context = [None];
+            let mut fetched = 0;
+            unsafe { contexts.Next(&mut context, &mut fetched)? };
+            let [Some(context)] = context else {
+                break;
+            };
+            inner.pairs.remove(&(context.as_raw() as usize));
+        }
         Ok(())
     }
     fn OnSetFocus(&self, focus: Option<&ITfDocumentMgr>, prevfocus: Option<&ITfDocumentMgr>) ->Result<()> {
-        self.write()?.abort()
+        let mut inner = self.write()?;
+        inner.abort()?;
+        inner.chord.reset();
+        inner.switch_window(focus)
     }
     fn OnPushContext(&self, pic: Option<&ITfContext>) -> Result<()> {
         Ok(())
     }
     fn OnPopContext(&self, pic: Option<&ITfContext>) -> Result<()> {
+        // the document is gone, and so are its quotes and brackets
+        if let Some(context) = pic {
+            self.write()?.pairs.remove(&(context.as_raw() as usize));
+        }
         Ok(())
     }
 }
\ No newline at end of file
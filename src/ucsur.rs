@@ -0,0 +1,237 @@
+use std::fmt::{self, Display};
+use crate::{engine::insert_long_glyph_with, extend::CharExt};
+
+//----------------------------------------------------------------------------
+//
+//  Sitelen pona text in UCSUR, wherever it's from. It's checked for controls
+//  that don't pair up, joiners that join nothing and mixed spaces, and
+//  normalized to a chosen form. See `ajemi-cli check` and `normalize`.
+//
+//----------------------------------------------------------------------------
+
+const START_OF_CARTOUCHE: char = '\u{F1990}';
+const END_OF_CARTOUCHE: char = '\u{F1991}';
+const STACKING_JOINER: char = '\u{F1995}';
+const SCALING_JOINER: char = '\u{F1996}';
+const START_OF_LONG_GLYPH: char = '\u{F1997}';
+const END_OF_LONG_GLYPH: char = '\u{F1998}';
+const START_OF_REVERSE_LONG_GLYPH: char = '\u{F199A}';
+const END_OF_REVERSE_LONG_GLYPH: char = '\u{F199B}';
+const ZERO_WIDTH_JOINER: char = '\u{200D}';
+const IDEOGRAPHIC_SPACE: char = '\u{3000}';
+
+fn is_ucsur(ch: char) -> bool {
+    matches!(ch, '\u{F1900}'..='\u{F19FF}')
+}
+
+/// The glyph of a word, rather than a control or a punctuator.
+fn is_glyph(ch: char) -> bool {
+    matches!(ch, '\u{F1900}'..='\u{F198F}' | '\u{F19A0}'..='\u{F19FF}')
+}
+
+fn is_long_glyph_control(ch: char) -> bool {
+    matches!(ch, START_OF_LONG_GLYPH | END_OF_LONG_GLYPH | START_OF_REVERSE_LONG_GLYPH | END_OF_REVERSE_LONG_GLYPH)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// A long glyph is started but never ended.
+    UnendedLongGlyph,
+    /// A long glyph is ended but never started.
+    UnstartedLongGlyph,
+    /// A long glyph is ended as a reverse one, or the other way around.
+    MismatchedLongGlyph,
+    UnclosedCartouche,
+    UnopenedCartouche,
+    /// A joiner with no glyph on either side.
+    StrayJoiner,
+    /// A space of another kind than the first one next to sitelen pona.
+    MixedSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Issue {
+    /// In bytes.
+    pub offset: usize,
+    pub kind: IssueKind,
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use IssueKind::*;
+        let message = match self.kind {
+            UnendedLongGlyph => "long glyph started but not ended",
+            UnstartedLongGlyph => "long glyph ended but not started",
+            MismatchedLongGlyph => "long glyph ended as the other kind",
+            UnclosedCartouche => "cartouche opened but not closed",
+            UnopenedCartouche => "cartouche closed but not opened",
+            StrayJoiner => "joiner not between glyphs",
+            MixedSpace => "space differs from the first one",
+        };
+        write!(f, "byte {}: {message}", self.offset)
+    }
+}
+
+/// Check the text. The issues are in the order of their offsets.
+pub fn check(text: &str) -> Vec<Issue> {
+    use IssueKind::*;
+    let mut issues = Vec::new();
+    let mut push = |offset, kind| issues.push(Issue { offset, kind });
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut long_glyph: Option<(usize, char)> = None;
+    let mut cartouche: Option<usize> = None;
+    let mut space = None;
+    for (index, &(offset, ch)) in chars.iter().enumerate() {
+        let prev = index.checked_sub(1).map(|index| chars[index].1);
+        let next = chars.get(index + 1).map(|(_, ch)| *ch);
+        match ch {
+            START_OF_LONG_GLYPH | START_OF_REVERSE_LONG_GLYPH => {
+                if let Some((start, _)) = long_glyph.replace((offset, ch)) {
+                    push(start, UnendedLongGlyph);
+                }
+            }
+            END_OF_LONG_GLYPH | END_OF_REVERSE_LONG_GLYPH => match long_glyph.take() {
+                None => push(offset, UnstartedLongGlyph),
+                Some((_, start)) if (start == START_OF_LONG_GLYPH) != (ch == END_OF_LONG_GLYPH) =>
+                    push(offset, MismatchedLongGlyph),
+                Some(_) => (),
+            }
+            START_OF_CARTOUCHE => {
+                if let Some(start) = cartouche.replace(offset) {
+                    push(start, UnclosedCartouche);
+                }
+            }
+            END_OF_CARTOUCHE if cartouche.is_none() => push(offset, UnopenedCartouche),
+            END_OF_CARTOUCHE => cartouche = None,
+            // zero width joiners are left alone in emoji sequences
+            ch if ch.is_joiner() && (ch != ZERO_WIDTH_JOINER || prev.is_some_and(is_ucsur) || next.is_some_and(is_ucsur)) => {
+                let joins_prev = prev.is_some_and(|prev|
+                    is_glyph(prev) || matches!(prev, END_OF_CARTOUCHE | END_OF_LONG_GLYPH | END_OF_REVERSE_LONG_GLYPH));
+                let joins_next = next.is_some_and(|next|
+                    is_glyph(next) || matches!(next, START_OF_CARTOUCHE | START_OF_LONG_GLYPH | START_OF_REVERSE_LONG_GLYPH));
+                if !joins_prev || !joins_next {
+                    push(offset, StrayJoiner);
+                }
+            }
+            ' ' | IDEOGRAPHIC_SPACE if prev.is_some_and(is_ucsur) || next.is_some_and(is_ucsur) => match space {
+                None => space = Some(ch),
+                Some(first) if first != ch => push(offset, MixedSpace),
+                Some(_) => (),
+            }
+            _ => (),
+        }
+    }
+    if let Some((start, _)) = long_glyph {
+        push(start, UnendedLongGlyph);
+    }
+    if let Some(start) = cartouche {
+        push(start, UnclosedCartouche);
+    }
+    issues.sort_by_key(|issue| issue.offset);
+    issues
+}
+
+//----------------------------------------------------------------------------
+//
+//  Normalization.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LongGlyphs {
+    #[default]
+    Keep,
+    Strip,
+    /// Strip them, then insert them the way the IME does. The long glyphs of pi only if `long_pi`.
+    Derive { long_pi: bool },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Joiners {
+    #[default]
+    Keep,
+    Stacking,
+    Scaling,
+}
+
+/// Spaces next to sitelen pona. The ones between other text are kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Spaces {
+    #[default]
+    Keep,
+    Ideographic,
+    Ascii,
+}
+
+/// The canonical form to normalize to. Everything is kept by default.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Form {
+    pub long_glyphs: LongGlyphs,
+    pub joiners: Joiners,
+    pub spaces: Spaces,
+}
+
+pub fn normalize(text: &str, form: &Form) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut output = String::with_capacity(text.len());
+    for (index, &ch) in chars.iter().enumerate() {
+        let next_to_ucsur = (index > 0 && is_ucsur(chars[index - 1]))
+            || chars.get(index + 1).is_some_and(|next| is_ucsur(*next));
+        let ch = match (ch, form.joiners, form.spaces) {
+            (STACKING_JOINER | SCALING_JOINER, Joiners::Stacking, _) => STACKING_JOINER,
+            (STACKING_JOINER | SCALING_JOINER, Joiners::Scaling, _) => SCALING_JOINER,
+            (' ' | IDEOGRAPHIC_SPACE, _, Spaces::Ideographic) if next_to_ucsur => IDEOGRAPHIC_SPACE,
+            (' ' | IDEOGRAPHIC_SPACE, _, Spaces::Ascii) if next_to_ucsur => ' ',
+            (ch, _, _) => ch,
+        };
+        if is_long_glyph_control(ch) && form.long_glyphs != LongGlyphs::Keep {
+            continue;
+        }
+        output.push(ch);
+    }
+    let LongGlyphs::Derive { long_pi } = form.long_glyphs else {
+        return output;
+    };
+    // long glyphs never go across anything but sitelen pona
+    let mut derived = String::with_capacity(output.len());
+    let mut run = String::new();
+    for ch in output.chars() {
+        if is_ucsur(ch) || ch == ZERO_WIDTH_JOINER {
+            run.push(ch);
+            continue;
+        }
+        insert_long_glyph_with(&mut run, long_pi, true);
+        derived.push_str(&run);
+        derived.push(ch);
+        run.clear();
+    }
+    insert_long_glyph_with(&mut run, long_pi, true);
+    derived.push_str(&run);
+    derived
+}
+
+#[test]
+fn test() {
+    use IssueKind::*;
+    let kinds = |text: &str| check(text).into_iter().map(|issue| (issue.offset, issue.kind)).collect::<Vec<_>>();
+    // pi 「ken」 and a stacked one in a cartouche
+    assert_eq!(kinds("\u{F194D}\u{F1997}\u{F1918}\u{F1998} \u{F1990}\u{F1900}\u{F1995}\u{F1901}\u{F1991}"), vec![]);
+    assert_eq!(kinds("\u{F194D}\u{F1997}\u{F1918}"), vec![(4, UnendedLongGlyph)]);
+    assert_eq!(kinds("\u{F1918}\u{F1998}\u{F199A}\u{F1918}\u{F1998}"), vec![(4, UnstartedLongGlyph), (16, MismatchedLongGlyph)]);
+    assert_eq!(kinds("\u{F1991}\u{F1990}\u{F1900}"), vec![(0, UnopenedCartouche), (4, UnclosedCartouche)]);
+    assert_eq!(kinds("\u{F1995}\u{F1900}\u{F1996} \u{F1900}\u{200D}"), vec![(0, StrayJoiner), (8, StrayJoiner), (17, StrayJoiner)]);
+    assert_eq!(kinds("\u{F1900} \u{F1900}\u{3000}\u{F1900} a\u{3000}b"), vec![(9, MixedSpace)]);
+    // emoji sequences are fine
+    assert_eq!(kinds("👨\u{200D}👩"), vec![]);
+    assert_eq!(check("\u{F1990}")[0].to_string(), "byte 0: cartouche opened but not closed");
+
+    let form = Form { long_glyphs: LongGlyphs::Strip, joiners: Joiners::Scaling, spaces: Spaces::Ideographic };
+    assert_eq!(normalize("\u{F194D}\u{F1997}\u{F1918}\u{F1998} \u{F1900}\u{F1995}\u{F1901} a b", &form),
+        "\u{F194D}\u{F1918}\u{3000}\u{F1900}\u{F1996}\u{F1901}\u{3000}a b");
+    assert_eq!(normalize("\u{F1900}\u{3000}\u{F1900}", &Form { spaces: Spaces::Ascii, ..Form::default() }), "\u{F1900} \u{F1900}");
+    // pi a, then lon a, each long glyph only over its own run
+    let form = Form { long_glyphs: LongGlyphs::Derive { long_pi: true }, ..Form::default() };
+    assert_eq!(normalize("\u{F194D}\u{F1997}\u{F1900} \u{F192C}\u{F1900}", &form),
+        "\u{F194D}\u{F1997}\u{F1900}\u{F1998} \u{F192C}\u{F1997}\u{F1900}\u{F1998}");
+    assert_eq!(normalize("\u{F194D}\u{F1918}", &Form::default()), "\u{F194D}\u{F1918}");
+}
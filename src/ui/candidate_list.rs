@@ -1,20 +1,13 @@
-use std::{cmp::max, ffi::{CString, OsString}, mem::{self, size_of, ManuallyDrop}};
+use std::{cell::{Cell, RefCell}, ffi::{CString, OsString}, mem::{self, size_of, ManuallyDrop}};
 use log::{trace, debug, error};
-use windows::{Win32::{UI::WindowsAndMessaging::{CreateWindowExA, DefWindowProcA, DestroyWindow, GetWindowLongPtrA, LoadCursorW, RegisterClassExA, SetWindowLongPtrA, SetWindowPos, ShowWindow, CS_DROPSHADOW, CS_HREDRAW, CS_IME, CS_VREDRAW, HICON, HWND_TOPMOST, IDC_ARROW, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SW_HIDE, SW_SHOWNOACTIVATE, WINDOW_LONG_PTR_INDEX, WM_PAINT, WNDCLASSEXA, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP}, Foundation::{GetLastError, BOOL, HWND, LPARAM, LRESULT, RECT, SIZE, WPARAM}, Graphics::Gdi::{self, BeginPaint, CreateFontA, EndPaint, GetDC, GetDeviceCaps, GetTextExtentPoint32W, InvalidateRect, ReleaseDC, SelectObject, SetBkMode, SetTextColor, TextOutW, HDC, HFONT, LOGPIXELSY, OUT_TT_PRECIS, PAINTSTRUCT, TRANSPARENT}}, core::{s, PCSTR}};
+use windows::{Win32::{UI::WindowsAndMessaging::{CreateWindowExA, DefWindowProcA, DestroyWindow, GetWindowLongPtrA, GetWindowRect, LoadCursorW, RegisterClassExA, SetWindowLongPtrA, SetWindowPos, ShowWindow, CS_DROPSHADOW, CS_HREDRAW, CS_IME, CS_VREDRAW, HICON, HWND_TOPMOST, IDC_ARROW, MA_NOACTIVATE, SWP_NOACTIVATE, SWP_NOMOVE, SWP_NOSIZE, SW_HIDE, SW_SHOWNOACTIVATE, WINDOW_LONG_PTR_INDEX, WM_LBUTTONUP, WM_MOUSEACTIVATE, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_PAINT, WNDCLASSEXA, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_POPUP}, Foundation::{GetLastError, BOOL, HWND, LPARAM, LRESULT, RECT, SIZE, WPARAM}, Graphics::Gdi::{self, BeginPaint, CreateFontA, CreateRoundRectRgn, DeleteObject, EndPaint, FrameRgn, GetDC, GetDeviceCaps, GetMonitorInfoW, MonitorFromRect, SetWindowRgn, GetTextExtentPoint32W, InvalidateRect, ReleaseDC, SelectObject, SetBkMode, SetTextColor, TextOutW, HBRUSH, HDC, HFONT, HRGN, LOGPIXELSY, MONITORINFO, MONITOR_DEFAULTTONEAREST, OUT_TT_PRECIS, PAINTSTRUCT, TRANSPARENT}}, core::{s, PCSTR}};
+use windows::Win32::UI::Input::KeyboardAndMouse::{TrackMouseEvent, TME_LEAVE, TRACKMOUSEEVENT};
 use windows::core::Result;
-use crate::{conf::*, engine::Suggestion, extend::OsStrExt2, global, ui::Color, CANDI_INDEXES, CANDI_INDEX_SUFFIX, CANDI_INDEX_SUFFIX_MONO};
+use crate::{conf::conf, engine::Suggestion, extend::OsStrExt2, global, ui::{self, layout::{Arrangement, Rect, Size}, Color}, CANDI_INDEXES, CANDI_INDEX_SUFFIX, CANDI_INDEX_SUFFIX_MONO};
 
 const WINDOW_CLASS: PCSTR = s!("CANDIDATE_LIST");
-// Layout
-const CLIP_WIDTH: i32 = 3;
-const LABEL_PADDING_TOP: i32 = 2;
-const LABEL_PADDING_BOTTOM: i32 = 2;
-const LABEL_PADDING_LEFT: i32 = 3;
-const LABEL_PADDING_RIGHT: i32 = 4;
-const BORDER_WIDTH: i32 = 0;
-
-const POS_OFFSETX: i32 = 2;
-const POS_OFFSETY: i32 = 2;
+// defined in Win32_UI_Controls, which is not worth the feature
+const WM_MOUSELEAVE: u32 = 0x02A3;
 
 #[cfg(target_pointer_width = "64")]
 type LongPointer = isize;
@@ -29,7 +22,7 @@ pub fn setup() -> Result<()> {
         style: CS_IME | CS_HREDRAW | CS_VREDRAW | CS_DROPSHADOW,
         lpfnWndProc: Some(wind_proc),
         cbClsExtra: 0,
-        cbWndExtra: size_of::<Box<PaintArg>>().try_into().unwrap(),
+        cbWndExtra: size_of::<Box<WindowState>>().try_into().unwrap(),
         hInstance: global::dll_module(),
         hIcon: HICON::default(),
         hCursor: unsafe{ LoadCursorW(None, IDC_ARROW)? },
@@ -48,10 +41,16 @@ pub fn setup() -> Result<()> {
     Ok(())
 }
 
-/// use default handlers for everything but repaint
+/// use default handlers for everything but repaint and the mouse
 unsafe extern "system" fn wind_proc(window: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
     match msg {
         WM_PAINT => paint(window),
+        // clicking the window must not take the focus from the client
+        WM_MOUSEACTIVATE => LRESULT(MA_NOACTIVATE as isize),
+        WM_MOUSEMOVE => hover(window, Some(point_of(lparam))),
+        WM_MOUSELEAVE => hover(window, None),
+        WM_LBUTTONUP => click(window, point_of(lparam)),
+        WM_MOUSEWHEEL => scroll(window, wparam),
         _  => DefWindowProcA(window, msg, wparam, lparam),
     }
 }
@@ -63,6 +62,15 @@ unsafe extern "system" fn wind_proc(window: HWND, msg: u32, wparam: WPARAM, lpar
 //
 //----------------------------------------------------------------------------
 
+/// What's done to the candidate list with the mouse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    /// A candidate on the page is clicked.
+    Select(usize),
+    NextPage,
+    PreviousPage,
+}
+
 #[derive(Default)]
 pub struct CandidateList {
     window: HWND,
@@ -72,7 +80,8 @@ pub struct CandidateList {
 }
 
 impl CandidateList {
-    pub fn create(_parent_window: HWND) -> Result<CandidateList> {
+    /// Mouse events are passed to `on_mouse`, which may show or hide the candidate list.
+    pub fn create(_parent_window: HWND, on_mouse: impl Fn(MouseEvent) + 'static) -> Result<CandidateList> {
         // WS_EX_TOOLWINDOW:  A floating toolbar that won't appear in taskbar and ALT+TAB.
         // WS_EX_NOACTIVATE:  A window that doesn't take the foreground thus not making parent window lose focus.
         // WS_EX_TOPMOST:     A window that is topmost.
@@ -90,10 +99,12 @@ impl CandidateList {
                 error!("CreateWindowExA returned null.");
                 return Err(GetLastError().into());
             }
+            let state = WindowState { paint: RefCell::new(None), hover: Cell::new(None), on_mouse: Box::new(on_mouse) };
+            SetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX::default(), state.to_long_ptr());
             let dc: HDC = GetDC(window);
             let pixel_per_inch = GetDeviceCaps(dc, LOGPIXELSY);
-            let font_size = FONT_SIZE * pixel_per_inch / 72;
-            let font_name = CString::new(FONT.as_str()).unwrap();
+            let font_size = conf().font.size * pixel_per_inch / 72;
+            let font_name = CString::new(conf().font.name.as_str()).unwrap();
             let font_name = PCSTR::from_raw(font_name.as_bytes_with_nul().as_ptr());
             let candi_font = CreateFontA (
                 font_size, 0, 0, 0, 0, 0, 0, 0, 0, OUT_TT_PRECIS.0 as u32, 0, 0, 0, font_name);
@@ -110,7 +121,7 @@ impl CandidateList {
             }
     
             let index_suffix;
-            let lowercase_font_name = FONT.to_ascii_lowercase();
+            let lowercase_font_name = conf().font.name.to_ascii_lowercase();
             // TODO this is no reliable at all
             if lowercase_font_name.contains("mono") || lowercase_font_name.contains("fairfax") {
                 index_suffix = CANDI_INDEX_SUFFIX_MONO;
@@ -122,89 +133,67 @@ impl CandidateList {
         }
     }
 
-    pub fn locate(&self, x: i32, y: i32) -> Result<()>{
-        trace!("locate({x}, {y})");
-        unsafe {SetWindowPos(
-            self.window, HWND_TOPMOST, 
-            x + POS_OFFSETX, y + POS_OFFSETY, 0, 0,
-            SWP_NOACTIVATE | SWP_NOSIZE)? };
-
+    /// Place the window next to the caret, within the monitor the caret is on.
+    pub fn locate(&self, caret: RECT) -> Result<()>{
+        trace!("locate({}, {}, {}, {})", caret.left, caret.top, caret.right, caret.bottom);
+        unsafe {
+            let mut window = RECT::default();
+            GetWindowRect(self.window, &mut window)?;
+            let size = Size { width: window.right - window.left, height: window.bottom - window.top };
+            let mut monitor = MONITORINFO { cbSize: size_of::<MONITORINFO>() as u32, ..Default::default() };
+            GetMonitorInfoW(MonitorFromRect(&caret, MONITOR_DEFAULTTONEAREST), &mut monitor);
+            let (x, y) = conf().layout.place(size, rect_of(caret), rect_of(monitor.rcWork));
+            SetWindowPos(self.window, HWND_TOPMOST, x, y, 0, 0, SWP_NOACTIVATE | SWP_NOSIZE)?;
+        }
         Ok(())
     }
 
-    pub fn show(&self, suggs: &Vec<Suggestion>) -> Result<()> {
+    /// Show the suggestions, the one at `highlighted` highlighted.
+    pub fn show(&self, suggs: &[Suggestion], highlighted: usize) -> Result<()> {
         unsafe{ 
             let mut indice = Vec::with_capacity(suggs.len());
             let mut candis = Vec::with_capacity(suggs.len());
-
-            let mut candi_height: i32 = 0;
-            let mut index_height: i32 = 0;
-            let mut index_width: i32 = 0;
-            let mut candi_width: i32 = 0;
-            let mut candi_widths = Vec::with_capacity(suggs.len());
+            let mut notes = Vec::with_capacity(suggs.len());
+            let mut index_sizes = Vec::with_capacity(suggs.len());
+            let mut candi_sizes = Vec::with_capacity(suggs.len());
+            let mut note_sizes = Vec::with_capacity(suggs.len());
                 
             let dc: HDC = GetDC(self.window);   
             for (index, sugg) in suggs.iter().enumerate() {
-                let mut size = SIZE::default();
                 let index = format!("{}{}", CANDI_INDEXES[index], self.index_suffix);
                 let index = OsString::from(index).wchars();
-                SelectObject(dc, self.index_font);
-                GetTextExtentPoint32W(dc, &index, &mut size);
-                index_height = max(index_height, size.cy);
-                index_width = max(index_width, size.cx);
+                index_sizes.push(measure(dc, &index, self.index_font));
                 indice.push(index);
 
                 let candi = OsString::from(&sugg.output).wchars();
-                SelectObject(dc, self.candi_font);
-                GetTextExtentPoint32W(dc, &candi, &mut size);
-                candi_height = max(candi_height, size.cy);
-                candi_width = max(candi_width, size.cx);
-                candi_widths.push(size.cx);
+                candi_sizes.push(measure(dc, &candi, self.candi_font));
                 candis.push(candi);
+
+                let note = OsString::from(note_of(sugg)).wchars();
+                note_sizes.push(if note.is_empty() { Size::default() } else { measure(dc, &note, self.index_font) });
+                notes.push(note);
             }
             ReleaseDC(self.window, dc);
-            let row_height = max(candi_height, index_height);
-            let label_height = LABEL_PADDING_TOP + row_height + LABEL_PADDING_BOTTOM;
-            let mut wnd_height = 0;
-            let mut wnd_width = 0;
-            if VERTICAL {
-                let candi_num: i32 = suggs.len().try_into().unwrap();
-                wnd_height += candi_num * label_height;
-                wnd_width += CLIP_WIDTH + LABEL_PADDING_LEFT + index_width + candi_width + LABEL_PADDING_RIGHT;
-                wnd_width = max(wnd_width, wnd_height * 4 / 5)
-            } else {
-                wnd_height += label_height;
-                wnd_width += CLIP_WIDTH;
-                for candi_width in candi_widths.iter() {
-                    wnd_width += LABEL_PADDING_LEFT + LABEL_PADDING_RIGHT;
-                    wnd_width += index_width;
-                    wnd_width += candi_width;
-                }
-            }
-            wnd_height += BORDER_WIDTH * 2;
-            wnd_width += BORDER_WIDTH * 2;
-
-            let highlight_width = if VERTICAL {
-                wnd_width - CLIP_WIDTH - BORDER_WIDTH * 2
-            } else {
-                LABEL_PADDING_LEFT + index_width + candi_widths[0] + LABEL_PADDING_RIGHT   
-            };
+            // not borrowed from the config, which is locked while borrowed
+            let layout = conf().layout.clone();
+            let arrangement = layout.arrange(&index_sizes, &candi_sizes, &note_sizes, highlighted);
+            let Size { width, height } = arrangement.size;
 
             // passing extra args to WndProc
             let arg = PaintArg {
-                wnd_width, wnd_height, highlight_width, 
-                label_height, row_height,
-                index_width, index_height, 
-                candi_widths: candi_widths.clone(), candi_height,
-                candis, indice, 
+                arrangement, highlighted, candis, indice, notes,
                 index_font: self.index_font,
                 candi_font: self.candi_font,
             };
-            let long_ptr = arg.to_long_ptr();
-            SetWindowLongPtrA(self.window, WINDOW_LONG_PTR_INDEX::default(), long_ptr);
+            if let Some(state) = WindowState::of(self.window) {
+                state.paint.replace(Some(arg));
+                state.hover.set(None);
+            }
             // resize and show
             SetWindowPos(
-                self.window, HWND_TOPMOST, 0, 0, wnd_width, wnd_height, SWP_NOACTIVATE | SWP_NOMOVE)?;
+                self.window, HWND_TOPMOST, 0, 0, width, height, SWP_NOACTIVATE | SWP_NOMOVE)?;
+            // the window takes the ownership of the region
+            SetWindowRgn(self.window, region(width, height, layout.corner_radius), BOOL::from(true));
             ShowWindow(self.window, SW_SHOWNOACTIVATE);
             // force repaint
             InvalidateRect(self.window, None, BOOL::from(true));
@@ -219,99 +208,215 @@ impl CandidateList {
     }
 
     pub fn destroy(&self) -> Result<()> {
-        unsafe { DestroyWindow(self.window) }
+        unsafe {
+            // the state is freed after the window, which might still use it while being destroyed
+            let long_ptr = SetWindowLongPtrA(self.window, WINDOW_LONG_PTR_INDEX::default(), 0);
+            DestroyWindow(self.window)?;
+            WindowState::drop_long_ptr(long_ptr);
+            // the index font falls back to the candidate font if it failed to be created
+            if self.index_font != self.candi_font {
+                DeleteObject(self.index_font);
+            }
+            DeleteObject(self.candi_font);
+        }
+        Ok(())
     }
 }
 
 struct PaintArg {
-    wnd_width: i32,
-    wnd_height: i32,
-    highlight_width: i32,
-    label_height: i32,
-    row_height: i32,
-    index_width: i32,
-    index_height: i32,
-    candi_widths: Vec<i32>,
-    candi_height: i32,
+    arrangement: Arrangement,
+    highlighted: usize,
     index_font: HFONT,
     candi_font: HFONT,
     indice: Vec<Vec<u16>>,
     candis: Vec<Vec<u16>>,
+    notes: Vec<Vec<u16>>,
+}
+
+/// Lives as long as the window, in its extra bytes. Only shared references are handed out
+/// since `on_mouse` may call `show` while the state is being used.
+struct WindowState {
+    paint: RefCell<Option<PaintArg>>,
+    hover: Cell<Option<usize>>,
+    on_mouse: Box<dyn Fn(MouseEvent)>,
 }
-impl PaintArg {
+impl WindowState {
     unsafe fn to_long_ptr(self) -> LongPointer{
         mem::transmute(ManuallyDrop::new(Box::new(self)))
     }
-    unsafe fn from_long_ptr(long_ptr: LongPointer) -> Option<Box<PaintArg>>{
-        if long_ptr == 0 {
-            None
-        } else {
-            Some(mem::transmute(long_ptr))
+    unsafe fn drop_long_ptr(long_ptr: LongPointer) {
+        if long_ptr != 0 {
+            drop(mem::transmute::<LongPointer, Box<WindowState>>(long_ptr));
         }
     }
+    unsafe fn of<'a>(window: HWND) -> Option<&'a WindowState> {
+        let long_ptr = GetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX::default());
+        (long_ptr as *const WindowState).as_ref()
+    }
 }
 
 unsafe fn paint(window: HWND) -> LRESULT{
-    // load the extra arg
-    let Some(arg) = PaintArg::from_long_ptr(GetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX::default())) else {
-        error!("Args for repaint is not found.");
-        return LRESULT::default();
-    };
-    SetWindowLongPtrA(window, WINDOW_LONG_PTR_INDEX::default(), 0);
     let mut ps = PAINTSTRUCT::default();
     let dc: HDC = BeginPaint(window, &mut ps);
     if dc.is_invalid() {
         error!("BeginPaint failed.");
         return LRESULT::default();
     }
+    // load the extra arg
+    let Some(state) = WindowState::of(window) else {
+        EndPaint(window, &mut ps);
+        return LRESULT::default();
+    };
+    let arg = state.paint.borrow();
+    let Some(arg) = arg.as_ref() else {
+        error!("Args for repaint is not found.");
+        EndPaint(window, &mut ps);
+        return LRESULT::default();
+    };
+    let palette = conf().color.palette(ui::system_dark());
+    let arrangement = &arg.arrangement;
+    let Size { width, height } = arrangement.size;
     // window
-    FillRect(dc, 0, 0, arg.wnd_width, arg.wnd_height, BKG_COLOR);
+    FillRect(dc, Rect { x: 0, y: 0, width, height }, palette.background);
+    // border
+    let border_width = conf().layout.border_width;
+    if border_width > 0 {
+        let rgn = region(width, height, conf().layout.corner_radius);
+        let brush = HBRUSH::from(palette.border);
+        FrameRgn(dc, rgn, brush, border_width, border_width);
+        DeleteObject(brush);
+        DeleteObject(rgn);
+    }
     // clip
-    FillRect(dc, BORDER_WIDTH, BORDER_WIDTH, CLIP_WIDTH, arg.label_height, CLIP_COLOR);
+    FillRect(dc, arrangement.clip, palette.clip);
     // highlight
-    FillRect(dc, BORDER_WIDTH + CLIP_WIDTH, BORDER_WIDTH, arg.highlight_width, arg.label_height, HIGHTLIGHT_COLOR);
-    // highlighted text
-    let mut index_x = BORDER_WIDTH + CLIP_WIDTH + LABEL_PADDING_LEFT;
-    let mut candi_x = BORDER_WIDTH + index_x + arg.index_width;
-    let mut index_y = BORDER_WIDTH + LABEL_PADDING_TOP + (arg.row_height - arg.index_height) / 2;
-    let mut candi_y = BORDER_WIDTH + LABEL_PADDING_TOP + (arg.row_height - arg.candi_height) / 2;
-    
+    FillRect(dc, arrangement.highlight, palette.highlight);
+    // the candidate under the mouse
+    if let Some(label) = state.hover.get().filter(|hover| *hover != arg.highlighted).and_then(|hover| arrangement.labels.get(hover)) {
+        FillRect(dc, label.rect, palette.hover);
+    }
+    // text
     SetBkMode(dc, TRANSPARENT);
-    TextOut(dc, index_x, index_y, &arg.indice[0], INDEX_COLOR, arg.index_font);
-    TextOut(dc, candi_x, candi_y, &arg.candis[0], CANDI_HIGHLIGHTED_COLOR, arg.candi_font);
-    // normal text
-    for i in 1..arg.candis.len() {
-        if VERTICAL {
-            index_y += arg.label_height;
-            candi_y += arg.label_height;
-        } else {
-            index_x += arg.index_width + arg.candi_widths[i - 1] + LABEL_PADDING_LEFT + LABEL_PADDING_RIGHT;
-            candi_x += arg.index_width + arg.candi_widths[i - 1] + LABEL_PADDING_LEFT + LABEL_PADDING_RIGHT;
+    for (i, label) in arrangement.labels.iter().enumerate() {
+        let candi_color = if i == arg.highlighted { palette.highlighted } else { palette.candidate };
+        TextOut(dc, label.index, &arg.indice[i], palette.index, arg.index_font);
+        TextOut(dc, label.candi, &arg.candis[i], candi_color, arg.candi_font);
+        if !arg.notes[i].is_empty() {
+            TextOut(dc, label.note, &arg.notes[i], palette.index, arg.index_font);
         }
-        TextOut(dc, index_x, index_y, &arg.indice[i], INDEX_COLOR, arg.index_font);
-        TextOut(dc, candi_x, candi_y, &arg.candis[i], CANDI_COLOR, arg.candi_font);
     }
     ReleaseDC(window, dc);
     EndPaint(window, &mut ps);
     LRESULT::default()
 }
 
+unsafe fn hover(window: HWND, point: Option<(i32, i32)>) -> LRESULT {
+    let Some(state) = WindowState::of(window) else {
+        return LRESULT::default();
+    };
+    if point.is_some() {
+        // ask for WM_MOUSELEAVE
+        let mut track = TRACKMOUSEEVENT {
+            cbSize: size_of::<TRACKMOUSEEVENT>() as u32,
+            dwFlags: TME_LEAVE,
+            hwndTrack: window,
+            dwHoverTime: 0,
+        };
+        let _ = TrackMouseEvent(&mut track);
+    }
+    let hover = point.and_then(|(x, y)| state.paint.borrow().as_ref()?.arrangement.hit(x, y));
+    if state.hover.replace(hover) != hover {
+        InvalidateRect(window, None, BOOL::from(true));
+    }
+    LRESULT::default()
+}
+
+unsafe fn click(window: HWND, (x, y): (i32, i32)) -> LRESULT {
+    let Some(state) = WindowState::of(window) else {
+        return LRESULT::default();
+    };
+    let index = state.paint.borrow().as_ref().and_then(|arg| arg.arrangement.hit(x, y));
+    if let Some(index) = index {
+        (state.on_mouse)(MouseEvent::Select(index));
+    }
+    LRESULT::default()
+}
+
+unsafe fn scroll(window: HWND, wparam: WPARAM) -> LRESULT {
+    let Some(state) = WindowState::of(window) else {
+        return LRESULT::default();
+    };
+    // the high word is how far the wheel is rotated, positive when rotated away from the user
+    let delta = (wparam.0 >> 16) as u16 as i16;
+    if delta > 0 {
+        (state.on_mouse)(MouseEvent::PreviousPage);
+    } else if delta < 0 {
+        (state.on_mouse)(MouseEvent::NextPage);
+    }
+    LRESULT::default()
+}
+
+/// The point in the client area, which is signed.
+fn point_of(lparam: LPARAM) -> (i32, i32) {
+    (lparam.0 as u16 as i16 as i32, (lparam.0 >> 16) as u16 as i16 as i32)
+}
+
 #[allow(non_snake_case)]
-unsafe fn TextOut(hdc: HDC, x: i32, y: i32, wchars:&[u16], color: Color, font: HFONT) {
+unsafe fn TextOut(hdc: HDC, (x, y): (i32, i32), wchars:&[u16], color: Color, font: HFONT) {
     SelectObject(hdc, font);
     SetTextColor(hdc, color);
     TextOutW(hdc, x, y, wchars);
 }
 
 #[allow(non_snake_case)]
-unsafe fn FillRect(hdc: HDC, x: i32, y: i32, width: i32, height: i32, color: Color) {
+unsafe fn FillRect(hdc: HDC, rect: Rect, color: Color) {
     let rect = RECT {
-        left: x,
-        top: y,
-        right: x + width,
-        bottom: height,
+        left: rect.x,
+        top: rect.y,
+        right: rect.right(),
+        bottom: rect.bottom(),
     };
     Gdi::FillRect(hdc, &rect, color);
 }
 
+/// The annotations turned on, like "akesi  reptile, amphibian  aoa·ea".
+fn note_of(sugg: &Suggestion) -> String {
+    let layout = &conf().layout;
+    let Some(annotation) = &sugg.annotation else {
+        return String::new();
+    };
+    let mut parts = Vec::with_capacity(4);
+    if layout.show_latin && !annotation.latin.is_empty() {
+        parts.push(annotation.latin.clone());
+    }
+    if layout.show_gloss && !annotation.gloss.is_empty() {
+        parts.push(annotation.gloss.clone());
+    }
+    if layout.show_code {
+        match &annotation.code {
+            (typed, rest) if rest.is_empty() => parts.push(typed.clone()),
+            (typed, rest) => parts.push(format!("{typed}·{rest}")),
+        }
+    }
+    if layout.show_tier && !annotation.tier.is_empty() {
+        parts.push(annotation.tier.to_string());
+    }
+    parts.join("  ")
+}
+
+unsafe fn measure(dc: HDC, wchars: &[u16], font: HFONT) -> Size {
+    let mut size = SIZE::default();
+    SelectObject(dc, font);
+    GetTextExtentPoint32W(dc, wchars, &mut size);
+    Size { width: size.cx, height: size.cy }
+}
+
+/// The shape of the window, rounded if there's a corner radius.
+unsafe fn region(width: i32, height: i32, corner_radius: i32) -> HRGN {
+    // the right and bottom edges are excluded from the region
+    CreateRoundRectRgn(0, 0, width + 1, height + 1, corner_radius * 2, corner_radius * 2)
+}
 
+fn rect_of(rect: RECT) -> Rect {
+    Rect { x: rect.left, y: rect.top, width: rect.right - rect.left, height: rect.bottom - rect.top }
+}
@@ -0,0 +1,292 @@
+use toml::Value;
+use crate::{conf::Problem, extend::{LoadValue, TableLoader}};
+
+//----------------------------------------------------------------------------
+//
+//  Layout of the candidate list, free of any GDI. The sizes of the measured
+//  texts go in, the size of the window and where to draw what come out.
+//  Rounded corners are left to the window region, see candidate_list.rs.
+//
+//----------------------------------------------------------------------------
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Size {
+    pub width: i32,
+    pub height: i32,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+}
+
+/// The space around the text of each candidate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Padding {
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    pub left: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    pub vertical: bool,
+    pub padding: Padding,
+    /// The bar left to the highlighted candidate.
+    pub clip_width: i32,
+    pub border_width: i32,
+    pub corner_radius: i32,
+    /// The distance between the caret and the window.
+    pub offset: i32,
+    /// Annotations of the candidates.
+    pub show_latin: bool,
+    pub show_gloss: bool,
+    pub show_code: bool,
+    pub show_tier: bool,
+    /// If the list is never shown, the candidates being cycled through in the preedit instead.
+    pub hidden: bool,
+}
+
+/// The area of a candidate and the top-left corners of its texts.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Label {
+    pub rect: Rect,
+    pub index: (i32, i32),
+    pub candi: (i32, i32),
+    pub note: (i32, i32),
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Arrangement {
+    /// The size of the window, borders included.
+    pub size: Size,
+    pub clip: Rect,
+    pub highlight: Rect,
+    pub labels: Vec<Label>,
+}
+
+impl Arrangement {
+    /// The index of the candidate at the point, for the mouse.
+    pub fn hit(&self, x: i32, y: i32) -> Option<usize> {
+        self.labels.iter().position(|label| {
+            let rect = label.rect;
+            rect.x <= x && x < rect.right() && rect.y <= y && y < rect.bottom()
+        })
+    }
+}
+
+impl Layout {
+    pub fn new() -> Layout {
+        Layout {
+            vertical: false,
+            padding: Padding { top: 2, right: 4, bottom: 2, left: 3 },
+            clip_width: 3,
+            border_width: 0,
+            corner_radius: 0,
+            offset: 2,
+            show_latin: false,
+            show_gloss: false,
+            show_code: false,
+            show_tier: false,
+            hidden: false,
+        }
+    }
+
+    /// Arrange the candidates, given the sizes of their indexes, texts and annotations.
+    /// An empty annotation takes no space. The clip goes along with the highlighted candidate.
+    pub fn arrange(&self, indexes: &[Size], candis: &[Size], notes: &[Size], highlighted: usize) -> Arrangement {
+        let Layout { padding, clip_width: clip, border_width: border, .. } = *self;
+        let index_width = indexes.iter().map(|size| size.width).max().unwrap_or(0);
+        let index_height = indexes.iter().map(|size| size.height).max().unwrap_or(0);
+        let candi_width = candis.iter().map(|size| size.width).max().unwrap_or(0);
+        let candi_height = candis.iter().map(|size| size.height).max().unwrap_or(0);
+        let note_width = notes.iter().map(|size| size.width).max().unwrap_or(0);
+        let note_height = notes.iter().map(|size| size.height).max().unwrap_or(0);
+        // the space between a candidate and its annotation
+        let gap = |note: &Size| if note.width > 0 { padding.right } else { 0 };
+        let row_height = index_height.max(candi_height).max(note_height);
+        let label_height = padding.top + row_height + padding.bottom;
+        // the width of a label without its candidate
+        let label_width = padding.left + index_width + padding.right;
+
+        let mut labels = Vec::with_capacity(candis.len());
+        let mut x = border + clip;
+        let mut y = border;
+        for (i, candi) in candis.iter().enumerate() {
+            let note = notes.get(i).copied().unwrap_or_default();
+            let index_x = x + padding.left;
+            let index_y = y + padding.top + (row_height - index_height) / 2;
+            let candi_x = index_x + index_width;
+            let candi_y = y + padding.top + (row_height - candi_height) / 2;
+            // annotations are in a column of their own if vertical
+            let note_x = candi_x + if self.vertical { candi_width } else { candi.width } + padding.right;
+            let note_y = y + padding.top + (row_height - note.height) / 2;
+            // the width is left for later if vertical
+            let width = label_width + candi.width + gap(&note) + note.width;
+            let rect = Rect { x, y, width, height: label_height };
+            labels.push(Label { rect, index: (index_x, index_y), candi: (candi_x, candi_y), note: (note_x, note_y) });
+            if self.vertical {
+                y += label_height;
+            } else {
+                x += width;
+            }
+        }
+        let note_column = if note_width > 0 { padding.right + note_width } else { 0 };
+
+        let mut size = if self.vertical {
+            let height = label_height * candis.len() as i32;
+            let width = clip + label_width + candi_width + note_column;
+            Size { width: width.max(height * 4 / 5), height }
+        } else {
+            Size { width: x - border, height: label_height }
+        };
+        size.width += border * 2;
+        size.height += border * 2;
+
+        if self.vertical {
+            for label in labels.iter_mut() {
+                label.rect.width = size.width - clip - border * 2;
+            }
+        }
+        let highlight = labels.get(highlighted).map(|label| label.rect).unwrap_or_default();
+        Arrangement {
+            size,
+            clip: Rect { x: border, y: highlight.y.max(border), width: clip, height: label_height },
+            highlight,
+            labels,
+        }
+    }
+
+    /// The top-left corner of the window, below the caret or above it if there's no room below,
+    /// and kept within the work area of the monitor.
+    pub fn place(&self, size: Size, caret: Rect, work_area: Rect) -> (i32, i32) {
+        let mut x = caret.x + self.offset;
+        let mut y = caret.bottom() + self.offset;
+        if y + size.height > work_area.bottom() {
+            y = caret.y - self.offset - size.height;
+        }
+        x = x.min(work_area.right() - size.width).max(work_area.x);
+        y = y.min(work_area.bottom() - size.height).max(work_area.y);
+        (x, y)
+    }
+}
+
+impl LoadValue for Layout {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        table.give("vertical", &mut self.vertical);
+        table.give("padding", &mut self.padding);
+        table.give("show_latin", &mut self.show_latin);
+        table.give("show_gloss", &mut self.show_gloss);
+        table.give("show_code", &mut self.show_code);
+        table.give("show_tier", &mut self.show_tier);
+        table.give("hidden", &mut self.hidden);
+        let keys = ["clip_width", "border_width", "corner_radius", "offset"];
+        let previous = [self.clip_width, self.border_width, self.corner_radius, self.offset];
+        let mut sizes = previous;
+        for (key, size) in keys.iter().zip(sizes.iter_mut()) {
+            table.give(key, size);
+        }
+        table.finish();
+        // negative sizes are left as they were
+        for i in 0..sizes.len() {
+            if sizes[i] < 0 {
+                problems.push(Problem::error(&Problem::join(path, keys[i]), format!("{} is negative", sizes[i])));
+                sizes[i] = previous[i];
+            }
+        }
+        [self.clip_width, self.border_width, self.corner_radius, self.offset] = sizes;
+    }
+}
+
+/// Either an integer for all sides, or an array of them in the order of top, right, bottom and left.
+impl LoadValue for Padding {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let sides = match &value {
+            Value::Integer(side) => vec![*side; 4],
+            Value::Array(sides) if sides.len() == 4 => sides.iter().filter_map(Value::as_integer).collect(),
+            _ => Vec::new(),
+        };
+        let sides: Vec<i32> = sides.into_iter().filter_map(|side| i32::try_from(side).ok()).collect();
+        match sides[..] {
+            [top, right, bottom, left] if sides.iter().all(|side| *side >= 0) => {
+                *self = Padding { top, right, bottom, left };
+            }
+            _ => problems.push(Problem::error(path,
+                format!("expected an integer or an array of 4 integers, none negative, found {value}"))),
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let size = |width, height| Size { width, height };
+    let mut layout = Layout::new();
+    layout.padding = Padding { top: 1, right: 2, bottom: 3, left: 4 };
+    layout.clip_width = 5;
+    layout.border_width = 1;
+    let indexes = [size(10, 8), size(12, 8)];
+    let candis = [size(30, 20), size(40, 20)];
+
+    let arrangement = layout.arrange(&indexes, &candis, &[], 0);
+    assert_eq!(arrangement.size, size(1 + 5 + (4 + 12 + 2) * 2 + 30 + 40 + 1, 1 + 24 + 1));
+    assert_eq!(arrangement.clip, Rect { x: 1, y: 1, width: 5, height: 24 });
+    assert_eq!(arrangement.highlight, Rect { x: 6, y: 1, width: 4 + 12 + 30 + 2, height: 24 });
+    assert_eq!(arrangement.labels, vec![
+        Label { rect: Rect { x: 6, y: 1, width: 48, height: 24 }, index: (10, 8), candi: (22, 2), note: (22 + 30 + 2, 12) },
+        Label { rect: Rect { x: 54, y: 1, width: 58, height: 24 }, index: (10 + 48, 8), candi: (22 + 48, 2), note: (22 + 48 + 40 + 2, 12) },
+    ]);
+    assert_eq!(arrangement.hit(6, 1), Some(0));
+    assert_eq!(arrangement.hit(60, 20), Some(1));
+    assert_eq!(arrangement.hit(3, 20), None);
+
+    layout.vertical = true;
+    // annotations in a column of their own
+    let notes = [size(15, 10), size(0, 0)];
+    let arrangement = layout.arrange(&indexes, &candis, &notes, 0);
+    assert_eq!(arrangement.size, size(1 + 5 + 4 + 12 + 40 + 2 + 2 + 15 + 1, 1 + 24 * 2 + 1));
+    assert_eq!(arrangement.highlight.width, 4 + 12 + 40 + 2 + 2 + 15);
+    assert_eq!(arrangement.labels[0].note, (22 + 40 + 2, 7));
+    assert_eq!(arrangement.labels[1], Label {
+        rect: Rect { x: 6, y: 25, width: 75, height: 24 },
+        index: (10, 8 + 24), candi: (22, 2 + 24), note: (64, 12 + 24)
+    });
+    assert_eq!(arrangement.hit(70, 30), Some(1));
+    // the clip follows the highlight down the list
+    let arrangement = layout.arrange(&indexes, &candis, &notes, 1);
+    assert_eq!(arrangement.highlight, arrangement.labels[1].rect);
+    assert_eq!(arrangement.clip, Rect { x: 1, y: 25, width: 5, height: 24 });
+
+    let work_area = Rect { x: 0, y: 0, width: 1000, height: 800 };
+    let caret = |x, y| Rect { x, y, width: 1, height: 20 };
+    let window = size(300, 50);
+    assert_eq!(layout.place(window, caret(100, 100), work_area), (102, 122));
+    // flipped above the caret near the bottom
+    assert_eq!(layout.place(window, caret(100, 770), work_area), (102, 718));
+    // clamped to the right edge
+    assert_eq!(layout.place(window, caret(900, 100), work_area), (700, 122));
+
+    let mut problems = Vec::new();
+    let table = "padding = [1, 2, 3]\nborder_width = -1\ncorner_radius = 6".parse::<toml::Table>().unwrap();
+    layout.load(Value::Table(table), "layout", &mut problems);
+    assert_eq!(problems.len(), 2);
+    assert_eq!(layout.padding, Padding { top: 1, right: 2, bottom: 3, left: 4 });
+    assert_eq!(layout.border_width, 1);
+    assert_eq!(layout.corner_radius, 6);
+}
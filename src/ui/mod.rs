@@ -1,13 +1,16 @@
 pub mod candidate_list;
+pub mod layout;
+pub mod theme;
 
 use toml::Value;
-use windows::Win32::{Foundation::COLORREF, Graphics::Gdi::{CreateSolidBrush, HBRUSH}};
-use windows::core::IntoParam;
+use std::{ffi::c_void, mem::{replace, size_of}};
+use windows::Win32::{Foundation::COLORREF, Graphics::Gdi::{CreateSolidBrush, HBRUSH}, System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD}};
+use windows::core::{w, IntoParam, PCWSTR};
 use windows::core::Param;
 
-use crate::extend::LoadValue;
+use crate::{conf::Problem, extend::LoadValue};
 
-#[derive(Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Color{
     r:u8, g:u8, b:u8
 }
@@ -33,26 +36,45 @@ impl Color {
     pub const fn white() -> Color {
         Color::hex(0xFFFFFF)
     }
+
+    /// The relative luminance, from 0 to 1.
+    pub fn luminance(self) -> f32 {
+        (0.2126 * self.r as f32 + 0.7152 * self.g as f32 + 0.0722 * self.b as f32) / 255.0
+    }
 }
 
 impl LoadValue for Color {
-    fn load(&mut self, value: Value) {
-        if let Value::Integer(value) = value {
-            *self = Color::hex(value as u32);
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match Color::try_from(&value) {
+            Ok(color) => *self = color,
+            Err(message) => problems.push(Problem::error(path, message)),
+        }
+    }
+}
+
+/// A color that is set once it's loaded, like one overriding the theme.
+impl LoadValue for Option<Color> {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        match Color::try_from(&value) {
+            Ok(color) => *self = Some(color),
+            Err(message) => problems.push(Problem::error(path, message)),
         }
     }
 }
-impl TryFrom<Value> for Color {
-    type Error = ();
-    fn try_from(value: Value) -> Result<Color, ()> {
+
+/// Colors are either integers like `0x0078D7` or strings like `"#0078D7"`.
+impl TryFrom<&Value> for Color {
+    type Error = String;
+    fn try_from(value: &Value) -> Result<Color, String> {
         match value {
-            Value::Integer(code) => Ok(Color::hex(code as u32)),
-            Value::String(text) => if text.starts_with("#") {
-                u32::from_str_radix(&text[1..], 16).map(Color::hex).map_err(|_|())
-            } else {
-                Err(())
-            }
-            _ => Err(())
+            Value::Integer(code @ 0..=0xFFFFFF) => Ok(Color::hex(*code as u32)),
+            Value::Integer(code) => Err(format!("{code:#X} is out of range")),
+            Value::String(text) => text.strip_prefix('#')
+                .filter(|hex| hex.len() == 6 && hex.chars().all(|ch| ch.is_ascii_hexdigit()))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .map(Color::hex)
+                .ok_or_else(|| format!("expected a color like \"#RRGGBB\", found {text:?}")),
+            value => Err(format!("expected a color, found {}", value.type_str())),
         }
     }
 }
@@ -81,3 +103,43 @@ impl IntoParam<HBRUSH> for Color {
     }
 }
 
+//----------------------------------------------------------------------------
+//
+//  The light/dark settings of the system, which the "auto" theme follows.
+//  The candidate list goes with apps, and the icon with the taskbar.
+//
+//----------------------------------------------------------------------------
+
+static mut SYSTEM_DARK: bool = false;
+static mut TASKBAR_DARK: bool = false;
+
+/// If apps are supposed to be dark, as of the last refresh.
+pub fn system_dark() -> bool {
+    unsafe { SYSTEM_DARK }
+}
+
+/// If the taskbar, where the icon is, is supposed to be dark, as of the last refresh.
+pub fn taskbar_dark() -> bool {
+    unsafe { TASKBAR_DARK }
+}
+
+/// Read the settings again. Returns true if either is changed.
+pub fn refresh_system_dark() -> bool {
+    let apps = !uses_light_theme(w!("AppsUseLightTheme"));
+    let taskbar = !uses_light_theme(w!("SystemUsesLightTheme"));
+    unsafe { (replace(&mut SYSTEM_DARK, apps) != apps) | (replace(&mut TASKBAR_DARK, taskbar) != taskbar) }
+}
+
+/// Light unless the value says otherwise.
+fn uses_light_theme(value: PCWSTR) -> bool {
+    let mut light: u32 = 1;
+    let mut size = size_of::<u32>() as u32;
+    let result = unsafe { RegGetValueW(
+        HKEY_CURRENT_USER,
+        w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+        value,
+        RRF_RT_REG_DWORD, None,
+        Some(&mut light as *mut u32 as *mut c_void),
+        Some(&mut size)) };
+    result.is_err() || light != 0
+}
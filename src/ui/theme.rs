@@ -0,0 +1,219 @@
+use std::fs;
+use toml::{Table, Value};
+use crate::{conf::{self, Problem}, extend::{LoadValue, TableLoader}};
+use super::Color;
+
+//----------------------------------------------------------------------------
+//
+//  Themes of the candidate list. A theme is either a preset or a file in
+//  %APPDATA%\Ajemi\themes, like themes\solarized.toml for "solarized".
+//  Colors in [color] override the ones of the theme.
+//
+//----------------------------------------------------------------------------
+
+/// The colors of the candidate list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub candidate: Color,
+    pub highlighted: Color,
+    pub index: Color,
+    pub clip: Color,
+    pub background: Color,
+    pub highlight: Color,
+    /// The candidate under the mouse.
+    pub hover: Color,
+    pub border: Color,
+}
+
+/// The keys of the colors in [color] and theme files, in the order of `Palette::colors_mut`.
+const COLOR_KEYS: [&str; 8] = ["candidate", "highlighted", "index", "clip", "background", "highlight", "hover", "border"];
+
+impl Palette {
+    fn colors_mut(&mut self) -> [&mut Color; COLOR_KEYS.len()] {
+        [
+            &mut self.candidate, &mut self.highlighted, &mut self.index, &mut self.clip,
+            &mut self.background, &mut self.highlight, &mut self.hover, &mut self.border,
+        ]
+    }
+}
+
+pub const LIGHT: Palette = Palette {
+    candidate: Color::hex(0x000000),
+    highlighted: Color::hex(0x000000),
+    index: Color::hex(0xA0A0A0),
+    clip: Color::hex(0x0078D7),
+    background: Color::hex(0xFAFAFA),
+    highlight: Color::hex(0xE8E8FF),
+    hover: Color::hex(0xF0F0F5),
+    border: Color::hex(0xD0D0D0),
+};
+
+pub const DARK: Palette = Palette {
+    candidate: Color::hex(0xE0E0E0),
+    highlighted: Color::hex(0xFFFFFF),
+    index: Color::hex(0x808080),
+    clip: Color::hex(0x4CC2FF),
+    background: Color::hex(0x2B2B2B),
+    highlight: Color::hex(0x3D3D5C),
+    hover: Color::hex(0x353545),
+    border: Color::hex(0x505050),
+};
+
+pub const HIGH_CONTRAST: Palette = Palette {
+    candidate: Color::hex(0xFFFFFF),
+    highlighted: Color::hex(0x000000),
+    index: Color::hex(0xFFFF00),
+    clip: Color::hex(0x00FF00),
+    background: Color::hex(0x000000),
+    highlight: Color::hex(0x1AEBFF),
+    hover: Color::hex(0x404040),
+    border: Color::hex(0xFFFFFF),
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Theme {
+    /// Light or dark, following the system.
+    Auto,
+    Light,
+    Dark,
+    HighContrast,
+    Custom(String, Palette),
+}
+
+impl Theme {
+    fn preset(name: &str) -> Option<Theme> {
+        match name {
+            "auto" => Some(Theme::Auto),
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            "high_contrast" => Some(Theme::HighContrast),
+            _ => None
+        }
+    }
+
+    pub fn palette(&self, system_dark: bool) -> Palette {
+        match self {
+            Theme::Auto if system_dark => DARK,
+            Theme::Auto | Theme::Light => LIGHT,
+            Theme::Dark => DARK,
+            Theme::HighContrast => HIGH_CONTRAST,
+            Theme::Custom(_, palette) => *palette,
+        }
+    }
+
+    /// If the theme is a dark one, which decides the tray icon.
+    pub fn is_dark(&self, system_dark: bool) -> bool {
+        self.palette(system_dark).background.luminance() < 0.5
+    }
+}
+
+/// The theme and the colors overriding it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorScheme {
+    pub theme: Theme,
+    /// In the order of `COLOR_KEYS`.
+    overrides: [Option<Color>; COLOR_KEYS.len()],
+}
+
+impl ColorScheme {
+    pub fn new() -> ColorScheme {
+        ColorScheme { theme: Theme::Auto, overrides: [None; COLOR_KEYS.len()] }
+    }
+
+    pub fn palette(&self, system_dark: bool) -> Palette {
+        let mut palette = self.theme.palette(system_dark);
+        for (color, overriding) in palette.colors_mut().into_iter().zip(self.overrides) {
+            if let Some(overriding) = overriding {
+                *color = overriding;
+            }
+        }
+        palette
+    }
+}
+
+impl LoadValue for ColorScheme {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Value::Table(mut table) = value else {
+            problems.push(Problem::mistyped(path, "a table", &value));
+            return;
+        };
+        if let Some(name) = table.remove("theme") {
+            let path = Problem::join(path, "theme");
+            match name {
+                Value::String(name) => if let Some(theme) = Theme::preset(&name).or_else(|| load_file(&name, &path, problems)) {
+                    self.theme = theme;
+                }
+                name => problems.push(Problem::mistyped(&path, "a string", &name)),
+            }
+        }
+        let Some(mut table) = TableLoader::new(Value::Table(table), path, problems) else {
+            return;
+        };
+        for (key, overriding) in COLOR_KEYS.iter().zip(&mut self.overrides) {
+            table.give(key, overriding);
+        }
+        table.finish();
+    }
+}
+
+impl LoadValue for Palette {
+    fn load(&mut self, value: Value, path: &str, problems: &mut Vec<Problem>) {
+        let Some(mut table) = TableLoader::new(value, path, problems) else {
+            return;
+        };
+        for (key, color) in COLOR_KEYS.iter().zip(self.colors_mut()) {
+            table.give(key, color);
+        }
+        table.finish();
+    }
+}
+
+/// Load themes\{name}.toml. Colors missing in the file are the ones of the light theme.
+fn load_file(name: &str, path: &str, problems: &mut Vec<Problem>) -> Option<Theme> {
+    let file = format!("themes/{name}.toml");
+    let text = conf::dir().ok()
+        .and_then(|dir| fs::read_to_string(dir.join("themes").join(format!("{name}.toml"))).ok());
+    let Some(text) = text else {
+        problems.push(Problem::error(path, format!("no such theme {name:?}, nor the file {file}")));
+        return None;
+    };
+    let table = match text.parse::<Table>() {
+        Ok(table) => table,
+        Err(err) => {
+            problems.push(Problem::error(&file, err.to_string()));
+            return None;
+        }
+    };
+    let mut palette = LIGHT;
+    palette.load(Value::Table(table), &file, problems);
+    Some(Theme::Custom(name.to_string(), palette))
+}
+
+#[test]
+fn test() {
+    let load = |text: &str| {
+        let mut scheme = ColorScheme::new();
+        let mut problems = Vec::new();
+        scheme.load(text.parse::<Table>().map(Value::Table).unwrap(), "color", &mut problems);
+        (scheme, problems)
+    };
+    let (scheme, problems) = load(r##"theme = "auto""##);
+    assert!(problems.is_empty());
+    assert_eq!(scheme.palette(false), LIGHT);
+    assert_eq!(scheme.palette(true), DARK);
+    assert!(scheme.theme.is_dark(true));
+    assert!(!scheme.theme.is_dark(false));
+
+    let (scheme, problems) = load(r##"
+        theme = "high_contrast"
+        clip = "#FF00FF"
+        index = "yellow"
+    "##);
+    assert_eq!(problems, vec![Problem::error("color.index", "expected a color like \"#RRGGBB\", found \"yellow\"")]);
+    assert_eq!(scheme.palette(false), Palette { clip: Color::hex(0xFF00FF), ..HIGH_CONTRAST });
+    assert!(scheme.theme.is_dark(false));
+
+    let (scheme, problems) = load(r##"theme = "no-such-theme""##);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(scheme.theme, Theme::Auto);
+}